@@ -1,6 +1,11 @@
+use crate::collection::collect::controllers::Controller;
+use crate::collection::compress::Compression;
+use crate::collection::sink::influx::InfluxDestination;
+use crate::collection::sink::SinkKind;
 use crate::polling::providers::ProviderType;
 use std::error;
 use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
@@ -13,6 +18,12 @@ type ShellOptions = crate::shell::Options;
 /// CLI version loaded from Cargo, or none if not build with cargo
 pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
+/// Outputs that can be given with `--output`, depending on the enabled features
+#[cfg(feature = "columnar")]
+const OUTPUTS: &[&str] = &["csvy", "influx", "parquet", "arrow"];
+#[cfg(not(feature = "columnar"))]
+const OUTPUTS: &[&str] = &["csvy", "influx"];
+
 lazy_static::lazy_static! {
     /// Authors loaded from Cargo, or none if not build with cargo
     pub static ref AUTHORS: Option<String> = option_env!("CARGO_PKG_AUTHORS")
@@ -86,6 +97,71 @@ pub struct CollectionOptions {
     )]
     pub directory: PathBuf,
 
+    /// Outputs to write collected statistics to. Can be given multiple times
+    /// (or as a comma-separated list) to write to several outputs at once:
+    /// csvy (a log file per target in the target directory), influx (InfluxDB
+    /// line protocol, see --influx-destination), parquet (an Apache Parquet
+    /// file per target), and arrow (an Arrow IPC file per target). The parquet
+    /// and arrow outputs require the columnar feature
+    #[clap(
+        name = "output",
+        long = "output",
+        default_value = "csvy",
+        possible_values = OUTPUTS,
+        use_delimiter = true,
+        global = true
+    )]
+    pub outputs: Vec<SinkKind>,
+
+    /// Destination of the influx output: file (a line protocol file per target
+    /// in the target directory, {id}_{timestamp}.lp), udp://<host>:<port>, or
+    /// tcp://<host>:<port>
+    #[clap(
+        name = "influx-destination",
+        long = "influx-destination",
+        default_value = "file",
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub influx_destination: InfluxDestination,
+
+    /// Size (in bytes) at which line protocol files are rotated, continuing in
+    /// a new file with an increasing sequence number ({id}_{timestamp}.1.lp)
+    #[clap(
+        parse(try_from_str = parse_byte),
+        name = "influx-rotate",
+        long = "influx-rotate",
+        default_value = "64MiB",
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub influx_rotate: Byte,
+
+    /// (optional) Streaming compressor to write log files through: zstd
+    /// ({id}_{timestamp}.log.zst) or gzip ({id}_{timestamp}.log.gz). The YAML
+    /// header of each log file is compressed separately from its records, so
+    /// it can be read without decompressing the rest of the file. Requires the
+    /// compress feature
+    #[cfg(feature = "compress")]
+    #[clap(
+        name = "compress",
+        long = "compress",
+        possible_values = &["zstd", "gzip"],
+        global = true
+    )]
+    pub compress: Option<Compression>,
+
+    /// (optional) Address to serve a Prometheus exporter on (such as
+    /// 0.0.0.0:9500), which exposes the most recent sample of every active
+    /// target on /metrics, in addition to the other outputs
+    #[clap(
+        name = "prometheus-listen",
+        long = "prometheus-listen",
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub prometheus_listen: Option<SocketAddr>,
+
     /// (optional) Target location to write an buffer flush event log
     #[clap(
         parse(from_os_str),
@@ -96,6 +172,31 @@ pub struct CollectionOptions {
     )]
     pub flush_log: Option<PathBuf>,
 
+    /// (optional) Target location to write a memory event log, which captures
+    /// OOM and memory pressure notifications for each cgroup target as they
+    /// occur
+    #[clap(
+        parse(from_os_str),
+        long = "event-log",
+        global = true,
+        value_hint = ValueHint::FilePath
+    )]
+    pub event_log: Option<PathBuf>,
+
+    /// (optional) Interval at which to write a process table for each cgroup
+    /// target, which lists every process in the target's cgroup to a secondary
+    /// log file ({id}_processes_{timestamp}.log). Sampled on a separate thread
+    /// from collection, since its cost grows with the number of processes.
+    /// Should usually be slower than the collection interval
+    #[clap(
+        parse(try_from_str = parse_duration),
+        name = "process-table",
+        long = "process-table",
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub process_table: Option<Duration>,
+
     /// Size (in bytes) of the heap-allocated buffer to use to write collection
     /// records in
     #[clap(
@@ -107,6 +208,73 @@ pub struct CollectionOptions {
         value_hint = ValueHint::Other
     )]
     pub buffer_size: Byte,
+
+    /// Whether to additionally collect block I/O statistics for each block
+    /// device used by a target, rather than only totals across all devices.
+    /// The set of devices is discovered when collection for each target starts
+    #[clap(long = "per-device", global = true)]
+    pub per_device: bool,
+
+    /// (optional) Metrics to collect, as column names (such as
+    /// memory.usage.current) or prefixes of column names (such as cpu). If
+    /// not given, every column is collected
+    #[clap(
+        name = "metrics",
+        long = "metrics",
+        use_delimiter = true,
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub metrics: Vec<String>,
+
+    /// (optional) Location of a YAML file containing a list of metrics to
+    /// collect, in addition to any given with --metrics
+    #[clap(
+        parse(from_os_str),
+        long = "metrics-file",
+        global = true,
+        value_hint = ValueHint::FilePath
+    )]
+    pub metrics_file: Option<PathBuf>,
+
+    /// (optional) Keys of additional entries in memory.stat to collect, each
+    /// written to a memory.stat.<key> column. Entries that aren't found in
+    /// memory.stat are listed in the log header
+    #[clap(
+        name = "memory-stat",
+        long = "memory-stat",
+        use_delimiter = true,
+        global = true,
+        value_hint = ValueHint::Other
+    )]
+    pub memory_stat: Vec<String>,
+
+    /// (optional) Additional cgroup controllers to collect statistics from:
+    /// hugetlb (usage of each huge page size), cpuset (effective CPUs and
+    /// memory nodes, included in the log header), and rdma (usage of each RDMA
+    /// device). Huge page sizes and RDMA devices are discovered when collection
+    /// for each target starts
+    #[clap(
+        name = "controllers",
+        long = "controllers",
+        possible_values = &["hugetlb", "cpuset", "rdma"],
+        use_delimiter = true,
+        global = true
+    )]
+    pub controllers: Vec<Controller>,
+}
+
+impl CollectionOptions {
+    /// Gets the streaming compressor to write log files through, if any
+    #[cfg(feature = "compress")]
+    #[must_use]
+    pub const fn compression(&self) -> Option<Compression> { self.compress }
+
+    /// Gets the streaming compressor to write log files through, which is
+    /// always None without the compress feature
+    #[cfg(not(feature = "compress"))]
+    #[must_use]
+    pub const fn compression(&self) -> Option<Compression> { None }
 }
 
 #[derive(Clap, Clone, Debug, PartialEq)]
@@ -124,6 +292,36 @@ pub struct PollingOptions {
     pub interval: Duration,
 }
 
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct FilterOptions {
+    /// (optional) Labels that targets must have to be collected, either as a
+    /// key (such as app) or as a key-value pair (such as app=web). Targets
+    /// must have all given labels
+    #[clap(long = "include-label", value_hint = ValueHint::Other)]
+    pub include_labels: Vec<String>,
+
+    /// (optional) Labels that exclude targets from being collected, either as
+    /// a key (such as app) or as a key-value pair (such as app=web). Targets
+    /// with any of the given labels are excluded
+    #[clap(long = "exclude-label", value_hint = ValueHint::Other)]
+    pub exclude_labels: Vec<String>,
+
+    /// (optional) Regular expression that the names of targets must match to
+    /// be collected (such as "^web-")
+    #[clap(long = "name-regex", value_hint = ValueHint::Other)]
+    pub name_regex: Option<String>,
+
+    /// (optional) Namespaces that targets must belong to to be collected. Not
+    /// supported by the docker provider, whose containers have no namespace
+    #[clap(long = "namespace", value_hint = ValueHint::Other)]
+    pub namespaces: Vec<String>,
+
+    /// (optional) Glob patterns of images (such as nginx:*) that targets must
+    /// use to be collected
+    #[clap(long = "image", value_hint = ValueHint::Other)]
+    pub images: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ParseFailure {
     field: String,
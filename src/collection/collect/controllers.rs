@@ -0,0 +1,273 @@
+//! Optional collection for the `hugetlb`, `cpuset`, and `rdma` controllers,
+//! enabled with `--controllers`. Like per-device statistics, the hugetlb and
+//! rdma columns depend on what is found when collection for each target starts
+//! (the huge page sizes supported by the kernel and the RDMA devices that the
+//! cgroup is charged for), and so are appended to the end of each row. The
+//! effective cpuset of a target rarely changes, and so it is instead included
+//! in the log header. Since these controllers are often only enabled in the
+//! unified hierarchy on hybrid systems, cgroup v1 targets fall back to the
+//! cgroup at the same path in the unified hierarchy for any controller that
+//! isn't mounted as a v1 hierarchy.
+
+use crate::collection::collect::{read, WorkingBuffers};
+use crate::util::{self, CgroupPath, CgroupVersion};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use csv::ByteRecord;
+use serde::Serialize;
+use strum_macros::{EnumString, IntoStaticStr};
+
+/// Additional cgroup controller that can be collected with `--controllers`
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Controller {
+    /// Usage of each huge page size
+    Hugetlb,
+    /// Effective CPUs and memory nodes, included in the log header
+    Cpuset,
+    /// Usage of each RDMA device
+    Rdma,
+}
+
+/// Files for each huge page size in a cgroup v1 `hugetlb` hierarchy (after the
+/// `hugetlb.<size>.` prefix) that map to the `HUGETLB_COLUMNS`
+const HUGETLB_V1_FILES: &[Option<&str>] = &[
+    Some("usage_in_bytes"),
+    Some("max_usage_in_bytes"),
+    Some("limit_in_bytes"),
+    Some("failcnt"),
+];
+
+/// Files for each huge page size in the unified hierarchy (after the
+/// `hugetlb.<size>.` prefix) that map to the `HUGETLB_COLUMNS`. The maximum
+/// usage isn't tracked, while the allocation failures are the `max` entry of
+/// the `events` file
+const HUGETLB_V2_FILES: &[Option<&str>] = &[Some("current"), None, Some("max"), Some("events")];
+
+/// Columns written for each huge page size (after the `hugetlb.<size>.` prefix)
+const HUGETLB_COLUMNS: &[&str] = &["usage.current", "usage.max", "limit.hard", "failcnt"];
+
+/// String offset used to skip the key of the only entry in the cgroup v2
+/// `hugetlb.<size>.events` file
+const HUGETLB_EVENTS_OFFSETS: [usize; 1] = ["max".len()];
+
+/// Keys in each line of the `rdma.current` file that map to the columns (in the
+/// same order) written for each RDMA device
+const RDMA_KEYS: &[&[u8]] = &[b"hca_handle", b"hca_object"];
+
+/// Effective CPUs and memory nodes that a target can use, as lists of ranges
+/// (such as `0-3,8`), which are included in the log header
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Cpuset {
+    pub cpus: Option<String>,
+    pub mems: Option<String>,
+}
+
+/// Open file handles for the additional controllers of a single cgroup target,
+/// along with the huge page sizes and RDMA devices found when they were opened
+pub struct ControllerHandles {
+    /// Version of the hierarchy that the hugetlb files were opened in
+    hugetlb_version: CgroupVersion,
+    huge_page_sizes: Vec<HugePageSize>,
+    rdma_current:    Option<File>,
+    /// Names of the RDMA devices in the order of their columns
+    rdma_devices:    Vec<String>,
+    cpuset:          Option<Cpuset>,
+}
+
+/// Single huge page size that has columns in the log output
+struct HugePageSize {
+    /// Size as it appears in the names of the hugetlb files (such as `2MB`)
+    name:  String,
+    /// Handles to the files that map to each of the `HUGETLB_COLUMNS`
+    files: Vec<Option<File>>,
+}
+
+impl ControllerHandles {
+    /// Opens the files of the given additional controllers for the given
+    /// cgroup, discovering the huge page sizes and RDMA devices that columns
+    /// are written for and reading the effective cpuset
+    #[must_use]
+    pub fn new(cgroup: &CgroupPath, controllers: &[Controller]) -> Self {
+        let mut handles = Self {
+            hugetlb_version: cgroup.version,
+            huge_page_sizes: Vec::new(),
+            rdma_current:    None,
+            rdma_devices:    Vec::new(),
+            cpuset:          None,
+        };
+
+        if controllers.contains(&Controller::Hugetlb) {
+            if let Some((dir, version)) = controller_dir(cgroup, "hugetlb") {
+                handles.hugetlb_version = version;
+                handles.huge_page_sizes = open_huge_page_sizes(&dir, version);
+            }
+        }
+
+        if controllers.contains(&Controller::Rdma) {
+            if let Some((dir, _)) = controller_dir(cgroup, "rdma") {
+                handles.rdma_current = File::open(dir.join("rdma.current")).ok();
+                handles.rdma_devices = find_rdma_devices(&handles.rdma_current);
+            }
+        }
+
+        if controllers.contains(&Controller::Cpuset) {
+            handles.cpuset = controller_dir(cgroup, "cpuset").and_then(|(dir, version)| {
+                let (cpus, mems) = match version {
+                    CgroupVersion::V1 => ("cpuset.effective_cpus", "cpuset.effective_mems"),
+                    CgroupVersion::V2 => ("cpuset.cpus.effective", "cpuset.mems.effective"),
+                };
+                let cpuset = Cpuset {
+                    cpus: read_value(&dir.join(cpus)),
+                    mems: read_value(&dir.join(mems)),
+                };
+                match (&cpuset.cpus, &cpuset.mems) {
+                    (None, None) => None,
+                    _ => Some(cpuset),
+                }
+            });
+        }
+
+        handles
+    }
+
+    /// Gets the effective cpuset of the target, if it was read
+    #[must_use]
+    pub const fn cpuset(&self) -> Option<&Cpuset> { self.cpuset.as_ref() }
+
+    /// Appends the headers for all hugetlb and rdma columns to the given header
+    /// row. Columns are grouped by huge page size or RDMA device
+    pub fn append_headers(&self, header: &mut ByteRecord) {
+        for size in &self.huge_page_sizes {
+            for column in HUGETLB_COLUMNS {
+                header.push_field(format!("hugetlb.{}.{}", size.name, column).as_bytes());
+            }
+        }
+        for device in &self.rdma_devices {
+            for key in RDMA_KEYS {
+                let key = String::from_utf8_lossy(key);
+                header.push_field(format!("rdma.{}.{}", device, key).as_bytes());
+            }
+        }
+    }
+}
+
+/// Resolves the directory of the given cgroup in the hierarchy of the given
+/// controller, along with the version of that hierarchy. Falls back to the
+/// unified hierarchy if the controller isn't mounted as a v1 hierarchy
+fn controller_dir(cgroup: &CgroupPath, controller: &str) -> Option<(PathBuf, CgroupVersion)> {
+    let mount = match cgroup.version {
+        CgroupVersion::V1 => util::cgroup_mounts().controller(controller),
+        CgroupVersion::V2 => None,
+    };
+    match mount {
+        Some(mount) => Some((mount.resolve(&cgroup.path), CgroupVersion::V1)),
+        None => Some((
+            util::cgroup_dir(&cgroup.path, CgroupVersion::V2)?,
+            CgroupVersion::V2,
+        )),
+    }
+}
+
+/// Finds the huge page sizes that have hugetlb files in the given cgroup
+/// directory (ordered from smallest to largest), opening the files for each
+fn open_huge_page_sizes(dir: &Path, version: CgroupVersion) -> Vec<HugePageSize> {
+    let (suffix, files) = match version {
+        CgroupVersion::V1 => (".usage_in_bytes", HUGETLB_V1_FILES),
+        CgroupVersion::V2 => (".current", HUGETLB_V2_FILES),
+    };
+    let mut names = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let file_name = entry.file_name().into_string().ok()?;
+                let name = file_name.strip_prefix("hugetlb.")?.strip_suffix(suffix)?;
+                // Skip the reservation files (such as hugetlb.2MB.rsvd.current)
+                match name.contains('.') {
+                    true => None,
+                    false => Some(String::from(name)),
+                }
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+
+    names.sort_by_key(|name| size_in_bytes(name));
+    names
+        .into_iter()
+        .map(|name| HugePageSize {
+            files: files
+                .iter()
+                .map(|file| {
+                    let file = (*file)?;
+                    File::open(dir.join(format!("hugetlb.{}.{}", name, file))).ok()
+                })
+                .collect(),
+            name,
+        })
+        .collect()
+}
+
+/// Parses a huge page size as it appears in the names of the hugetlb files
+/// (such as `2MB` or `1GB`), returning 0 if it can't be parsed
+fn size_in_bytes(name: &str) -> u64 {
+    let split = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    let shift = match &name[split..] {
+        "KB" => 10,
+        "MB" => 20,
+        "GB" => 30,
+        _ => 0,
+    };
+    name[..split].parse::<u64>().unwrap_or(0) << shift
+}
+
+/// Examines the given `rdma.current` file to find the RDMA devices that the
+/// cgroup is charged for, which are the first field of each line
+fn find_rdma_devices(file: &Option<File>) -> Vec<String> {
+    let mut buffer = String::new();
+    if let Some(file) = file {
+        let mut file_mut = file;
+        let _ = file_mut.read_to_string(&mut buffer);
+        // Ignore errors: if seeking fails, then the effect next time will be pushing
+        // empty buffers to the CSV rows
+        let _ = file_mut.seek(SeekFrom::Start(0));
+    }
+
+    buffer
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads a single-line cgroup file, returning None if it can't be read
+fn read_value(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| String::from(value.trim()))
+}
+
+/// Collects the hugetlb and rdma stats for the given target, if enabled
+#[inline]
+pub fn collect(buffers: &mut WorkingBuffers, handles: &ControllerHandles) {
+    for size in &handles.huge_page_sizes {
+        for (i, file) in size.files.iter().enumerate() {
+            match (handles.hugetlb_version, i == HUGETLB_COLUMNS.len() - 1) {
+                (CgroupVersion::V2, true) => {
+                    read::stat_file(file, &HUGETLB_EVENTS_OFFSETS, buffers);
+                },
+                _ => read::entry(file, buffers),
+            }
+        }
+    }
+
+    if !handles.rdma_devices.is_empty() {
+        let devices = handles.rdma_devices.iter().map(String::as_bytes);
+        read::keyed_lines(&handles.rdma_current, devices, RDMA_KEYS, buffers);
+    }
+}
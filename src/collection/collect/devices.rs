@@ -0,0 +1,189 @@
+//! Optional per-device block I/O collection, which splits the I/O statistics of
+//! a target into separate columns for each block device (rather than only
+//! aggregating them across all devices). The set of devices is discovered once
+//! when the collector is created, and each device is labelled with its kernel
+//! name from `/sys/dev/block/<major>:<minor>/uevent`.
+
+use crate::collection::collect::files::{ProcFileHandles, ProcFileHandlesV2};
+use crate::collection::collect::{read, WorkingBuffers};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+
+use csv::ByteRecord;
+
+/// Files in the cgroup v1 `blkio` hierarchy that per-device columns are read
+/// from
+pub const V1_FILES: &[&str] = &[
+    "blkio.throttle.io_service_bytes",
+    "blkio.throttle.io_serviced",
+];
+
+/// Files in the unified hierarchy that per-device columns are read from
+pub const V2_FILES: &[&str] = &["io.stat"];
+
+/// Operations in the cgroup v1 `blkio.throttle.*` files that map to the
+/// per-device columns (in the same order) for each device in the final output
+const BLKIO_OPERATIONS: &[&[u8]] = &[b"Read", b"Write"];
+
+/// Keys in each line of the cgroup v2 `io.stat` file that map to the
+/// per-device `service.bytes` columns (in the same order) for each device
+const IO_STAT_BYTES_KEYS: &[&[u8]] = &[b"rbytes", b"wbytes"];
+
+/// Keys in each line of the cgroup v2 `io.stat` file that map to the
+/// per-device `service.ios` columns (in the same order) for each device
+const IO_STAT_IOS_KEYS: &[&[u8]] = &[b"rios", b"wios"];
+
+/// Groups of per-device columns in the output, each of which has a column for
+/// every device (in the order of the layout) and operation
+const COLUMN_GROUPS: &[&str] = &["service.bytes", "service.ios"];
+
+/// Operations that each group of per-device columns is split into
+const COLUMN_OPERATIONS: &[&str] = &["read", "write"];
+
+/// Used to store the block devices found during an initial examination of the
+/// I/O files of a target, determining which per-device columns are written
+pub struct DeviceLayout {
+    /// Devices in the order of their columns in the final output
+    devices: Vec<BlockDevice>,
+}
+
+/// Single block device that has per-device columns in the log output
+pub struct BlockDevice {
+    /// Device number in the form of `<major>:<minor>`, as it appears at the
+    /// start of each line of the I/O files
+    pub number: Vec<u8>,
+    /// Kernel name of the device (such as `nvme0n1`), or its device number if
+    /// the name couldn't be resolved
+    pub name:   String,
+}
+
+impl DeviceLayout {
+    /// Examines the given I/O files, where each line starts with a device
+    /// number, to find the set of devices used by the target
+    #[must_use]
+    pub fn new(files: &[&Option<File>]) -> Self {
+        let mut numbers: Vec<(u32, u32)> = Vec::new();
+        for file in files {
+            let mut buffer = String::new();
+            if let Some(file) = file {
+                let mut file_mut = file;
+                let _ = file_mut.read_to_string(&mut buffer);
+                // Ignore errors: if seeking fails, then the effect next time will be pushing
+                // empty buffers to the CSV rows
+                let _ = file_mut.seek(SeekFrom::Start(0));
+            }
+            for line in buffer.lines() {
+                if let Some(number) = line.split_whitespace().next().and_then(parse_number) {
+                    if !numbers.contains(&number) {
+                        numbers.push(number);
+                    }
+                }
+            }
+        }
+
+        numbers.sort_unstable();
+        Self {
+            devices: numbers
+                .into_iter()
+                .map(|(major, minor)| BlockDevice {
+                    number: format!("{}:{}", major, minor).into_bytes(),
+                    name:   resolve_name(major, minor)
+                        .unwrap_or_else(|| format!("{}:{}", major, minor)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Examines the cgroup v1 `blkio` files of a target
+    #[must_use]
+    pub fn cgroup_v1(handles: &ProcFileHandles) -> Self {
+        Self::new(&[
+            &handles.blkio_throttle_io_service_bytes,
+            &handles.blkio_throttle_io_serviced,
+        ])
+    }
+
+    /// Examines the cgroup v2 `io.stat` file of a target
+    #[must_use]
+    pub fn cgroup_v2(handles: &ProcFileHandlesV2) -> Self { Self::new(&[&handles.io_stat]) }
+
+    /// Iterates over the devices in the layout, in the order of their columns
+    pub fn devices(&self) -> impl Iterator<Item = &BlockDevice> { self.devices.iter() }
+
+    /// Gets the map of device numbers to device names that gets included with
+    /// each log file
+    #[must_use]
+    pub fn device_map(&self) -> BTreeMap<String, String> {
+        self.devices
+            .iter()
+            .map(|d| {
+                (
+                    String::from_utf8_lossy(&d.number).into_owned(),
+                    d.name.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Appends the headers for all per-device columns to the given header row,
+    /// using the given column prefix (such as `blkio`). Columns are grouped by
+    /// statistic, and then ordered by device
+    pub fn append_headers(&self, prefix: &str, header: &mut ByteRecord) {
+        for group in COLUMN_GROUPS {
+            for device in &self.devices {
+                for operation in COLUMN_OPERATIONS {
+                    let column =
+                        format!("{}.device.{}.{}.{}", prefix, device.name, group, operation);
+                    header.push_field(column.as_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Parses a device number in the form of `<major>:<minor>`
+fn parse_number(raw: &str) -> Option<(u32, u32)> {
+    let (major, minor) = raw.split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Resolves the kernel name of the block device with the given number from the
+/// `DEVNAME` entry in its sysfs `uevent` file
+fn resolve_name(major: u32, minor: u32) -> Option<String> {
+    let uevent = fs::read_to_string(format!("/sys/dev/block/{}:{}/uevent", major, minor)).ok()?;
+    uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .map(String::from)
+}
+
+/// Collects the per-device stats for a cgroup v1 target from the
+/// `blkio.throttle.*` files, which are populated regardless of the I/O
+/// scheduler used
+#[inline]
+pub fn collect_v1(buffers: &mut WorkingBuffers, handles: &ProcFileHandles, layout: &DeviceLayout) {
+    read::device_io(
+        &handles.blkio_throttle_io_service_bytes,
+        BLKIO_OPERATIONS,
+        layout,
+        buffers,
+    );
+    read::device_io(
+        &handles.blkio_throttle_io_serviced,
+        BLKIO_OPERATIONS,
+        layout,
+        buffers,
+    );
+}
+
+/// Collects the per-device stats for a cgroup v2 target from `io.stat`
+#[inline]
+pub fn collect_v2(
+    buffers: &mut WorkingBuffers,
+    handles: &ProcFileHandlesV2,
+    layout: &DeviceLayout,
+) {
+    read::device_keyed_io(&handles.io_stat, IO_STAT_BYTES_KEYS, layout, buffers);
+    read::device_keyed_io(&handles.io_stat, IO_STAT_IOS_KEYS, layout, buffers);
+}
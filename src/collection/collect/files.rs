@@ -1,7 +1,16 @@
-use std::fs::File;
+use crate::collection::collect::psi;
+use crate::util::{self, CgroupVersion};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-const CGROUP_V1_ROOT: &str = "/sys/fs/cgroup";
+/// Initial delay before trying again to find a process in a cgroup without
+/// any, which doubles after each failed attempt (up to `MAX_RESOLVE_DELAY`)
+const RESOLVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between attempts to find a process in a cgroup without any
+const MAX_RESOLVE_DELAY: Duration = Duration::from_secs(30);
 
 /// File handles re-used for each target that read into the /proc VFS
 pub struct ProcFileHandles {
@@ -34,49 +43,299 @@ pub struct ProcFileHandles {
 }
 
 impl ProcFileHandles {
-    /// Initializes all file handles to /proc files, utilizing them over the
-    /// entire timeline of the target monitoring. If a handle fails to
-    /// open, the struct field will be None
+    /// Initializes the file handles to /proc files that are opened (as
+    /// determined by the given predicate on each file name), utilizing them
+    /// over the entire timeline of the target monitoring. If a handle isn't
+    /// opened or fails to open, the struct field will be None
     #[must_use]
-    pub fn new<C: AsRef<Path>>(cgroup: C) -> Self {
+    pub fn new<C: AsRef<Path>, F: Fn(&str) -> bool>(cgroup: C, opens: F) -> Self {
+        let open = |subsystem: &str, file: &str| match opens(file) {
+            true => o(&cgroup, subsystem, file),
+            false => None,
+        };
         Self {
-            current_pids:                    o(&cgroup, "pids", "pids.current"),
-            max_pids:                        o(&cgroup, "pids", "pids.max"),
-            cpu_stat:                        o(&cgroup, "cpu", "cpu.stat"),
-            cpuacct_stat:                    o(&cgroup, "cpuacct", "cpuacct.stat"),
-            cpuacct_usage:                   o(&cgroup, "cpuacct", "cpuacct.usage"),
-            cpuacct_usage_sys:               o(&cgroup, "cpuacct", "cpuacct.usage_sys"),
-            cpuacct_usage_user:              o(&cgroup, "cpuacct", "cpuacct.usage_user"),
-            cpuacct_usage_percpu:            o(&cgroup, "cpuacct", "cpuacct.usage_percpu"),
-            memory_usage_in_bytes:           o(&cgroup, "memory", "memory.usage_in_bytes"),
-            memory_max_usage_in_bytes:       o(&cgroup, "memory", "memory.max_usage_in_bytes"),
-            memory_limit_in_bytes:           o(&cgroup, "memory", "memory.limit_in_bytes"),
-            memory_soft_limit_in_bytes:      o(&cgroup, "memory", "memory.soft_limit_in_bytes"),
-            memory_failcnt:                  o(&cgroup, "memory", "memory.failcnt"),
-            memory_stat:                     o(&cgroup, "memory", "memory.stat"),
-            blkio_io_service_bytes:          o(&cgroup, "blkio", "blkio.io_service_bytes"),
-            blkio_io_serviced:               o(&cgroup, "blkio", "blkio.io_serviced"),
-            blkio_io_service_time:           o(&cgroup, "blkio", "blkio.io_service_time"),
-            blkio_io_queued:                 o(&cgroup, "blkio", "blkio.io_queued"),
-            blkio_io_wait_time:              o(&cgroup, "blkio", "blkio.io_wait_time"),
-            blkio_io_merged:                 o(&cgroup, "blkio", "blkio.io_merged"),
-            blkio_time:                      o(&cgroup, "blkio", "blkio.time"),
-            blkio_sectors:                   o(&cgroup, "blkio", "blkio.sectors"),
-            blkio_throttle_io_service_bytes: o(&cgroup, "blkio", "blkio.throttle.io_service_bytes"),
-            blkio_throttle_io_serviced:      o(&cgroup, "blkio", "blkio.throttle.io_serviced"),
-            blkio_bfq_io_service_bytes:      o(&cgroup, "blkio", "blkio.bfq.io_service_bytes"),
-            blkio_bfq_io_serviced:           o(&cgroup, "blkio", "blkio.bfq.io_serviced"),
+            current_pids:                    open("pids", "pids.current"),
+            max_pids:                        open("pids", "pids.max"),
+            cpu_stat:                        open("cpu", "cpu.stat"),
+            cpuacct_stat:                    open("cpuacct", "cpuacct.stat"),
+            cpuacct_usage:                   open("cpuacct", "cpuacct.usage"),
+            cpuacct_usage_sys:               open("cpuacct", "cpuacct.usage_sys"),
+            cpuacct_usage_user:              open("cpuacct", "cpuacct.usage_user"),
+            cpuacct_usage_percpu:            open("cpuacct", "cpuacct.usage_percpu"),
+            memory_usage_in_bytes:           open("memory", "memory.usage_in_bytes"),
+            memory_max_usage_in_bytes:       open("memory", "memory.max_usage_in_bytes"),
+            memory_limit_in_bytes:           open("memory", "memory.limit_in_bytes"),
+            memory_soft_limit_in_bytes:      open("memory", "memory.soft_limit_in_bytes"),
+            memory_failcnt:                  open("memory", "memory.failcnt"),
+            memory_stat:                     open("memory", "memory.stat"),
+            blkio_io_service_bytes:          open("blkio", "blkio.io_service_bytes"),
+            blkio_io_serviced:               open("blkio", "blkio.io_serviced"),
+            blkio_io_service_time:           open("blkio", "blkio.io_service_time"),
+            blkio_io_queued:                 open("blkio", "blkio.io_queued"),
+            blkio_io_wait_time:              open("blkio", "blkio.io_wait_time"),
+            blkio_io_merged:                 open("blkio", "blkio.io_merged"),
+            blkio_time:                      open("blkio", "blkio.time"),
+            blkio_sectors:                   open("blkio", "blkio.sectors"),
+            blkio_throttle_io_service_bytes: open("blkio", "blkio.throttle.io_service_bytes"),
+            blkio_throttle_io_serviced:      open("blkio", "blkio.throttle.io_serviced"),
+            blkio_bfq_io_service_bytes:      open("blkio", "blkio.bfq.io_service_bytes"),
+            blkio_bfq_io_serviced:           open("blkio", "blkio.bfq.io_serviced"),
         }
     }
 }
 
 /// Opens a stats file in /proc for the cgroup corresponding to the given
-/// relative cgroup in the given subsystem
+/// relative cgroup in the given subsystem, using the discovered mount point of
+/// the subsystem's hierarchy
 #[must_use]
 fn o<C: AsRef<Path>>(cgroup: C, subsystem: &str, file: &str) -> Option<File> {
-    let mut path: PathBuf = PathBuf::from(CGROUP_V1_ROOT);
-    path.push(subsystem);
-    path.push(cgroup);
+    let mut path = util::cgroup_mounts().controller(subsystem)?.resolve(cgroup);
     path.push(file);
     File::open(path).ok()
 }
+
+/// File handles re-used for each target that read into the unified (v2) cgroup
+/// hierarchy, where all controller files live in the single cgroup directory
+pub struct ProcFileHandlesV2 {
+    pub pids_current:   Option<File>,
+    pub pids_max:       Option<File>,
+    pub cpu_stat:       Option<File>,
+    pub memory_current: Option<File>,
+    pub memory_max:     Option<File>,
+    pub memory_stat:    Option<File>,
+    pub io_stat:        Option<File>,
+}
+
+impl ProcFileHandlesV2 {
+    /// Initializes the file handles to cgroup files in the unified hierarchy
+    /// that are opened (as determined by the given predicate on each file
+    /// name), utilizing them over the entire timeline of the target
+    /// monitoring. If a handle isn't opened or fails to open, the struct field
+    /// will be None
+    #[must_use]
+    pub fn new<C: AsRef<Path>, F: Fn(&str) -> bool>(cgroup: C, opens: F) -> Self {
+        let dir = util::cgroup_dir(cgroup, CgroupVersion::V2);
+        let open = |file: &str| match opens(file) {
+            true => o_v2(dir.as_ref(), file),
+            false => None,
+        };
+        Self {
+            pids_current:   open("pids.current"),
+            pids_max:       open("pids.max"),
+            cpu_stat:       open("cpu.stat"),
+            memory_current: open("memory.current"),
+            memory_max:     open("memory.max"),
+            memory_stat:    open("memory.stat"),
+            io_stat:        open("io.stat"),
+        }
+    }
+}
+
+/// Opens a stats file in the given cgroup directory of the unified hierarchy,
+/// if the hierarchy is mounted
+#[must_use]
+fn o_v2(dir: Option<&PathBuf>, file: &str) -> Option<File> { File::open(dir?.join(file)).ok() }
+
+/// File handles re-used for each target that read into the pressure files of
+/// either a cgroup in the unified hierarchy or the whole system
+pub struct PressureFileHandles {
+    pub cpu:    Option<File>,
+    pub memory: Option<File>,
+    pub io:     Option<File>,
+}
+
+impl PressureFileHandles {
+    /// Initializes the file handles to the pressure files of the cgroup in the
+    /// unified hierarchy with the given directory that are opened (as
+    /// determined by the given predicate on each file name). Since the unified
+    /// hierarchy is also mounted in hybrid mode, this is also used for cgroup
+    /// v1 targets. If a handle isn't opened or fails to open (such as if PSI is
+    /// unavailable), the struct field will be None
+    #[must_use]
+    pub fn new<F: Fn(&str) -> bool>(cgroup_dir: Option<&PathBuf>, opens: F) -> Self {
+        let open = |file: &str| match opens(file) {
+            true => o_v2(cgroup_dir, file),
+            false => None,
+        };
+        Self {
+            cpu:    open("cpu.pressure"),
+            memory: open("memory.pressure"),
+            io:     open("io.pressure"),
+        }
+    }
+
+    /// Initializes all file handles to the system-wide pressure files in
+    /// `/proc/pressure`
+    #[must_use]
+    pub fn host() -> Self {
+        let dir = Path::new(psi::HOST_PRESSURE_DIR);
+        Self {
+            cpu:    File::open(dir.join("cpu")).ok(),
+            memory: File::open(dir.join("memory")).ok(),
+            io:     File::open(dir.join("io")).ok(),
+        }
+    }
+}
+
+/// File handles re-used for each process target that read into the
+/// per-process directory in /proc. The handles stay bound to the original
+/// process, so reads fail (rather than reading another process) once it exits
+pub struct ProcessFileHandles {
+    pub stat:   Option<File>,
+    pub status: Option<File>,
+    pub io:     Option<File>,
+}
+
+impl ProcessFileHandles {
+    /// Initializes the file handles to the /proc files of the given process
+    /// that are opened (as determined by the given predicate on each file
+    /// name), utilizing them over the entire timeline of the target
+    /// monitoring. If a handle isn't opened or fails to open (such as `io`
+    /// without sufficient permissions), the struct field will be None
+    #[must_use]
+    pub fn new<F: Fn(&str) -> bool>(pid: u32, opens: F) -> Self {
+        let dir = PathBuf::from(format!("/proc/{}", pid));
+        let open = |file: &str| match opens(file) {
+            true => File::open(dir.join(file)).ok(),
+            false => None,
+        };
+        Self {
+            stat:   open("stat"),
+            status: open("status"),
+            io:     open("io"),
+        }
+    }
+}
+
+/// File handles re-used for the built-in host target that read into the
+/// system-wide files in /proc
+pub struct HostFileHandles {
+    pub stat:      Option<File>,
+    pub meminfo:   Option<File>,
+    pub vmstat:    Option<File>,
+    pub diskstats: Option<File>,
+    pub loadavg:   Option<File>,
+    pub net_dev:   Option<File>,
+    pub net_snmp:  Option<File>,
+}
+
+impl HostFileHandles {
+    /// Initializes the file handles to the system-wide /proc files that are
+    /// opened (as determined by the given predicate on each file path),
+    /// utilizing them over the entire timeline of the host monitoring. If a
+    /// handle isn't opened or fails to open, the struct field will be None
+    #[must_use]
+    pub fn new<F: Fn(&str) -> bool>(opens: F) -> Self {
+        let open = |file: &str| match opens(file) {
+            true => File::open(file).ok(),
+            false => None,
+        };
+        Self {
+            stat:      open("/proc/stat"),
+            meminfo:   open("/proc/meminfo"),
+            vmstat:    open("/proc/vmstat"),
+            diskstats: open("/proc/diskstats"),
+            loadavg:   open("/proc/loadavg"),
+            net_dev:   open("/proc/net/dev"),
+            net_snmp:  open("/proc/net/snmp"),
+        }
+    }
+}
+
+/// File handles re-used for each cgroup target that read into the network
+/// namespace of a process in the target's cgroup. Since the process can exit
+/// before the target does, the handles are re-opened for another process in
+/// the cgroup once it does. While the cgroup has no processes (such as a pod
+/// that is still being set up), finding one is retried with a backoff, rather
+/// than walking the cgroup on every tick
+pub struct NetworkFileHandles {
+    /// Directory of the target's cgroup, used to find a process in the cgroup
+    cgroup_dir: Option<PathBuf>,
+    /// Earliest time to try again to find a process, after a failed attempt
+    retry_at:   Option<Instant>,
+    delay:      Duration,
+    /// Process that the handles were opened for, if any was found
+    pub pid:    Option<u32>,
+    /// Handle to `/proc/<pid>/stat`, only used to detect when the process exits
+    stat:       Option<File>,
+    pub dev:    Option<File>,
+    pub snmp:   Option<File>,
+}
+
+impl NetworkFileHandles {
+    /// Initializes all file handles to the /proc network files of a process in
+    /// the cgroup with the given directory. If no process could be found or a
+    /// handle fails to open, the struct field will be None
+    #[must_use]
+    pub fn new(cgroup_dir: Option<PathBuf>) -> Self {
+        let mut handles = Self {
+            cgroup_dir,
+            retry_at: None,
+            delay: RESOLVE_DELAY,
+            pid: None,
+            stat: None,
+            dev: None,
+            snmp: None,
+        };
+        handles.resolve();
+        handles
+    }
+
+    /// Whether the process that the handles were opened for is still running.
+    /// Reads to the `/proc/<pid>` files of a process fail once it exits, even
+    /// if they were opened beforehand
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        match &self.stat {
+            None => false,
+            Some(stat) => {
+                let mut stat_mut = stat;
+                let mut byte = [0_u8; 1];
+                let alive = matches!(stat_mut.read(&mut byte), Ok(1));
+                let _ = stat_mut.seek(SeekFrom::Start(0));
+                alive
+            },
+        }
+    }
+
+    /// Finds a process in the cgroup (or in any of its descendants, such as for
+    /// pods, whose processes are all in container cgroups) and re-opens all
+    /// file handles for it. If none could be found, the next attempt is
+    /// delayed, doubling the delay after each failed attempt
+    pub fn resolve(&mut self) {
+        if matches!(self.retry_at, Some(retry_at) if Instant::now() < retry_at) {
+            return;
+        }
+
+        self.pid = self.cgroup_dir.as_deref().and_then(find_process);
+        let dir = self.pid.map(|pid| PathBuf::from(format!("/proc/{}", pid)));
+        let open = |file: &str| File::open(dir.as_ref()?.join(file)).ok();
+        self.stat = open("stat");
+        self.dev = open("net/dev");
+        self.snmp = open("net/snmp");
+
+        if self.stat.is_some() {
+            self.retry_at = None;
+            self.delay = RESOLVE_DELAY;
+        } else {
+            self.retry_at = Some(Instant::now() + self.delay);
+            self.delay = (self.delay * 2).min(MAX_RESOLVE_DELAY);
+        }
+    }
+}
+
+/// Finds the first process in the `cgroup.procs` file of the given cgroup
+/// directory, searching its child cgroups if it doesn't directly contain any
+/// processes
+fn find_process(dir: &Path) -> Option<u32> {
+    let procs = fs::read_to_string(dir.join("cgroup.procs")).ok()?;
+    if let Some(pid) = procs.lines().find_map(|line| line.trim().parse().ok()) {
+        return Some(pid);
+    }
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| matches!(entry.file_type(), Ok(t) if t.is_dir()))
+        .find_map(|entry| find_process(&entry.path()))
+}
@@ -0,0 +1,344 @@
+//! Collection for the built-in host target, which writes system-wide statistics
+//! from /proc to its own log file alongside the per-target logs, providing a
+//! baseline for what the whole machine was doing at each point in time.
+//! See <https://man7.org/linux/man-pages/man5/proc.5.html>
+
+use crate::collection::collect::files::HostFileHandles;
+use crate::collection::collect::network;
+use crate::collection::collect::plan::{ColumnGroup, ReadPlan};
+use crate::collection::collect::read::{self, StatEntries};
+use crate::collection::collect::WorkingBuffers;
+use crate::collection::perf_table::{Column, ColumnType};
+use crate::shared::CollectionTarget;
+use crate::util::{self, BufferLike};
+use std::collections::BTreeMap;
+
+/// Provider of the built-in system-wide targets
+pub const PROVIDER_TYPE: &str = "host";
+
+/// Id of the built-in host target, which targets from providers can't use
+pub const TARGET_ID: &str = "host";
+
+/// Group of columns in the host logfile
+type Group = ColumnGroup<HostSource>;
+
+/// Creates the groups of columns for the host logfile (in the order of the
+/// output), along with the files that each group is read from
+#[must_use]
+pub fn get_groups() -> Vec<Group> {
+    let mut stat_columns = CPU_TIME_COLUMNS
+        .iter()
+        .map(|c| format!("cpu.{}", c))
+        .collect::<Vec<_>>();
+    stat_columns.push(String::from(CPUS_COLUMN));
+    stat_columns.extend(CPU_TIME_COLUMNS.iter().map(|c| format!("cpu.percpu.{}", c)));
+    stat_columns.extend(STAT_COLUMNS.iter().map(|&c| String::from(c)));
+    let mut disk_columns = vec![String::from(DISKS_COLUMN)];
+    disk_columns.extend(DISKSTATS_COLUMNS.iter().map(|&c| String::from(c)));
+
+    vec![
+        Group::new(&["/proc/stat"], stat_columns, read_stat),
+        Group::new(&["/proc/loadavg"], LOADAVG_COLUMNS, |b, s| {
+            read::table_with(&s.handles.loadavg, split_loadavg, false, LOADAVG_FIELDS, b);
+        }),
+        Group::new(&["/proc/meminfo"], MEMINFO_COLUMNS, |b, s| {
+            read::keyed_values(&s.handles.meminfo, MEMINFO_KEYS, b);
+        }),
+        Group::stat(
+            &["/proc/vmstat"],
+            StatEntries::new(VMSTAT_ENTRIES, VMSTAT_COLUMNS),
+            |b, s| read::with_layout(&s.handles.vmstat, &s.vmstat_layout, b),
+        ),
+        Group::new(&["/proc/diskstats"], disk_columns, |b, s| {
+            read::table_with(
+                &s.handles.diskstats,
+                split_diskstats,
+                true,
+                DISKSTATS_FIELDS,
+                b,
+            );
+        }),
+        Group::new(HOST_NETWORK_FILES, network::columns(), |b, s| {
+            network::collect_files(b, &s.handles.net_dev, &s.handles.net_snmp);
+        }),
+    ]
+}
+
+/// Gets the metadata for all columns in the host logfile with non-default
+/// types
+#[must_use]
+pub fn get_column_metadata() -> BTreeMap<String, Column> {
+    let mut columns: BTreeMap<String, Column> = BTreeMap::new();
+    // Include metadata on the read (timestamp) column
+    columns.insert(String::from("read"), Column::Scalar {
+        r#type: ColumnType::Epoch19,
+    });
+    // Include metadata on the per-CPU and per-disk columns, which are vector
+    // columns that contain a space-delimited entry per CPU or disk. Since CPUs
+    // can be taken offline and disks can be attached, their number varies
+    for &names in &[CPUS_COLUMN, DISKS_COLUMN] {
+        columns.insert(String::from(names), Column::Vector {
+            r#type: ColumnType::String,
+            count:  None,
+        });
+    }
+    let percpu = CPU_TIME_COLUMNS.iter().map(|c| format!("cpu.percpu.{}", c));
+    let disks = DISKSTATS_COLUMNS.iter().map(|&c| String::from(c));
+    for column in percpu.chain(disks) {
+        columns.insert(column, Column::Vector {
+            r#type: ColumnType::Int,
+            count:  None,
+        });
+    }
+    for &column in LOADAVG_COLUMNS {
+        columns.insert(String::from(column), Column::Scalar {
+            r#type: ColumnType::Float,
+        });
+    }
+    network::append_table_metadata(&mut columns);
+    columns
+}
+
+/// Creates the built-in target used to collect system-wide statistics into its
+/// own log file, alongside the per-target logs
+#[must_use]
+pub fn host_target() -> CollectionTarget {
+    CollectionTarget {
+        provider:  PROVIDER_TYPE,
+        id:        String::from(TARGET_ID),
+        name:      String::from("host"),
+        metadata:  None,
+        poll_time: util::nano_ts(),
+        filter:    None,
+    }
+}
+
+/// Open file handles and pre-examined stat file layouts for the built-in host
+/// target
+pub struct HostSource {
+    handles:       HostFileHandles,
+    vmstat_layout: read::StatFileLayout,
+}
+
+impl HostSource {
+    /// Opens the file handles read by the plan and examines the layout of the
+    /// variable stat files
+    #[must_use]
+    pub fn new(plan: &ReadPlan<Self>) -> Self {
+        let handles = HostFileHandles::new(|file| plan.opens(file));
+        Self {
+            vmstat_layout: plan.layout("/proc/vmstat", &handles.vmstat),
+            handles,
+        }
+    }
+
+    /// Gets the pre-examined layout of each stat file
+    #[must_use]
+    pub fn layouts(&self) -> Vec<(&'static str, &read::StatFileLayout)> {
+        vec![("/proc/vmstat", &self.vmstat_layout)]
+    }
+}
+
+/// Files in /proc that the host network columns are read from
+const HOST_NETWORK_FILES: &[&str] = &["/proc/net/dev", "/proc/net/snmp"];
+
+/// Indices of the values in each CPU line of `/proc/stat` that map to columns
+/// (in the same order) in the final output, counted from the value after the
+/// CPU name
+const CPU_TIME_FIELDS: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Suffixes of the columns in the output that each field in `CPU_TIME_FIELDS`
+/// maps to, following `cpu.` for the totals across all CPUs and
+/// `cpu.percpu.` for the per-CPU vector columns
+const CPU_TIME_COLUMNS: &[&str] = &[
+    "time.user",
+    "time.nice",
+    "time.system",
+    "time.idle",
+    "time.iowait",
+    "time.irq",
+    "time.softirq",
+    "time.steal",
+];
+
+/// Column in the output that contains the name of each CPU (such as `cpu0`),
+/// which determines the order of the entries in each per-CPU column
+const CPUS_COLUMN: &str = "cpu.percpu.cpus";
+
+/// Original entries in `/proc/stat` that map to columns (in the same order) in
+/// the final output
+const STAT_ENTRIES: &[&[u8]] = &[b"ctxt", b"processes", b"procs_running", b"procs_blocked"];
+
+/// Columns in the output that each entry in `STAT_ENTRIES` maps to
+const STAT_COLUMNS: &[&str] = &[
+    "system.switches",
+    "system.forks",
+    "system.procs.running",
+    "system.procs.blocked",
+];
+
+/// Indices of the values in `/proc/loadavg` that map to columns (in the same
+/// order) in the final output
+const LOADAVG_FIELDS: &[usize] = &[0, 1, 2];
+
+/// Columns in the output that each field in `LOADAVG_FIELDS` maps to
+const LOADAVG_COLUMNS: &[&str] = &["load.1", "load.5", "load.15"];
+
+/// Keys in `/proc/meminfo` that map to columns (in the same order) in the final
+/// output
+const MEMINFO_KEYS: &[&[u8]] = &[
+    b"MemTotal",
+    b"MemFree",
+    b"MemAvailable",
+    b"Buffers",
+    b"Cached",
+    b"SwapTotal",
+    b"SwapFree",
+    b"Active",
+    b"Inactive",
+    b"Dirty",
+    b"Writeback",
+    b"AnonPages",
+    b"Mapped",
+    b"Shmem",
+    b"Slab",
+    b"Committed_AS",
+    b"HugePages_Total",
+    b"HugePages_Free",
+];
+
+/// Columns in the output that each key in `MEMINFO_KEYS` maps to
+const MEMINFO_COLUMNS: &[&str] = &[
+    "memory.total",
+    "memory.free",
+    "memory.available",
+    "memory.buffers",
+    "memory.cache",
+    "memory.swap.total",
+    "memory.swap.free",
+    "memory.active",
+    "memory.inactive",
+    "memory.dirty",
+    "memory.writeback",
+    "memory.anon",
+    "memory.mapped",
+    "memory.shmem",
+    "memory.slab",
+    "memory.committed",
+    "memory.hugepages.total",
+    "memory.hugepages.free",
+];
+
+/// Original entries in `/proc/vmstat` that map to columns (in the same order)
+/// in the final output
+const VMSTAT_ENTRIES: &[&[u8]] = &[
+    b"pgpgin",
+    b"pgpgout",
+    b"pswpin",
+    b"pswpout",
+    b"pgfault",
+    b"pgmajfault",
+    b"pgscan_kswapd",
+    b"pgscan_direct",
+    b"pgsteal_kswapd",
+    b"pgsteal_direct",
+    b"oom_kill",
+];
+
+/// Columns in the output that each entry in `VMSTAT_ENTRIES` maps to
+const VMSTAT_COLUMNS: &[&str] = &[
+    "vm.paged.in",
+    "vm.paged.out",
+    "vm.swapped.in",
+    "vm.swapped.out",
+    "vm.fault.total",
+    "vm.fault.major",
+    "vm.scan.kswapd",
+    "vm.scan.direct",
+    "vm.steal.kswapd",
+    "vm.steal.direct",
+    "vm.oom_kill",
+];
+
+/// Column in the output that contains the name of each block device (such as
+/// `nvme0n1`), which determines the order of the entries in each disk column
+const DISKS_COLUMN: &str = "disk.devices";
+
+/// Indices of the values in each line of `/proc/diskstats` that map to columns
+/// (in the same order) in the final output, counted from the value after the
+/// device name
+const DISKSTATS_FIELDS: &[usize] = &[0, 2, 3, 4, 6, 7, 8, 9];
+
+/// Columns in the output that each field in `DISKSTATS_FIELDS` maps to
+const DISKSTATS_COLUMNS: &[&str] = &[
+    "disk.ios.read",
+    "disk.sectors.read",
+    "disk.time.read",
+    "disk.ios.write",
+    "disk.sectors.write",
+    "disk.time.write",
+    "disk.inflight",
+    "disk.time.total",
+];
+
+/// Reads `/proc/stat` once and writes the CPU times across all CPUs, the
+/// per-CPU times, and the system-wide entries from it. Since there is a line
+/// per online CPU, the system-wide entries are found by their key on each read
+/// rather than by a pre-examined layout, which CPU hotplug would invalidate
+fn read_stat(buffers: &mut WorkingBuffers, source: &mut HostSource) {
+    // Ignore errors: the buffer will just remain empty
+    read::read_to_buffer(&source.handles.stat, buffers);
+    read::table_in_buffer(split_cpu_total, false, CPU_TIME_FIELDS, buffers);
+    read::table_in_buffer(split_cpu, true, CPU_TIME_FIELDS, buffers);
+    read::keyed_values_in_buffer(b' ', STAT_ENTRIES, buffers);
+    buffers.buffer.clear();
+}
+
+/// Splits the line of `/proc/stat` with the totals across all CPUs, which has
+/// the name `cpu`
+fn split_cpu_total(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (name, rest) = split_after_token(line, 0)?;
+    match name == b"cpu" {
+        true => Some((name, rest)),
+        false => None,
+    }
+}
+
+/// Splits each per-CPU line of `/proc/stat`, which have names such as `cpu0`
+fn split_cpu(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (name, rest) = split_after_token(line, 0)?;
+    match name.strip_prefix(b"cpu") {
+        Some(id) if !id.is_empty() && id.iter().all(u8::is_ascii_digit) => Some((name, rest)),
+        _ => None,
+    }
+}
+
+/// Splits the single line of `/proc/loadavg`, which doesn't have a name
+fn split_loadavg(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    match line.is_empty() {
+        true => None,
+        false => Some((&line[..0], line)),
+    }
+}
+
+/// Splits each line of `/proc/diskstats`, where the device name follows its
+/// major and minor numbers
+fn split_diskstats(line: &[u8]) -> Option<(&[u8], &[u8])> { split_after_token(line, 2) }
+
+/// Splits a line of space-delimited tokens after the token at the given index,
+/// returning that token and the rest of the line after it
+fn split_after_token(line: &[u8], index: usize) -> Option<(&[u8], &[u8])> {
+    let mut start = 0;
+    for i in 0..=index {
+        start += line[start..].iter().position(|&b| !util::is_space(b))?;
+        let length = line[start..]
+            .iter()
+            .position(|&b| util::is_space(b))
+            .unwrap_or(line.len() - start);
+        if i == index {
+            return Some((&line[start..(start + length)], &line[(start + length)..]));
+        }
+        start += length;
+    }
+    None
+}
@@ -1,93 +1,192 @@
-use crate::collection::collect::files::ProcFileHandles;
+use crate::cli::CollectionOptions;
+use crate::collection::collect::controllers::{Controller, ControllerHandles, Cpuset};
+use crate::collection::collect::devices::DeviceLayout;
+use crate::collection::collect::files::{NetworkFileHandles, PressureFileHandles, ProcFileHandles,
+                                        ProcessFileHandles};
+use crate::collection::collect::plan::{ColumnGroup, MetricSelection, MetricsError, ReadPlan};
+use crate::collection::collect::read::StatEntries;
 use crate::collection::collector::Collector;
 use crate::collection::perf_table::{Column, ColumnType, TableMetadata};
-use crate::util::{self, AnonymousSlice, Buffer, BufferLike};
+use crate::util::{self, AnonymousSlice, Buffer, BufferLike, CgroupPath, CgroupVersion};
 use std::collections::BTreeMap;
 
-use csv::{ByteRecord, Error};
+use csv::ByteRecord;
+use failure::Error;
 
+pub mod controllers;
+pub mod devices;
 pub mod files;
+pub mod host;
+pub mod network;
+pub mod plan;
+pub mod process;
+pub mod psi;
 pub mod read;
+pub mod v2;
 
-lazy_static::lazy_static! {
-    /// CSV header for the stats collector
-    static ref HEADER: ByteRecord = ByteRecord::from(get_headers());
+/// Group of columns in the cgroup v1 logfiles
+type Group = ColumnGroup<CgroupV1Source>;
 
-    /// Length of each row of the collected stats
-    static ref ROW_LENGTH: usize = HEADER.len();
+/// Creates the groups of columns for the logfiles (in the order of the output),
+/// along with the files that each group is read from
+#[allow(clippy::too_many_lines)]
+fn get_groups(opts: &CollectionOptions) -> Vec<Group> {
+    vec![
+        // pids subsystem,
+        // see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/pids.html>
+        Group::new(&["pids.current"], &["pids.current"], |b, s| {
+            read::entry(&s.file_handles.current_pids, b);
+        }),
+        Group::new(&["pids.max"], &["pids.max"], |b, s| {
+            read::entry(&s.file_handles.max_pids, b);
+        }),
+        // cpu and cpuacct subsystems,
+        // see <https://access.redhat.com/documentation/en-us/red_hat_enterprise_linux/6/html/resource_management_guide/sec-cpuacct>
+        Group::new(&["cpuacct.usage"], &["cpu.usage.total"], |b, s| {
+            read::entry(&s.file_handles.cpuacct_usage, b);
+        }),
+        Group::new(&["cpuacct.usage_sys"], &["cpu.usage.system"], |b, s| {
+            read::entry(&s.file_handles.cpuacct_usage_sys, b);
+        }),
+        Group::new(&["cpuacct.usage_user"], &["cpu.usage.user"], |b, s| {
+            read::entry(&s.file_handles.cpuacct_usage_user, b);
+        }),
+        Group::new(&["cpuacct.usage_percpu"], &["cpu.usage.percpu"], |b, s| {
+            read::entry(&s.file_handles.cpuacct_usage_percpu, b);
+        }),
+        Group::new(
+            &["cpuacct.stat"],
+            &["cpu.stat.user", "cpu.stat.system"],
+            |b, s| {
+                read::stat_file(&s.file_handles.cpuacct_stat, &CPUACCT_STAT_OFFSETS, b);
+            },
+        ),
+        Group::stat(
+            &["cpu.stat"],
+            StatEntries::new(CPU_STAT_ENTRIES, CPU_STAT_COLUMNS),
+            |b, s| read::with_layout(&s.file_handles.cpu_stat, &s.cpu_layout, b),
+        ),
+        // memory subsystem,
+        // see <https://access.redhat.com/documentation/en-us/red_hat_enterprise_linux/6/html/resource_management_guide/sec-memory>
+        Group::new(
+            &["memory.usage_in_bytes"],
+            &["memory.usage.current"],
+            |b, s| {
+                read::entry(&s.file_handles.memory_usage_in_bytes, b);
+            },
+        ),
+        Group::new(
+            &["memory.max_usage_in_bytes"],
+            &["memory.usage.max"],
+            |b, s| {
+                read::entry(&s.file_handles.memory_max_usage_in_bytes, b);
+            },
+        ),
+        Group::new(
+            &["memory.limit_in_bytes"],
+            &["memory.limit.hard"],
+            |b, s| {
+                read::entry(&s.file_handles.memory_limit_in_bytes, b);
+            },
+        ),
+        Group::new(
+            &["memory.soft_limit_in_bytes"],
+            &["memory.limit.soft"],
+            |b, s| {
+                read::entry(&s.file_handles.memory_soft_limit_in_bytes, b);
+            },
+        ),
+        Group::new(&["memory.failcnt"], &["memory.failcnt"], |b, s| {
+            read::entry(&s.file_handles.memory_failcnt, b);
+        }),
+        Group::stat(
+            &["memory.stat"],
+            StatEntries::new(MEMORY_STAT_ENTRIES, MEMORY_STAT_COLUMNS)
+                .with_additional("memory.stat", &opts.memory_stat),
+            |b, s| read::with_layout(&s.file_handles.memory_stat, &s.memory_layout, b),
+        ),
+        // blkio subsystem,
+        // see <https://www.kernel.org/doc/Documentation/cgroup-v1/blkio-controller.txt>
+        Group::new(&["blkio.time"], &["blkio.time"], |b, s| {
+            read::simple_io(&s.file_handles.blkio_time, b);
+        }),
+        Group::new(&["blkio.sectors"], &["blkio.sectors"], |b, s| {
+            read::simple_io(&s.file_handles.blkio_sectors, b);
+        }),
+        // Add in the IO 4-part groups
+        Group::new(
+            &["blkio.io_service_bytes"],
+            io_columns("blkio.service.bytes"),
+            |b, s| read::io(&s.file_handles.blkio_io_service_bytes, b),
+        ),
+        Group::new(
+            &["blkio.io_serviced"],
+            io_columns("blkio.service.ios"),
+            |b, s| read::io(&s.file_handles.blkio_io_serviced, b),
+        ),
+        Group::new(
+            &["blkio.io_service_time"],
+            io_columns("blkio.service.time"),
+            |b, s| read::io(&s.file_handles.blkio_io_service_time, b),
+        ),
+        Group::new(&["blkio.io_queued"], io_columns("blkio.queued"), |b, s| {
+            read::io(&s.file_handles.blkio_io_queued, b)
+        }),
+        Group::new(&["blkio.io_wait_time"], io_columns("blkio.wait"), |b, s| {
+            read::io(&s.file_handles.blkio_io_wait_time, b)
+        }),
+        Group::new(&["blkio.io_merged"], io_columns("blkio.merged"), |b, s| {
+            read::io(&s.file_handles.blkio_io_merged, b)
+        }),
+        Group::new(
+            &["blkio.throttle.io_service_bytes"],
+            io_columns("blkio.throttle.service.bytes"),
+            |b, s| read::io(&s.file_handles.blkio_throttle_io_service_bytes, b),
+        ),
+        Group::new(
+            &["blkio.throttle.io_serviced"],
+            io_columns("blkio.throttle.service.ios"),
+            |b, s| read::io(&s.file_handles.blkio_throttle_io_serviced, b),
+        ),
+        Group::new(
+            &["blkio.bfq.io_service_bytes"],
+            io_columns("blkio.bfq.service.bytes"),
+            |b, s| read::io(&s.file_handles.blkio_bfq_io_service_bytes, b),
+        ),
+        Group::new(
+            &["blkio.bfq.io_serviced"],
+            io_columns("blkio.bfq.service.ios"),
+            |b, s| read::io(&s.file_handles.blkio_bfq_io_serviced, b),
+        ),
+        Group::new(network::FILES, network::columns(), |b, s| {
+            network::collect(b, &mut s.network_handles);
+        }),
+        Group::new(&["cpu.pressure"], psi::columns("cpu"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.cpu);
+        }),
+        Group::new(&["memory.pressure"], psi::columns("memory"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.memory);
+        }),
+        Group::new(&["io.pressure"], psi::columns("io"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.io);
+        }),
+    ]
 }
 
-/// Creates the headers for the logfiles
-fn get_headers() -> Vec<String> {
-    let mut headers = (vec![
-        "read",
-        "pids.current",
-        "pids.max",
-        "cpu.usage.total",
-        "cpu.usage.system",
-        "cpu.usage.user",
-        "cpu.usage.percpu",
-        "cpu.stat.user",
-        "cpu.stat.system",
-        "cpu.throttling.periods",
-        "cpu.throttling.throttled.count",
-        "cpu.throttling.throttled.time",
-        "memory.usage.current",
-        "memory.usage.max",
-        "memory.limit.hard",
-        "memory.limit.soft",
-        "memory.failcnt",
-        "memory.hierarchical_limit.memory",
-        "memory.hierarchical_limit.memoryswap",
-        "memory.cache",
-        "memory.rss.all",
-        "memory.rss.huge",
-        "memory.mapped",
-        "memory.swap",
-        "memory.paged.in",
-        "memory.paged.out",
-        "memory.fault.total",
-        "memory.fault.major",
-        "memory.anon.inactive",
-        "memory.anon.active",
-        "memory.file.inactive",
-        "memory.file.active",
-        "memory.unevictable",
-        "blkio.time",
-        "blkio.sectors",
-    ])
-    .into_iter()
-    .map(String::from)
-    .collect::<Vec<_>>();
-
-    // Add in the IO 4-part headers
-    append_io_headers(&mut headers, "blkio.service.bytes");
-    append_io_headers(&mut headers, "blkio.service.ios");
-    append_io_headers(&mut headers, "blkio.service.time");
-    append_io_headers(&mut headers, "blkio.queued");
-    append_io_headers(&mut headers, "blkio.wait");
-    append_io_headers(&mut headers, "blkio.merged");
-    append_io_headers(&mut headers, "blkio.throttle.service.bytes");
-    append_io_headers(&mut headers, "blkio.throttle.service.ios");
-    append_io_headers(&mut headers, "blkio.bfq.service.bytes");
-    append_io_headers(&mut headers, "blkio.bfq.service.ios");
-
-    headers
-}
-
-/// Expands a single I/O prefix to the 4 headers that will end up in the logfile
+/// Expands a single I/O prefix to the 4 columns that will end up in the logfile
 /// (read, write, sync, async)
-pub fn append_io_headers(headers: &mut Vec<String>, base: &'static str) {
-    headers.push(base.to_owned() + ".read");
-    headers.push(base.to_owned() + ".write");
-    headers.push(base.to_owned() + ".sync");
-    headers.push(base.to_owned() + ".async");
+#[must_use]
+pub fn io_columns(base: &'static str) -> Vec<String> {
+    vec![
+        base.to_owned() + ".read",
+        base.to_owned() + ".write",
+        base.to_owned() + ".sync",
+        base.to_owned() + ".async",
+    ]
 }
 
-/// Gets the perf table metadata for this collection setup
-/// (currently static)
-#[must_use]
-pub fn get_table_metadata() -> TableMetadata {
+/// Gets the metadata for all columns in the logfiles with non-default types
+fn get_column_metadata() -> BTreeMap<String, Column> {
     let mut columns: BTreeMap<String, Column> = BTreeMap::new();
     // Include metadata on the read (timestamp) column
     columns.insert(String::from("read"), Column::Scalar {
@@ -97,66 +196,311 @@ pub fn get_table_metadata() -> TableMetadata {
     // which is a vector column that contains a space-delimited entry per CPU
     columns.insert(String::from("cpu.usage.percpu"), Column::Vector {
         r#type: ColumnType::Int,
-        count:  util::remap::<_, usize>(util::num_cores()),
+        count:  Some(util::remap::<_, usize>(util::num_cores())),
     });
-    TableMetadata {
-        delimiter: ",",
-        columns,
+    network::append_table_metadata(&mut columns);
+    psi::append_table_metadata(&mut columns);
+    columns
+}
+
+/// Read plan and perf table metadata for a single CSV schema
+pub struct Schema<S> {
+    pub plan:  ReadPlan<S>,
+    pub table: TableMetadata,
+}
+
+impl<S> Schema<S> {
+    /// Builds the read plan and perf table metadata for the given column groups
+    /// and metadata of the schema
+    #[must_use]
+    fn new(
+        groups: &[ColumnGroup<S>],
+        column_metadata: BTreeMap<String, Column>,
+        selection: &MetricSelection,
+    ) -> Self {
+        let plan = ReadPlan::new(groups, selection);
+        let table = plan.table_metadata(column_metadata, selection);
+        Self { plan, table }
     }
 }
 
-/// Gets an amortized byte record containing the entries for a header row in the
-/// stats CSV log files
-#[must_use]
-pub fn get_header() -> &'static ByteRecord { &HEADER }
+/// Read plans and perf table metadata for each CSV schema, built once from the
+/// metric selection and shared between all collectors
+pub struct Schemas {
+    pub cgroup_v1:     Schema<CgroupV1Source>,
+    pub cgroup_v2:     Schema<v2::CgroupV2Source>,
+    pub process:       Schema<ProcessFileHandles>,
+    pub host:          Schema<host::HostSource>,
+    pub host_pressure: TableMetadata,
+}
+
+impl Schemas {
+    /// Loads the metric selection from the command line options and builds the
+    /// schemas for it, failing if any metric selector doesn't match a column in
+    /// any schema
+    pub fn new(opts: &CollectionOptions) -> Result<Self, MetricsError> {
+        let selection = MetricSelection::new(opts)?;
+        let v1_groups = get_groups(opts);
+        let v2_groups = v2::get_groups(opts);
+        let process_groups = process::get_groups();
+        let host_groups = host::get_groups();
+        let columns = v1_groups
+            .iter()
+            .flat_map(ColumnGroup::columns)
+            .chain(v2_groups.iter().flat_map(ColumnGroup::columns))
+            .chain(process_groups.iter().flat_map(ColumnGroup::columns))
+            .chain(host_groups.iter().flat_map(ColumnGroup::columns))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        selection.validate(&columns)?;
+
+        Ok(Self {
+            cgroup_v1:     Schema::new(&v1_groups, get_column_metadata(), &selection),
+            cgroup_v2:     Schema::new(&v2_groups, v2::get_column_metadata(), &selection),
+            process:       Schema::new(&process_groups, process::get_column_metadata(), &selection),
+            host:          Schema::new(&host_groups, host::get_column_metadata(), &selection),
+            host_pressure: psi::get_host_table_metadata(),
+        })
+    }
+
+    /// Gets the number of columns in the largest CSV schema, not including any
+    /// per-device columns
+    fn max_row_length(&self) -> usize {
+        [
+            self.cgroup_v1.plan.header().len(),
+            self.cgroup_v2.plan.header().len(),
+            self.process.plan.header().len(),
+            self.host.plan.header().len(),
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+    }
+
+    /// Gets the largest number of fields written by a single read in any CSV
+    /// schema
+    fn max_read(&self) -> usize {
+        [
+            self.cgroup_v1.plan.max_read(),
+            self.cgroup_v2.plan.max_read(),
+            self.process.plan.max_read(),
+            self.host.plan.max_read(),
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+    }
+}
 
 /// Length of the buffer for each row. Designed to be a reasonable upper limit
 /// to prevent expensive re-allocation
 const ROW_BUFFER_SIZE: usize = 1200;
 
-/// Length of the buffer used to build up stat file entries as the reader uses
-/// pre-examined layouts to map lines to entries.
-///
-/// **Currently set to the number of entries used for `memory.stat`**
-const SLICES_BUFFER_SIZE: usize = 16;
-
 /// Working buffers used to avoid heap allocations at runtime
 pub struct WorkingBuffers {
     record:      ByteRecord,
+    /// Record that reads which include unselected columns are written to
+    /// before the selected fields are copied to the main record
+    scratch:     ByteRecord,
     buffer:      Buffer,
     copy_buffer: Buffer,
-    slices:      [AnonymousSlice; SLICES_BUFFER_SIZE],
-}
-
-impl Default for WorkingBuffers {
-    fn default() -> Self { Self::new() }
+    /// Buffer used to build up stat file entries as the reader uses
+    /// pre-examined layouts to map lines to entries, with a slice for each
+    /// entry of the largest read
+    slices:      Vec<AnonymousSlice>,
 }
 
 impl WorkingBuffers {
-    /// Allocates the working buffers using upper limits to avoid expensive heap
-    /// allocations at runtime
+    /// Allocates the working buffers using upper limits (based on the given
+    /// schemas) to avoid expensive heap allocations at runtime
     #[must_use]
-    pub fn new() -> Self {
+    pub fn new(schemas: &Schemas) -> Self {
+        let row_length = schemas.max_row_length();
         Self {
-            record:      ByteRecord::with_capacity(ROW_BUFFER_SIZE, *ROW_LENGTH),
-            slices:      [<AnonymousSlice>::default(); SLICES_BUFFER_SIZE],
+            record:      ByteRecord::with_capacity(ROW_BUFFER_SIZE, row_length),
+            scratch:     ByteRecord::with_capacity(ROW_BUFFER_SIZE, row_length),
+            slices:      vec![<AnonymousSlice>::default(); schemas.max_read()],
             buffer:      Buffer::new(),
             copy_buffer: Buffer::new(),
         }
     }
 }
 
-/// Collects the current statistics for the given target, writing the CSV
-/// entries to the writer. Utilizes /proc and cgroups (Linux-only)
+/// Open file handles and pre-examined stat file layouts for a single target in
+/// the cgroup v1 hierarchies
+pub struct CgroupV1Source {
+    file_handles:     ProcFileHandles,
+    cpu_layout:       read::StatFileLayout,
+    memory_layout:    read::StatFileLayout,
+    network_handles:  NetworkFileHandles,
+    pressure_handles: PressureFileHandles,
+    device_layout:    Option<DeviceLayout>,
+    controllers:      ControllerHandles,
+}
+
+impl CgroupV1Source {
+    /// Opens the file handles read by the plan for the given cgroup in the v1
+    /// hierarchies and examines the layout of the variable stat files, as well
+    /// as the set of block devices if collecting per-device statistics and the
+    /// files of any additional controllers
+    #[must_use]
+    pub fn new(
+        cgroup: &CgroupPath,
+        plan: &ReadPlan<Self>,
+        per_device: bool,
+        controllers: &[Controller],
+    ) -> Self {
+        let file_handles = ProcFileHandles::new(&cgroup.path, |file| {
+            plan.opens(file) || (per_device && devices::V1_FILES.contains(&file))
+        });
+        let cpu_layout = plan.layout("cpu.stat", &file_handles.cpu_stat);
+        let memory_layout = plan.layout("memory.stat", &file_handles.memory_stat);
+        let device_layout = match per_device {
+            true => Some(DeviceLayout::cgroup_v1(&file_handles)),
+            false => None,
+        };
+        let network_dir = match plan.opens(network::FILES[0]) {
+            true => util::cgroup_dir(&cgroup.path, cgroup.version),
+            false => None,
+        };
+        let pressure_dir = util::cgroup_dir(&cgroup.path, CgroupVersion::V2);
+        Self {
+            file_handles,
+            cpu_layout,
+            memory_layout,
+            network_handles: NetworkFileHandles::new(network_dir),
+            pressure_handles: PressureFileHandles::new(pressure_dir.as_ref(), |file| {
+                plan.opens(file)
+            }),
+            device_layout,
+            controllers: ControllerHandles::new(cgroup, controllers),
+        }
+    }
+}
+
+/// Open file handles and pre-examined stat file layouts for a single target,
+/// depending on the collection method (and resultant CSV schema) used
+pub enum CollectionSource {
+    /// Per-controller hierarchies of cgroup v1
+    CgroupV1(CgroupV1Source),
+    /// Unified hierarchy of cgroup v2
+    CgroupV2(v2::CgroupV2Source),
+    /// Per-process files in /proc
+    Process(ProcessFileHandles),
+    /// System-wide files in /proc
+    Host(host::HostSource),
+    /// System-wide pressure files in /proc/pressure
+    HostPressure(PressureFileHandles),
+}
+
+impl CollectionSource {
+    /// Builds the header row for the CSV schema used by the source, including
+    /// any per-device columns at the end
+    #[must_use]
+    pub fn header(&self, schemas: &Schemas) -> ByteRecord {
+        match self {
+            Self::CgroupV1(source) => {
+                let mut header = schemas.cgroup_v1.plan.header().clone();
+                if let Some(layout) = &source.device_layout {
+                    layout.append_headers("blkio", &mut header);
+                }
+                source.controllers.append_headers(&mut header);
+                header
+            },
+            Self::CgroupV2(source) => {
+                let mut header = schemas.cgroup_v2.plan.header().clone();
+                if let Some(layout) = source.device_layout() {
+                    layout.append_headers("io", &mut header);
+                }
+                source.controllers().append_headers(&mut header);
+                header
+            },
+            Self::Process(_) => schemas.process.plan.header().clone(),
+            Self::Host(_) => schemas.host.plan.header().clone(),
+            Self::HostPressure(_) => psi::get_host_header().clone(),
+        }
+    }
+
+    /// Gets the entries of each stat file that weren't found when it was
+    /// examined (and so are always written as empty fields), if there are any
+    #[must_use]
+    pub fn unknown_stat_keys(&self) -> Option<BTreeMap<String, Vec<String>>> {
+        let layouts = match self {
+            Self::CgroupV1(source) => vec![
+                ("cpu.stat", &source.cpu_layout),
+                ("memory.stat", &source.memory_layout),
+            ],
+            Self::CgroupV2(source) => source.layouts(),
+            Self::Host(source) => source.layouts(),
+            Self::Process(_) | Self::HostPressure(_) => Vec::new(),
+        };
+        let unknown = layouts
+            .into_iter()
+            .filter(|(_, layout)| !layout.missing().is_empty())
+            .map(|(file, layout)| (String::from(file), layout.missing().to_vec()))
+            .collect::<BTreeMap<_, _>>();
+        match unknown.is_empty() {
+            true => None,
+            false => Some(unknown),
+        }
+    }
+
+    /// Gets the set of block devices that per-device statistics are collected
+    /// for, if enabled
+    #[must_use]
+    pub const fn device_layout(&self) -> Option<&DeviceLayout> {
+        match self {
+            Self::CgroupV1(source) => source.device_layout.as_ref(),
+            Self::CgroupV2(source) => source.device_layout(),
+            Self::Process(_) | Self::Host(_) | Self::HostPressure(_) => None,
+        }
+    }
+
+    /// Gets the effective cpuset of the target, if the cpuset controller is
+    /// collected
+    #[must_use]
+    pub const fn cpuset(&self) -> Option<&Cpuset> {
+        match self {
+            Self::CgroupV1(source) => source.controllers.cpuset(),
+            Self::CgroupV2(source) => source.controllers().cpuset(),
+            Self::Process(_) | Self::Host(_) | Self::HostPressure(_) => None,
+        }
+    }
+}
+
+/// Collects the current statistics for the given target, writing the entries
+/// to each sink. Utilizes /proc and cgroups (Linux-only)
 pub fn run(collector: &mut Collector, buffers: &mut WorkingBuffers) -> Result<(), Error> {
     collect_read(buffers);
-    collect_pids(buffers, &collector.file_handles);
-    collect_cpu(buffers, &collector.file_handles);
-    collect_memory(buffers, &collector.file_handles, &collector.memory_layout);
-    collect_blkio(buffers, &collector.file_handles);
-    collector.writer.write_byte_record(&buffers.record)?;
+    let schemas = &collector.schemas;
+    match &mut collector.source {
+        CollectionSource::CgroupV1(source) => {
+            schemas.cgroup_v1.plan.run(buffers, source);
+            if let Some(layout) = &source.device_layout {
+                devices::collect_v1(buffers, &source.file_handles, layout);
+            }
+            controllers::collect(buffers, &source.controllers);
+        },
+        CollectionSource::CgroupV2(source) => {
+            schemas.cgroup_v2.plan.run(buffers, source);
+            v2::collect_devices(buffers, source);
+            controllers::collect(buffers, source.controllers());
+        },
+        CollectionSource::Process(source) => schemas.process.plan.run(buffers, source),
+        CollectionSource::Host(source) => schemas.host.plan.run(buffers, source),
+        CollectionSource::HostPressure(handles) => psi::collect(buffers, handles),
+    }
+    // Write to every sink even if one of them fails, reporting the last error
+    let mut result = Ok(());
+    for sink in &mut collector.sinks {
+        if let Err(err) = sink.write(&buffers.record) {
+            result = Err(err);
+        }
+    }
     buffers.record.clear();
-    Ok(())
+    result
 }
 
 /// Collects the nanosecond unix timestamp read time
@@ -167,34 +511,19 @@ fn collect_read(buffers: &mut WorkingBuffers) {
     buffers.buffer.clear_unmanaged();
 }
 
-/// Collects all stats for the pids subsystem
-/// see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/pids.html>
-#[inline]
-fn collect_pids(buffers: &mut WorkingBuffers, handles: &ProcFileHandles) {
-    read::entry(&handles.current_pids, buffers);
-    read::entry(&handles.max_pids, buffers);
-}
-
 /// String offsets used for row headers for the cpuacct.stat file
 const CPUACCT_STAT_OFFSETS: [usize; 2] = ["user".len(), "system".len()];
-/// String offsets used for row headers for the cpu.stat file
-const CPU_STAT_OFFSETS: [usize; 3] = [
-    "nr_periods".len(),
-    "nr_throttled".len(),
-    "throttled_time".len(),
-];
 
-/// Collects all stats for the cpu and cpuacct subsystems
-/// see <https://access.redhat.com/documentation/en-us/red_hat_enterprise_linux/6/html/resource_management_guide/sec-cpuacct>
-#[inline]
-fn collect_cpu(buffers: &mut WorkingBuffers, handles: &ProcFileHandles) {
-    read::entry(&handles.cpuacct_usage, buffers);
-    read::entry(&handles.cpuacct_usage_sys, buffers);
-    read::entry(&handles.cpuacct_usage_user, buffers);
-    read::entry(&handles.cpuacct_usage_percpu, buffers);
-    read::stat_file(&handles.cpuacct_stat, &CPUACCT_STAT_OFFSETS, buffers);
-    read::stat_file(&handles.cpu_stat, &CPU_STAT_OFFSETS, buffers);
-}
+/// Original entries in the cpu.stat file that map to columns (in the same
+/// order) in the final output
+const CPU_STAT_ENTRIES: &[&[u8]] = &[b"nr_periods", b"nr_throttled", b"throttled_time"];
+
+/// Columns in the output that each entry in `CPU_STAT_ENTRIES` maps to
+const CPU_STAT_COLUMNS: &[&str] = &[
+    "cpu.throttling.periods",
+    "cpu.throttling.throttled.count",
+    "cpu.throttling.throttled.time",
+];
 
 /// Original entries in the memory.stat file that map to columns (in the same
 /// order) in the final output
@@ -217,42 +546,22 @@ const MEMORY_STAT_ENTRIES: &[&[u8]] = &[
     b"total_unevictable",
 ];
 
-/// Generates a stat file layout struct for `memory.stat`
-#[must_use]
-pub fn examine_memory(handles: &ProcFileHandles) -> read::StatFileLayout {
-    read::StatFileLayout::new(&handles.memory_stat, MEMORY_STAT_ENTRIES)
-}
-
-/// Collects all stats for the memory subsystem
-/// see <https://access.redhat.com/documentation/en-us/red_hat_enterprise_linux/6/html/resource_management_guide/sec-memory>
-#[inline]
-fn collect_memory(
-    buffers: &mut WorkingBuffers,
-    handles: &ProcFileHandles,
-    layout: &read::StatFileLayout,
-) {
-    read::entry(&handles.memory_usage_in_bytes, buffers);
-    read::entry(&handles.memory_max_usage_in_bytes, buffers);
-    read::entry(&handles.memory_limit_in_bytes, buffers);
-    read::entry(&handles.memory_soft_limit_in_bytes, buffers);
-    read::entry(&handles.memory_failcnt, buffers);
-    read::with_layout(&handles.memory_stat, layout, buffers)
-}
-
-/// Collects all stats for the blkio subsystem
-/// see <https://www.kernel.org/doc/Documentation/cgroup-v1/blkio-controller.txt>
-#[inline]
-fn collect_blkio(buffers: &mut WorkingBuffers, handles: &ProcFileHandles) {
-    read::simple_io(&handles.blkio_time, buffers);
-    read::simple_io(&handles.blkio_sectors, buffers);
-    read::io(&handles.blkio_io_service_bytes, buffers);
-    read::io(&handles.blkio_io_serviced, buffers);
-    read::io(&handles.blkio_io_service_time, buffers);
-    read::io(&handles.blkio_io_queued, buffers);
-    read::io(&handles.blkio_io_wait_time, buffers);
-    read::io(&handles.blkio_io_merged, buffers);
-    read::io(&handles.blkio_throttle_io_service_bytes, buffers);
-    read::io(&handles.blkio_throttle_io_serviced, buffers);
-    read::io(&handles.blkio_bfq_io_service_bytes, buffers);
-    read::io(&handles.blkio_bfq_io_serviced, buffers);
-}
+/// Columns in the output that each entry in `MEMORY_STAT_ENTRIES` maps to
+const MEMORY_STAT_COLUMNS: &[&str] = &[
+    "memory.hierarchical_limit.memory",
+    "memory.hierarchical_limit.memoryswap",
+    "memory.cache",
+    "memory.rss.all",
+    "memory.rss.huge",
+    "memory.mapped",
+    "memory.swap",
+    "memory.paged.in",
+    "memory.paged.out",
+    "memory.fault.total",
+    "memory.fault.major",
+    "memory.anon.inactive",
+    "memory.anon.active",
+    "memory.file.inactive",
+    "memory.file.active",
+    "memory.unevictable",
+];
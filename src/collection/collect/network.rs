@@ -0,0 +1,86 @@
+//! Collection of network statistics for cgroup targets, which aren't exposed
+//! by any cgroup controller. Instead, statistics are read from the network
+//! namespace of a process in the target's cgroup, using `/proc/<pid>/net`.
+//! See <https://man7.org/linux/man-pages/man5/proc.5.html>
+
+use crate::collection::collect::files::NetworkFileHandles;
+use crate::collection::collect::{read, WorkingBuffers};
+use crate::collection::perf_table::{Column, ColumnType};
+use std::collections::BTreeMap;
+use std::fs::File;
+
+/// Indices of the values in each interface line of `/proc/<pid>/net/dev` that
+/// map to columns (in the same order) in the final output, counted from the
+/// value after the interface name
+const DEV_FIELDS: &[usize] = &[0, 1, 2, 3, 8, 9, 10, 11];
+
+/// Column in the output that contains the name of each interface, which
+/// determines the order of the entries in each of the `DEV_COLUMNS`
+const INTERFACES_COLUMN: &str = "network.interfaces";
+
+/// Columns in the output that each field in `DEV_FIELDS` maps to
+const DEV_COLUMNS: &[&str] = &[
+    "network.rx.bytes",
+    "network.rx.packets",
+    "network.rx.errors",
+    "network.rx.dropped",
+    "network.tx.bytes",
+    "network.tx.packets",
+    "network.tx.errors",
+    "network.tx.dropped",
+];
+
+/// Protocol and key of the entry in `/proc/<pid>/net/snmp` that maps to the
+/// TCP retransmits column
+const SNMP_RETRANSMITS: (&[u8], &[u8]) = (b"Tcp:", b"RetransSegs");
+
+/// Column in the output that the TCP retransmits entry maps to
+const RETRANSMITS_COLUMN: &str = "network.tcp.retransmits";
+
+/// Files in `/proc/<pid>` that the network columns are read from
+pub const FILES: &[&str] = &["net/dev", "net/snmp"];
+
+/// Gets the names of all network columns
+#[must_use]
+pub fn columns() -> Vec<String> {
+    let mut columns = vec![String::from(INTERFACES_COLUMN)];
+    columns.extend(DEV_COLUMNS.iter().map(|&c| String::from(c)));
+    columns.push(String::from(RETRANSMITS_COLUMN));
+    columns
+}
+
+/// Includes metadata on the per-interface network columns, which are vector
+/// columns that contain a space-delimited entry per interface. Since the
+/// interfaces in a network namespace can change, their number varies
+pub fn append_table_metadata(columns: &mut BTreeMap<String, Column>) {
+    columns.insert(String::from(INTERFACES_COLUMN), Column::Vector {
+        r#type: ColumnType::String,
+        count:  None,
+    });
+    for &column in DEV_COLUMNS {
+        columns.insert(String::from(column), Column::Vector {
+            r#type: ColumnType::Int,
+            count:  None,
+        });
+    }
+}
+
+/// Collects all network stats for the network namespace of the target,
+/// re-resolving the process used to find the namespace if it has exited (at
+/// most once per backoff delay while the cgroup has no processes)
+#[inline]
+pub fn collect(buffers: &mut WorkingBuffers, handles: &mut NetworkFileHandles) {
+    if !handles.is_alive() {
+        handles.resolve();
+    }
+
+    collect_files(buffers, &handles.dev, &handles.snmp);
+}
+
+/// Collects all network stats from the given `net/dev` and `net/snmp` files
+#[inline]
+pub fn collect_files(buffers: &mut WorkingBuffers, dev: &Option<File>, snmp: &Option<File>) {
+    read::table(dev, DEV_FIELDS, buffers);
+    let (protocol, key) = SNMP_RETRANSMITS;
+    read::snmp(snmp, protocol, key, buffers);
+}
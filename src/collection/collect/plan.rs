@@ -0,0 +1,313 @@
+//! Column selection for the collected statistics, which determines the columns
+//! that each CSV schema writes. Each schema is made up of groups of columns
+//! that are read together from the same file(s); the groups that contain at
+//! least one selected column make up the read plan that gets run on each tick,
+//! and only the files that they read get opened.
+
+use crate::cli::CollectionOptions;
+use crate::collection::collect::read::{StatEntries, StatFileLayout};
+use crate::collection::collect::WorkingBuffers;
+use crate::collection::perf_table::{Column, TableMetadata};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::mem;
+use std::path::PathBuf;
+
+use csv::ByteRecord;
+
+/// Name of the timestamp column that is always the first column of each row
+pub const READ_COLUMN: &str = "read";
+
+/// Selectors given with `--metrics` and `--metrics-file` that select which
+/// columns are collected. A selector matches a column if it is the column's
+/// name or a prefix of it that ends at a `.` (so `memory.usage` selects both
+/// `memory.usage.current` and `memory.usage.max`)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricSelection {
+    /// Selectors given, or None if every column is collected
+    selectors: Option<Vec<String>>,
+}
+
+/// Possible errors that can occur while loading the metric selection
+#[derive(Debug)]
+pub enum MetricsError {
+    ReadFile(PathBuf, std::io::Error),
+    ParseFile(PathBuf, serde_yaml::Error),
+    UnknownMetric(String),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadFile(path, err) => {
+                write!(
+                    f,
+                    "Could not read metrics file at {}: {}",
+                    path.display(),
+                    err
+                )
+            },
+            Self::ParseFile(path, err) => write!(
+                f,
+                "Could not parse metrics file at {}: {}. Metrics files contain a YAML list of \
+                 metric selectors",
+                path.display(),
+                err
+            ),
+            Self::UnknownMetric(selector) => write!(
+                f,
+                "Unknown metric '{}'. Metric selectors are either a column name (such as \
+                 memory.usage.current) or a prefix of column names (such as cpu)",
+                selector
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MetricsError {}
+
+impl MetricSelection {
+    /// Loads the metric selection from the `--metrics` and `--metrics-file`
+    /// command line options, selecting every column if neither was given
+    pub fn new(opts: &CollectionOptions) -> Result<Self, MetricsError> {
+        let mut selectors = opts.metrics.clone();
+        if let Some(path) = &opts.metrics_file {
+            let raw = fs::read_to_string(path)
+                .map_err(|err| MetricsError::ReadFile(path.clone(), err))?;
+            let from_file: Vec<String> = serde_yaml::from_str(&raw)
+                .map_err(|err| MetricsError::ParseFile(path.clone(), err))?;
+            selectors.extend(from_file);
+        }
+
+        let selectors: Vec<String> = selectors
+            .into_iter()
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect();
+        Ok(Self {
+            selectors: match selectors.is_empty() {
+                true => None,
+                false => Some(selectors),
+            },
+        })
+    }
+
+    /// Whether the given column is selected. The `read` column is always
+    /// selected
+    #[must_use]
+    pub fn matches(&self, column: &str) -> bool {
+        match &self.selectors {
+            None => true,
+            Some(selectors) => {
+                column == READ_COLUMN || selectors.iter().any(|s| selects(s, column))
+            },
+        }
+    }
+
+    /// Ensures that every selector matches the `read` column or at least one of
+    /// the given columns, failing on the first that doesn't
+    pub fn validate(&self, columns: &[&str]) -> Result<(), MetricsError> {
+        for selector in self.selectors.iter().flatten() {
+            if selector != READ_COLUMN && !columns.iter().any(|c| selects(selector, c)) {
+                return Err(MetricsError::UnknownMetric(selector.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the selectors that get included with the perf table metadata of
+    /// each log file, if not every column is selected
+    #[must_use]
+    pub const fn selectors(&self) -> Option<&Vec<String>> { self.selectors.as_ref() }
+}
+
+/// Whether the selector selects the given column
+#[must_use]
+pub fn selects(selector: &str, column: &str) -> bool {
+    match column.strip_prefix(selector) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Function that reads a group of columns from a source, pushing one field per
+/// column to the record in the working buffers
+pub type ReadFn<S> = fn(&mut WorkingBuffers, &mut S);
+
+/// Group of columns in a CSV schema that are read together from the same
+/// file(s), where `S` contains the open file handles for a single target
+pub struct ColumnGroup<S> {
+    /// Files that the group reads, which are only opened if the group is part
+    /// of the read plan
+    files:   &'static [&'static str],
+    /// Columns that the group writes, in order
+    columns: Vec<String>,
+    /// Keys of the entries in the group's stat file that map to its columns,
+    /// if the group is read using a pre-examined layout
+    keys:    Option<Vec<Vec<u8>>>,
+    read:    ReadFn<S>,
+}
+
+impl<S> ColumnGroup<S> {
+    #[must_use]
+    pub fn new<I>(files: &'static [&'static str], columns: I, read: ReadFn<S>) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Self {
+            files,
+            columns: columns.into_iter().map(|c| c.as_ref().to_owned()).collect(),
+            keys: None,
+            read,
+        }
+    }
+
+    /// Creates a group of columns that is read from a single stat file using a
+    /// layout examined for the given entries (see `ReadPlan::layout`)
+    #[must_use]
+    pub fn stat(file: &'static [&'static str], entries: StatEntries, read: ReadFn<S>) -> Self {
+        Self {
+            files: file,
+            columns: entries.columns,
+            keys: Some(entries.keys),
+            read,
+        }
+    }
+
+    /// Gets the columns that the group writes
+    #[must_use]
+    pub fn columns(&self) -> &[String] { &self.columns }
+}
+
+/// Sequence of reads to run on each tick for a single CSV schema, built once
+/// from the metric selection and shared between all collectors of the schema
+pub struct ReadPlan<S> {
+    steps:     Vec<Step<S>>,
+    header:    ByteRecord,
+    columns:   Vec<String>,
+    files:     Vec<&'static str>,
+    /// Keys of the entries read from each stat file, for the groups in the
+    /// plan that are read using a pre-examined layout
+    stat_keys: Vec<(&'static str, Vec<Vec<u8>>)>,
+    /// Largest number of fields written by a single read in the plan
+    max_read:  usize,
+}
+
+/// Single read in a read plan
+struct Step<S> {
+    read:   ReadFn<S>,
+    /// Indices of the fields written by the read that are selected, or None
+    /// if all of them are
+    fields: Option<Vec<usize>>,
+}
+
+impl<S> ReadPlan<S> {
+    /// Builds the read plan for the given groups of columns (in the order of
+    /// the output), only including the groups with at least one selected column
+    #[must_use]
+    pub fn new(groups: &[ColumnGroup<S>], selection: &MetricSelection) -> Self {
+        let mut steps: Vec<Step<S>> = Vec::new();
+        let mut columns: Vec<String> = vec![String::from(READ_COLUMN)];
+        let mut files: Vec<&'static str> = Vec::new();
+        let mut stat_keys: Vec<(&'static str, Vec<Vec<u8>>)> = Vec::new();
+        let mut max_read = 0;
+        for group in groups {
+            let fields = group
+                .columns
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| selection.matches(c))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+
+            columns.extend(fields.iter().map(|&i| group.columns[i].clone()));
+            files.extend(group.files);
+            if let (Some(keys), Some(&file)) = (&group.keys, group.files.first()) {
+                stat_keys.push((file, keys.clone()));
+            }
+            max_read = cmp::max(max_read, group.columns.len());
+            steps.push(Step {
+                read:   group.read,
+                fields: match fields.len() == group.columns.len() {
+                    true => None,
+                    false => Some(fields),
+                },
+            });
+        }
+
+        Self {
+            steps,
+            header: ByteRecord::from(columns.clone()),
+            columns,
+            files,
+            stat_keys,
+            max_read,
+        }
+    }
+
+    /// Gets the header row for the columns in the plan
+    #[must_use]
+    pub const fn header(&self) -> &ByteRecord { &self.header }
+
+    /// Whether the given file is read by the plan, and so should be opened
+    #[must_use]
+    pub fn opens(&self, file: &str) -> bool { self.files.contains(&file) }
+
+    /// Examines the layout of the given stat file for the entries of the group
+    /// that reads it, or returns an empty layout if it isn't read by the plan
+    #[must_use]
+    pub fn layout(&self, file: &str, handle: &Option<File>) -> StatFileLayout {
+        match self.stat_keys.iter().find(|(f, _)| *f == file) {
+            Some((_, keys)) => StatFileLayout::new(handle, keys),
+            None => StatFileLayout::new::<&[u8]>(&None, &[]),
+        }
+    }
+
+    /// Gets the largest number of fields written by a single read in the plan,
+    /// which determines the size of the slices buffer
+    #[must_use]
+    pub const fn max_read(&self) -> usize { self.max_read }
+
+    /// Builds the perf table metadata for the columns in the plan, given the
+    /// metadata for all columns of the schema with non-default types
+    #[must_use]
+    pub fn table_metadata(
+        &self,
+        mut all_columns: BTreeMap<String, Column>,
+        selection: &MetricSelection,
+    ) -> TableMetadata {
+        all_columns.retain(|name, _| self.columns.contains(name));
+        TableMetadata {
+            delimiter: ",",
+            columns:   all_columns,
+            metrics:   selection.selectors().cloned(),
+        }
+    }
+
+    /// Runs each read in the plan for the given source. Reads that include
+    /// unselected columns are first written to the scratch record, and then
+    /// only the selected fields are copied over
+    #[inline]
+    pub fn run(&self, buffers: &mut WorkingBuffers, source: &mut S) {
+        for step in &self.steps {
+            match &step.fields {
+                None => (step.read)(buffers, source),
+                Some(fields) => {
+                    mem::swap(&mut buffers.record, &mut buffers.scratch);
+                    (step.read)(buffers, source);
+                    mem::swap(&mut buffers.record, &mut buffers.scratch);
+                    for &i in fields {
+                        buffers.record.push_field(&buffers.scratch[i]);
+                    }
+                    buffers.scratch.clear();
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Collection for individual processes, using the per-process files in
+//! `/proc/<pid>` rather than any cgroup.
+//! See <https://man7.org/linux/man-pages/man5/proc.5.html>
+
+use crate::collection::collect::files::ProcessFileHandles;
+use crate::collection::collect::plan::ColumnGroup;
+use crate::collection::collect::read;
+use crate::collection::perf_table::{Column, ColumnType};
+use std::collections::BTreeMap;
+
+/// Group of columns in the per-process logfiles
+type Group = ColumnGroup<ProcessFileHandles>;
+
+/// Creates the groups of columns for the per-process logfiles (in the order of
+/// the output), along with the files that each group is read from
+#[must_use]
+pub fn get_groups() -> Vec<Group> {
+    vec![
+        Group::new(&["stat"], STAT_COLUMNS, |b, h| {
+            read::fields(&h.stat, b')', STAT_FIELDS, b);
+        }),
+        Group::new(&["status"], STATUS_COLUMNS, |b, h| {
+            read::keyed_values(&h.status, STATUS_KEYS, b);
+        }),
+        Group::new(&["io"], IO_COLUMNS, |b, h| {
+            read::keyed_values(&h.io, IO_KEYS, b);
+        }),
+    ]
+}
+
+/// Gets the metadata for all columns in the per-process logfiles with
+/// non-default types
+#[must_use]
+pub fn get_column_metadata() -> BTreeMap<String, Column> {
+    let mut columns: BTreeMap<String, Column> = BTreeMap::new();
+    // Include metadata on the read (timestamp) column
+    columns.insert(String::from("read"), Column::Scalar {
+        r#type: ColumnType::Epoch19,
+    });
+    columns
+}
+
+/// Indices of the fields in `/proc/<pid>/stat` that map to columns (in the same
+/// order) in the final output, counted from the field after the command name
+/// (the process state, which is field 3 in proc(5))
+const STAT_FIELDS: &[usize] = &[0, 7, 9, 11, 12, 17];
+
+/// Columns in the output that each field in `STAT_FIELDS` maps to
+const STAT_COLUMNS: &[&str] = &[
+    "process.state",
+    "memory.fault.minor",
+    "memory.fault.major",
+    "cpu.time.user",
+    "cpu.time.system",
+    "process.threads",
+];
+
+/// Keys in `/proc/<pid>/status` that map to columns (in the same order) in the
+/// final output
+const STATUS_KEYS: &[&[u8]] = &[
+    b"VmPeak",
+    b"VmSize",
+    b"VmHWM",
+    b"VmRSS",
+    b"RssAnon",
+    b"RssFile",
+    b"RssShmem",
+    b"VmSwap",
+    b"voluntary_ctxt_switches",
+    b"nonvoluntary_ctxt_switches",
+];
+
+/// Columns in the output that each key in `STATUS_KEYS` maps to
+const STATUS_COLUMNS: &[&str] = &[
+    "memory.virtual.max",
+    "memory.virtual.current",
+    "memory.rss.max",
+    "memory.rss.current",
+    "memory.rss.anon",
+    "memory.rss.file",
+    "memory.rss.shmem",
+    "memory.swap",
+    "process.switches.voluntary",
+    "process.switches.involuntary",
+];
+
+/// Keys in `/proc/<pid>/io` that map to columns (in the same order) in the
+/// final output
+const IO_KEYS: &[&[u8]] = &[
+    b"rchar",
+    b"wchar",
+    b"syscr",
+    b"syscw",
+    b"read_bytes",
+    b"write_bytes",
+    b"cancelled_write_bytes",
+];
+
+/// Columns in the output that each key in `IO_KEYS` maps to
+const IO_COLUMNS: &[&str] = &[
+    "io.chars.read",
+    "io.chars.write",
+    "io.syscalls.read",
+    "io.syscalls.write",
+    "io.service.bytes.read",
+    "io.service.bytes.write",
+    "io.cancelled.bytes.write",
+];
@@ -0,0 +1,134 @@
+//! Collection of Pressure Stall Information (PSI), which reports the share of
+//! time that tasks were stalled waiting on the CPU, memory, or I/O. Available
+//! per cgroup in the unified (v2) hierarchy (`<resource>.pressure`) and
+//! system-wide in `/proc/pressure/<resource>` on kernels 4.20+.
+//! See <https://www.kernel.org/doc/html/latest/accounting/psi.html>
+
+use crate::collection::collect::files::PressureFileHandles;
+use crate::collection::collect::host;
+use crate::collection::collect::{read, WorkingBuffers};
+use crate::collection::perf_table::{Column, ColumnType, TableMetadata};
+use crate::shared::CollectionTarget;
+use crate::util;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use csv::ByteRecord;
+
+lazy_static::lazy_static! {
+    /// CSV header for the host-level pressure collector
+    static ref HOST_HEADER: ByteRecord = ByteRecord::from(get_host_headers());
+}
+
+/// Id of the built-in host pressure target, which targets from providers can't
+/// use
+pub const TARGET_ID: &str = "host-pressure";
+
+/// Directory containing the system-wide pressure files
+pub const HOST_PRESSURE_DIR: &str = "/proc/pressure";
+
+/// Resources that each have a pressure file, in the order of their columns in
+/// the final output
+const RESOURCES: &[&str] = &["cpu", "memory", "io"];
+
+/// Line categories and keys of the entries in each pressure file that map to
+/// columns (in the same order) in the final output
+const PRESSURE_ENTRIES: &[(&[u8], &[u8])] = &[
+    (b"some", b"avg10"),
+    (b"some", b"total"),
+    (b"full", b"avg10"),
+    (b"full", b"total"),
+];
+
+/// Suffixes of the columns in the output that each entry in `PRESSURE_ENTRIES`
+/// maps to, following `<resource>.pressure`
+const PRESSURE_SUFFIXES: &[&str] = &["some.avg10", "some.total", "full.avg10", "full.total"];
+
+/// Gets the names of the pressure columns for the given resource
+#[must_use]
+pub fn columns(resource: &str) -> Vec<String> {
+    PRESSURE_SUFFIXES
+        .iter()
+        .map(|suffix| format!("{}.pressure.{}", resource, suffix))
+        .collect()
+}
+
+/// Includes metadata on the pressure columns, where the running averages are
+/// percentages with two decimal places
+pub fn append_table_metadata(columns: &mut BTreeMap<String, Column>) {
+    for resource in RESOURCES {
+        for window in &["some.avg10", "full.avg10"] {
+            columns.insert(
+                format!("{}.pressure.{}", resource, window),
+                Column::Scalar {
+                    r#type: ColumnType::Float,
+                },
+            );
+        }
+    }
+}
+
+/// Creates the headers for the host-level pressure logfile
+fn get_host_headers() -> Vec<String> {
+    let mut headers = vec![String::from("read")];
+    headers.extend(RESOURCES.iter().flat_map(|resource| columns(resource)));
+    headers
+}
+
+/// Gets the perf table metadata for the host-level pressure collection setup
+/// (currently static)
+#[must_use]
+pub fn get_host_table_metadata() -> TableMetadata {
+    let mut columns: BTreeMap<String, Column> = BTreeMap::new();
+    // Include metadata on the read (timestamp) column
+    columns.insert(String::from("read"), Column::Scalar {
+        r#type: ColumnType::Epoch19,
+    });
+    append_table_metadata(&mut columns);
+    TableMetadata {
+        delimiter: ",",
+        columns,
+        metrics: None,
+    }
+}
+
+/// Gets an amortized byte record containing the entries for a header row in the
+/// host-level pressure CSV log file
+#[must_use]
+pub fn get_host_header() -> &'static ByteRecord { &HOST_HEADER }
+
+/// Whether system-wide pressure information is available, which requires a
+/// kernel built with `CONFIG_PSI` that wasn't booted with `psi=0`
+#[must_use]
+pub fn host_available() -> bool { Path::new(HOST_PRESSURE_DIR).join("cpu").exists() }
+
+/// Creates the built-in target used to collect system-wide pressure
+/// information into its own log file, alongside the per-target logs
+#[must_use]
+pub fn host_target() -> CollectionTarget {
+    CollectionTarget {
+        provider:  host::PROVIDER_TYPE,
+        id:        String::from(TARGET_ID),
+        name:      String::from("host pressure"),
+        metadata:  None,
+        poll_time: util::nano_ts(),
+        filter:    None,
+    }
+}
+
+/// Collects the pressure stats for all resources, writing empty fields for any
+/// that are unavailable
+#[inline]
+pub fn collect(buffers: &mut WorkingBuffers, handles: &PressureFileHandles) {
+    collect_file(buffers, &handles.cpu);
+    collect_file(buffers, &handles.memory);
+    collect_file(buffers, &handles.io);
+}
+
+/// Collects the pressure stats for a single resource, writing empty fields if
+/// they are unavailable
+#[inline]
+pub fn collect_file(buffers: &mut WorkingBuffers, file: &Option<File>) {
+    read::pressure(file, PRESSURE_ENTRIES, buffers);
+}
@@ -1,8 +1,11 @@
+use crate::collection::collect::devices::DeviceLayout;
 use crate::collection::collect::{AnonymousSlice, WorkingBuffers};
-use crate::util::{self, BufferLike, LazyQuantity};
+use crate::util::{self, Buffer, BufferLike, LazyQuantity};
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 
+use csv::ByteRecord;
+
 const EMPTY_BUFFER: &[u8] = &[];
 
 /// Tries to read the given file handle, and directly write the contents as a
@@ -62,6 +65,39 @@ pub fn stat_file(file: &Option<File>, offsets: &[usize], buffers: &mut WorkingBu
     buffers.buffer.clear();
 }
 
+/// Entries in a `.stat` cgroup file that map to columns (in the same order) in
+/// the final output, made up of a default set of entries and any additional
+/// entries requested by the user
+pub struct StatEntries {
+    pub keys:    Vec<Vec<u8>>,
+    pub columns: Vec<String>,
+}
+
+impl StatEntries {
+    /// Creates the default entries of a stat file, where each key maps to the
+    /// column at the same index
+    #[must_use]
+    pub fn new(keys: &[&[u8]], columns: &[&str]) -> Self {
+        Self {
+            keys:    keys.iter().map(|k| k.to_vec()).collect(),
+            columns: columns.iter().map(|&c| String::from(c)).collect(),
+        }
+    }
+
+    /// Adds the given additional keys, each of which maps to the column
+    /// `<prefix>.<key>`. Keys that are already included are skipped
+    #[must_use]
+    pub fn with_additional(mut self, prefix: &str, keys: &[String]) -> Self {
+        for key in keys {
+            if !self.keys.iter().any(|k| k == key.as_bytes()) {
+                self.keys.push(key.as_bytes().to_vec());
+                self.columns.push(format!("{}.{}", prefix, key));
+            }
+        }
+        self
+    }
+}
+
 /// Used to store the results of an initial examination of the layout of a
 /// `.stat` cgroup file, determining how each line maps to the target entries.
 /// Used for `.stat` files where the contents vary depending on the
@@ -71,7 +107,11 @@ pub struct StatFileLayout {
     /// value will be Some and the inner value will be that index, along
     /// with the length of the entry. Otherwise, if a line doesn't
     /// correspond to any indexed entry, its value will be None
-    lines: Vec<Option<StatFileLine>>,
+    lines:   Vec<Option<StatFileLine>>,
+    /// Number of target entries (and resultant fields) for the layout
+    entries: usize,
+    /// Target entries that weren't found in the file, if it could be read
+    missing: Vec<String>,
 }
 
 /// Represents metadata about a single stat file line that corresponds to an
@@ -87,7 +127,7 @@ impl StatFileLayout {
     /// Examines the layout of a stat file, to determine on which lines
     /// predetermined entries exist for faster processing during collection
     #[must_use]
-    pub fn new(file: &Option<File>, entries: &[&[u8]]) -> Self {
+    pub fn new<K: AsRef<[u8]>>(file: &Option<File>, entries: &[K]) -> Self {
         let mut buffer: Vec<u8> = Vec::new();
         let read_successful = match file {
             None => false,
@@ -112,27 +152,41 @@ impl StatFileLayout {
                         let index_option = find_index(entries, key);
                         lines_to_entries.push(index_option.map(|idx| StatFileLine {
                             entry:  idx,
-                            offset: entries[idx].len(),
+                            offset: entries[idx].as_ref().len(),
                         }));
                     },
                 }
             }
+            let missing = (0..entries.len())
+                .filter(|&idx| !lines_to_entries.iter().flatten().any(|l| l.entry == idx))
+                .map(|idx| String::from_utf8_lossy(entries[idx].as_ref()).into_owned())
+                .collect();
             Self {
                 lines: lines_to_entries,
+                entries: entries.len(),
+                missing,
             }
         } else {
             Self {
-                lines: Vec::with_capacity(0),
+                lines:   Vec::with_capacity(0),
+                entries: entries.len(),
+                missing: Vec::with_capacity(0),
             }
         }
     }
+
+    /// Gets the target entries that weren't found in the file when it was
+    /// examined, which are always written as empty fields
+    #[must_use]
+    pub fn missing(&self) -> &[String] { &self.missing }
 }
 
 /// Tries to find the index in the source array of the given target slice,
 /// comparing each byte-by-byte until the target is found. Runs in O(n) time on
 /// the length of source
-fn find_index(source: &[&[u8]], target: &[u8]) -> Option<usize> {
+fn find_index<K: AsRef<[u8]>>(source: &[K], target: &[u8]) -> Option<usize> {
     for (i, slice) in source.iter().enumerate() {
+        let slice = slice.as_ref();
         if slice.len() == target.len() {
             let mut all_equal = true;
             for j in 0..target.len() {
@@ -156,9 +210,9 @@ pub fn with_layout(file: &Option<File>, layout: &StatFileLayout, buffers: &mut W
     if successful {
         let lines = util::ByteLines::new(&buffers.buffer.b);
         for (i, (line, start)) in lines.enumerate() {
-            match &layout.lines[i] {
-                None => {},
-                Some(line_metadata) => {
+            match layout.lines.get(i) {
+                None | Some(None) => {},
+                Some(Some(line_metadata)) => {
                     let value_start = start + line_metadata.offset + 1;
                     let value_end = start + line.len();
                     buffers.slices[line_metadata.entry] = AnonymousSlice {
@@ -170,8 +224,8 @@ pub fn with_layout(file: &Option<File>, layout: &StatFileLayout, buffers: &mut W
         }
     }
 
-    // Write all slices to the record
-    for i in 0..buffers.slices.len() {
+    // Write all slices for the layout's entries to the record
+    for i in 0..layout.entries {
         let slice: &[u8] = match buffers.slices[i].consume(&buffers.buffer.b) {
             Some(s) => s,
             None => EMPTY_BUFFER,
@@ -194,7 +248,7 @@ fn clear_slice_buffer(buffers: &mut WorkingBuffers) {
 /// Attempts to read the given file into the buffer, if it exists. If
 /// successful, returns Some with the length of the part of the file read. If
 /// the file handle wasn't given, or reading was unsuccessful, returns a None
-fn read_to_buffer(file: &Option<File>, buffers: &mut WorkingBuffers) -> Option<usize> {
+pub fn read_to_buffer(file: &Option<File>, buffers: &mut WorkingBuffers) -> Option<usize> {
     match file {
         None => None,
         Some(f) => {
@@ -362,3 +416,464 @@ fn aggregate_lines_simple<'a>(buffers: &'a mut WorkingBuffers) {
 
     quantity.write_to_record(&mut buffers.copy_buffer, &mut buffers.record);
 }
+
+/// Tries to read a keyed I/O file (such as `io.stat` in cgroup v2) and
+/// aggregates the value of each of the given keys across all devices, writing
+/// one field per key to the record.
+/// The original files are in the form of:
+/// ```txt
+/// 8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0
+/// 8:0 rbytes=90430464 wbytes=299008000 rios=8950 wios=1252 dbytes=50331648 dios=3021
+/// ```
+pub fn keyed_io(file: &Option<File>, keys: &[&[u8]], buffers: &mut WorkingBuffers) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let trimmed = buffers.buffer.trim();
+    if util::content_len_raw(trimmed) == 0 {
+        // Buffer ended up empty; prevent writing NUL bytes
+        for _ in keys {
+            buffers.record.push_field(EMPTY_BUFFER);
+        }
+    } else {
+        // Scan each line once per key and aggregate into a single record
+        for key in keys {
+            aggregate_keyed_lines(buffers, key);
+        }
+    }
+
+    buffers.buffer.clear();
+}
+
+/// Scans each line in the buffer and aggregates the values for the given key
+/// (appearing as `key=value` after the device number) to make a single entry,
+/// which is written to the record
+fn aggregate_keyed_lines<'a>(buffers: &'a mut WorkingBuffers, key: &[u8]) {
+    let mut quantity: LazyQuantity<'a, u64> = LazyQuantity::default();
+    let lines = util::ByteLines::new(&buffers.buffer.b);
+    for (line, _) in lines {
+        let mut position = 0;
+        // Visit each space-delimited pair after the device number
+        while let Some(space) = util::find_char(line, position, util::is_space) {
+            position = space + 1;
+            let pair_end = util::find_char(line, position, util::is_space).unwrap_or(line.len());
+            if let Some(number_slice) = parse_pair(&line[position..pair_end], key) {
+                quantity = quantity.plus(number_slice);
+                break;
+            }
+        }
+    }
+
+    quantity.write_to_record(&mut buffers.copy_buffer, &mut buffers.record);
+}
+
+/// Tries to read an IO file (such as `blkio.throttle.io_service_bytes`) and
+/// writes the value of each given operation for each device in the layout as
+/// a separate field to the record, ordered by device and then by operation.
+/// Devices or operations that don't appear in the file result in empty fields.
+/// The original files are in the form of:
+/// ```txt
+/// 8:0 Read 4272128
+/// 8:0 Write 0
+/// 11:0 Read 1073152
+/// 11:0 Write 0
+/// Total 5345280
+/// ```
+pub fn device_io(
+    file: &Option<File>,
+    operations: &[&[u8]],
+    layout: &DeviceLayout,
+    buffers: &mut WorkingBuffers,
+) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let content = &buffers.buffer.b[..buffers.buffer.len];
+    for device in layout.devices() {
+        for operation in operations {
+            let value = find_device_line(content, &device.number, |rest| {
+                parse_category(rest, operation)
+            });
+            buffers.record.push_field(value.unwrap_or(EMPTY_BUFFER));
+        }
+    }
+
+    buffers.buffer.clear();
+}
+
+/// Tries to read a keyed I/O file (such as `io.stat` in cgroup v2) and writes
+/// the value of each given key for each device in the layout as a separate
+/// field to the record, ordered by device and then by key. Devices or keys
+/// that don't appear in the file result in empty fields.
+/// The original files are in the form of:
+/// ```txt
+/// 8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0
+/// 8:0 rbytes=90430464 wbytes=299008000 rios=8950 wios=1252 dbytes=50331648 dios=3021
+/// ```
+pub fn device_keyed_io(
+    file: &Option<File>,
+    keys: &[&[u8]],
+    layout: &DeviceLayout,
+    buffers: &mut WorkingBuffers,
+) {
+    let numbers = layout.devices().map(|device| device.number.as_slice());
+    keyed_lines(file, numbers, keys, buffers);
+}
+
+/// Tries to read a file where each line is a name followed by space-delimited
+/// `key=value` pairs (such as `rdma.current`), and writes the value of each
+/// given key for each given name as a separate field to the record, ordered by
+/// name and then by key. Names or keys that don't appear in the file result in
+/// empty fields.
+/// The original files are in the form of:
+/// ```txt
+/// mlx4_0 hca_handle=2 hca_object=2000
+/// ocrdma1 hca_handle=3 hca_object=max
+/// ```
+pub fn keyed_lines<'a, I>(
+    file: &Option<File>,
+    names: I,
+    keys: &[&[u8]],
+    buffers: &mut WorkingBuffers,
+) where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let content = &buffers.buffer.b[..buffers.buffer.len];
+    for name in names {
+        for key in keys {
+            let value = find_device_line(content, name, |rest| {
+                rest.split(|&b| util::is_space(b))
+                    .find_map(|pair| parse_pair(pair, key))
+            });
+            buffers.record.push_field(value.unwrap_or(EMPTY_BUFFER));
+        }
+    }
+
+    buffers.buffer.clear();
+}
+
+/// Finds the first line for the given device number (appearing at the start of
+/// the line) for which the given function returns a value when applied to the
+/// rest of the line
+fn find_device_line<'a, F>(content: &'a [u8], number: &[u8], f: F) -> Option<&'a [u8]>
+where
+    F: Fn(&'a [u8]) -> Option<&'a [u8]>,
+{
+    content.split(|&b| util::is_newline(b)).find_map(|line| {
+        let space = util::find_char(line, 0, util::is_space)?;
+        match &line[..space] == number {
+            true => f(util::trim_raw(&line[(space + 1)..])),
+            false => None,
+        }
+    })
+}
+
+/// Determines if the slice is a `key=value` pair with the given key, and if it
+/// is, returns the value
+fn parse_pair<'a>(slice: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    if slice.len() <= key.len() || slice[key.len()] != b'=' {
+        return None;
+    }
+
+    match slice.starts_with(key) {
+        true => Some(&slice[(key.len() + 1)..]),
+        false => None,
+    }
+}
+
+/// Tries to read a file consisting of a single line of space-delimited fields
+/// (such as `/proc/<pid>/stat`), writing the fields at the given (ascending)
+/// indices to the record. Fields are counted from just after the last
+/// occurrence of the `after` byte, which allows skipping over leading fields
+/// that may themselves contain spaces (such as the parenthesized command name).
+/// The original files are in the form of:
+/// ```txt
+/// 1234 (nginx: worker) S 1233 1233 1233 0 -1 4194624 2131 0 3 0 43 12 ...
+/// ```
+pub fn fields(file: &Option<File>, after: u8, indices: &[usize], buffers: &mut WorkingBuffers) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let mut success_count = 0;
+    let content = &buffers.buffer.b[..buffers.buffer.len];
+    if let Some(marker) = content.iter().rposition(|&b| b == after) {
+        let mut remaining = indices.iter().peekable();
+        let tokens = content[(marker + 1)..]
+            .split(|&b| util::is_whitespace(b))
+            .filter(|token| !token.is_empty());
+        for (i, token) in tokens.enumerate() {
+            match remaining.peek() {
+                None => break,
+                Some(&&index) if index == i => {
+                    buffers.record.push_field(token);
+                    success_count += 1;
+                    remaining.next();
+                },
+                Some(_) => {},
+            }
+        }
+    }
+
+    // Write empty buffers for remaining positions that weren't parsed successfully
+    for _ in 0..(indices.len() - success_count) {
+        buffers.record.push_field(EMPTY_BUFFER);
+    }
+
+    buffers.buffer.clear();
+}
+
+/// Tries to read a file where each line is a key followed by a colon and then a
+/// value, optionally followed by a unit (such as `/proc/<pid>/status`), writing
+/// one field per given key to the record. The unit is dropped from the value.
+/// The original files are in the form of:
+/// ```txt
+/// VmHWM:       4032 kB
+/// VmRSS:       4032 kB
+/// voluntary_ctxt_switches:    150
+/// ```
+pub fn keyed_values(file: &Option<File>, keys: &[&[u8]], buffers: &mut WorkingBuffers) {
+    if read_to_buffer(file, buffers).is_some() {
+        find_keyed_values(b':', keys, buffers);
+    }
+    push_keyed_values(keys, buffers);
+    buffers.buffer.clear();
+}
+
+/// Writes one field per given key to the record from a file that was already
+/// read into the buffer, where each line is a key followed by the given
+/// separator and then a value (such as `ctxt 31741` in `/proc/stat`). Unlike
+/// reading with a pre-examined layout, keys are found wherever they are in the
+/// file, so lines can come and go between reads. Doesn't clear the buffer
+pub fn keyed_values_in_buffer(separator: u8, keys: &[&[u8]], buffers: &mut WorkingBuffers) {
+    find_keyed_values(separator, keys, buffers);
+    push_keyed_values(keys, buffers);
+}
+
+/// Finds the value of each given key in the buffer, storing them in the slices
+/// buffer in the same order as the keys
+fn find_keyed_values(separator: u8, keys: &[&[u8]], buffers: &mut WorkingBuffers) {
+    let lines = util::ByteLines::new(&buffers.buffer.b);
+    for (line, start) in lines {
+        let split = match line.iter().position(|&b| b == separator) {
+            Some(split) => split,
+            None => continue,
+        };
+        if let Some(idx) = find_index(keys, &line[0..split]) {
+            // Skip the whitespace after the separator and drop everything after the value
+            let value = &line[(split + 1)..];
+            let value_offset = match value.iter().position(|&b| !is_blank(b)) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let value_length = value[value_offset..]
+                .iter()
+                .position(|&b| is_blank(b))
+                .unwrap_or(value.len() - value_offset);
+            buffers.slices[idx] = AnonymousSlice {
+                start:  start + split + 1 + value_offset,
+                length: value_length,
+            }
+        }
+    }
+}
+
+/// Writes the slices found for the given keys to the record, writing empty
+/// fields for keys that weren't found
+fn push_keyed_values(keys: &[&[u8]], buffers: &mut WorkingBuffers) {
+    for i in 0..keys.len() {
+        let slice: &[u8] = match buffers.slices[i].consume(&buffers.buffer.b) {
+            Some(s) => s,
+            None => EMPTY_BUFFER,
+        };
+        buffers.record.push_field(slice);
+    }
+
+    clear_slice_buffer(buffers);
+}
+
+/// Whether the byte is a space or a tab, which both separate keys, values, and
+/// units in `/proc/<pid>` files
+const fn is_blank(c: u8) -> bool { util::is_space(c) || c == b'\t' }
+
+/// Tries to read a table file where each line is a name followed by a colon and
+/// then space-delimited values (such as `/proc/<pid>/net/dev`), writing one
+/// field with the space-delimited names of all lines, and then one field per
+/// given index with the space-delimited values at that index for each line (in
+/// the same order). Lines without a colon (such as column headers) are
+/// skipped.
+/// The original files are in the form of:
+/// ```txt
+/// Inter-|   Receive                            |  Transmit
+///  face |bytes    packets errs drop fifo frame |bytes    packets errs drop ...
+///     lo:    3192      38    0    0    0     0 ...    3192      38    0    0 ...
+///   eth0: 1492302    1143    0    0    0     0 ...   84512     923    0    0 ...
+/// ```
+pub fn table(file: &Option<File>, indices: &[usize], buffers: &mut WorkingBuffers) {
+    table_with(file, split_colon, true, indices, buffers);
+}
+
+/// Tries to read a table file where each line is split into a name and then
+/// space-delimited values using the given function (which skips the line if it
+/// returns None), optionally writing one field with the space-delimited names
+/// of all lines, and then one field per given index with the space-delimited
+/// values at that index for each line (in the same order)
+pub fn table_with(
+    file: &Option<File>,
+    split: SplitLine,
+    names: bool,
+    indices: &[usize],
+    buffers: &mut WorkingBuffers,
+) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+    table_in_buffer(split, names, indices, buffers);
+    buffers.buffer.clear();
+}
+
+/// Writes the fields of a table file that was already read into the buffer, in
+/// the same way as `table_with`. Doesn't clear the buffer, so several tables
+/// can be parsed from different lines of the same file
+pub fn table_in_buffer(
+    split: SplitLine,
+    names: bool,
+    indices: &[usize],
+    buffers: &mut WorkingBuffers,
+) {
+    let WorkingBuffers {
+        buffer,
+        copy_buffer,
+        record,
+        ..
+    } = buffers;
+    let content = &buffer.b[..buffer.len];
+    if names {
+        push_table_column(content, split, None, copy_buffer, record);
+    }
+    for &index in indices {
+        push_table_column(content, split, Some(index), copy_buffer, record);
+    }
+}
+
+/// Function that splits a line of a table file into its name and the rest of
+/// the line containing its values, or returns None if the line should be
+/// skipped
+pub type SplitLine = fn(&[u8]) -> Option<(&[u8], &[u8])>;
+
+/// Splits a line of a table file at the first colon, skipping lines without
+/// one (such as column headers)
+fn split_colon(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let colon = line.iter().position(|&b| b == b':')?;
+    Some((&line[..colon], &line[(colon + 1)..]))
+}
+
+/// Writes a single vector field to the record, containing either the name of
+/// each line in the table (if index is None) or the value at the given index
+/// of each line
+fn push_table_column(
+    content: &[u8],
+    split: SplitLine,
+    index: Option<usize>,
+    copy_buffer: &mut Buffer,
+    record: &mut ByteRecord,
+) {
+    for line in content.split(|&b| util::is_newline(b)) {
+        let (name, values) = match split(line) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let part = match index {
+            None => name,
+            Some(_) => values,
+        };
+        let mut tokens = part
+            .split(|&b| util::is_whitespace(b))
+            .filter(|token| !token.is_empty());
+        let token = match index {
+            None => tokens.next(),
+            Some(i) => tokens.nth(i),
+        };
+
+        if let Some(token) = token {
+            // Separate each entry with a space, dropping entries that don't fit
+            let separator = usize::from(copy_buffer.len != 0);
+            let end = copy_buffer.len + separator + token.len();
+            if end <= copy_buffer.b.len() {
+                if separator != 0 {
+                    copy_buffer.b[copy_buffer.len] = b' ';
+                }
+                copy_buffer.b[(copy_buffer.len + separator)..end].copy_from_slice(token);
+                copy_buffer.len = end;
+            }
+        }
+    }
+
+    record.push_field(copy_buffer.content());
+    copy_buffer.clear();
+}
+
+/// Tries to read an SNMP counters file (such as `/proc/<pid>/net/snmp`), where
+/// each protocol has a line with the names of its counters followed by a line
+/// with their values, writing the value of the given counter as a field to the
+/// record.
+/// The original files are in the form of:
+/// ```txt
+/// Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens ... RetransSegs InErrs
+/// Tcp: 1 200 120000 -1 2045 12 ... 37 0
+/// ```
+pub fn snmp(file: &Option<File>, protocol: &[u8], key: &[u8], buffers: &mut WorkingBuffers) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let content = &buffers.buffer.b[..buffers.buffer.len];
+    let mut lines = content
+        .split(|&b| util::is_newline(b))
+        .filter(|line| parse_category(line, protocol).is_some());
+    let value = lines.next().zip(lines.next()).and_then(|(names, values)| {
+        let index = snmp_tokens(names).position(|name| name == key)?;
+        snmp_tokens(values).nth(index)
+    });
+
+    buffers.record.push_field(value.unwrap_or(EMPTY_BUFFER));
+    buffers.buffer.clear();
+}
+
+/// Splits a line of an SNMP counters file into its tokens, skipping the leading
+/// protocol name
+fn snmp_tokens(line: &[u8]) -> impl Iterator<Item = &[u8]> {
+    line.split(|&b| util::is_whitespace(b))
+        .filter(|token| !token.is_empty())
+        .skip(1)
+}
+
+/// Tries to read a pressure file (such as `cpu.pressure`), where each line is a
+/// category followed by space-delimited `key=value` pairs, writing the value
+/// for each given category and key as a field to the record. Categories that
+/// aren't present (such as `full` in `cpu.pressure` before Linux 5.13) result
+/// in empty fields.
+/// The original files are in the form of:
+/// ```txt
+/// some avg10=3.36 avg60=3.81 avg300=3.24 total=220426338
+/// full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+/// ```
+pub fn pressure(file: &Option<File>, entries: &[(&[u8], &[u8])], buffers: &mut WorkingBuffers) {
+    // Ignore errors: the buffer will just remain empty
+    read_to_buffer(file, buffers);
+
+    let content = &buffers.buffer.b[..buffers.buffer.len];
+    for &(category, key) in entries {
+        let value = content
+            .split(|&b| util::is_newline(b))
+            .find_map(|line| parse_category(line, category))
+            .and_then(|pairs| {
+                pairs
+                    .split(|&b| util::is_whitespace(b))
+                    .find_map(|pair| parse_pair(pair, key))
+            });
+        buffers.record.push_field(value.unwrap_or(EMPTY_BUFFER));
+    }
+
+    buffers.buffer.clear();
+}
@@ -0,0 +1,242 @@
+//! Collection for targets in the unified (v2) cgroup hierarchy, where every
+//! controller exposes its files in the same cgroup directory.
+//! See <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html>
+
+use crate::cli::CollectionOptions;
+use crate::collection::collect::controllers::{Controller, ControllerHandles};
+use crate::collection::collect::devices::{self, DeviceLayout};
+use crate::collection::collect::files::{NetworkFileHandles, PressureFileHandles, ProcFileHandlesV2};
+use crate::collection::collect::plan::{ColumnGroup, ReadPlan};
+use crate::collection::collect::read::StatEntries;
+use crate::collection::collect::{network, psi, read, WorkingBuffers};
+use crate::collection::perf_table::{Column, ColumnType};
+use crate::util::{self, CgroupPath, CgroupVersion};
+use std::collections::BTreeMap;
+
+/// Group of columns in the cgroup v2 logfiles
+type Group = ColumnGroup<CgroupV2Source>;
+
+/// Creates the groups of columns for the cgroup v2 logfiles (in the order of
+/// the output), along with the files that each group is read from
+#[must_use]
+pub fn get_groups(opts: &CollectionOptions) -> Vec<Group> {
+    vec![
+        // pids controller,
+        // see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#pid>
+        Group::new(&["pids.current"], &["pids.current"], |b, s| {
+            read::entry(&s.file_handles.pids_current, b);
+        }),
+        Group::new(&["pids.max"], &["pids.max"], |b, s| {
+            read::entry(&s.file_handles.pids_max, b);
+        }),
+        // cpu controller,
+        // see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#cpu>
+        Group::stat(
+            &["cpu.stat"],
+            StatEntries::new(CPU_STAT_ENTRIES, CPU_STAT_COLUMNS),
+            |b, s| read::with_layout(&s.file_handles.cpu_stat, &s.cpu_layout, b),
+        ),
+        // memory controller,
+        // see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#memory>
+        Group::new(&["memory.current"], &["memory.usage.current"], |b, s| {
+            read::entry(&s.file_handles.memory_current, b);
+        }),
+        Group::new(&["memory.max"], &["memory.limit.hard"], |b, s| {
+            read::entry(&s.file_handles.memory_max, b);
+        }),
+        Group::stat(
+            &["memory.stat"],
+            StatEntries::new(MEMORY_STAT_ENTRIES, MEMORY_STAT_COLUMNS)
+                .with_additional("memory.stat", &opts.memory_stat),
+            |b, s| read::with_layout(&s.file_handles.memory_stat, &s.memory_layout, b),
+        ),
+        // io controller,
+        // see <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#io>
+        Group::new(&["io.stat"], IO_STAT_COLUMNS, |b, s| {
+            read::keyed_io(&s.file_handles.io_stat, IO_STAT_KEYS, b);
+        }),
+        Group::new(network::FILES, network::columns(), |b, s| {
+            network::collect(b, &mut s.network_handles);
+        }),
+        Group::new(&["cpu.pressure"], psi::columns("cpu"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.cpu);
+        }),
+        Group::new(&["memory.pressure"], psi::columns("memory"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.memory);
+        }),
+        Group::new(&["io.pressure"], psi::columns("io"), |b, s| {
+            psi::collect_file(b, &s.pressure_handles.io);
+        }),
+    ]
+}
+
+/// Gets the metadata for all columns in the cgroup v2 logfiles with
+/// non-default types
+#[must_use]
+pub fn get_column_metadata() -> BTreeMap<String, Column> {
+    let mut columns: BTreeMap<String, Column> = BTreeMap::new();
+    // Include metadata on the read (timestamp) column
+    columns.insert(String::from("read"), Column::Scalar {
+        r#type: ColumnType::Epoch19,
+    });
+    network::append_table_metadata(&mut columns);
+    psi::append_table_metadata(&mut columns);
+    columns
+}
+
+/// Open file handles and pre-examined stat file layouts for a single target in
+/// the unified hierarchy
+pub struct CgroupV2Source {
+    file_handles:     ProcFileHandlesV2,
+    cpu_layout:       read::StatFileLayout,
+    memory_layout:    read::StatFileLayout,
+    network_handles:  NetworkFileHandles,
+    pressure_handles: PressureFileHandles,
+    device_layout:    Option<DeviceLayout>,
+    controllers:      ControllerHandles,
+}
+
+impl CgroupV2Source {
+    /// Opens the file handles read by the plan for the given cgroup in the
+    /// unified hierarchy and examines the layout of the variable stat files, as
+    /// well as the set of block devices if collecting per-device statistics and
+    /// the files of any additional controllers
+    #[must_use]
+    pub fn new(
+        cgroup: &CgroupPath,
+        plan: &ReadPlan<Self>,
+        per_device: bool,
+        controllers: &[Controller],
+    ) -> Self {
+        let file_handles = ProcFileHandlesV2::new(&cgroup.path, |file| {
+            plan.opens(file) || (per_device && devices::V2_FILES.contains(&file))
+        });
+        let cpu_layout = plan.layout("cpu.stat", &file_handles.cpu_stat);
+        let memory_layout = plan.layout("memory.stat", &file_handles.memory_stat);
+        let device_layout = match per_device {
+            true => Some(DeviceLayout::cgroup_v2(&file_handles)),
+            false => None,
+        };
+        let network_dir = match plan.opens(network::FILES[0]) {
+            true => util::cgroup_dir(&cgroup.path, cgroup.version),
+            false => None,
+        };
+        let pressure_dir = util::cgroup_dir(&cgroup.path, CgroupVersion::V2);
+        Self {
+            file_handles,
+            cpu_layout,
+            memory_layout,
+            network_handles: NetworkFileHandles::new(network_dir),
+            pressure_handles: PressureFileHandles::new(pressure_dir.as_ref(), |file| {
+                plan.opens(file)
+            }),
+            device_layout,
+            controllers: ControllerHandles::new(cgroup, controllers),
+        }
+    }
+
+    /// Gets the set of block devices that per-device statistics are collected
+    /// for, if enabled
+    #[must_use]
+    pub const fn device_layout(&self) -> Option<&DeviceLayout> { self.device_layout.as_ref() }
+
+    /// Gets the open file handles for the additional controllers
+    #[must_use]
+    pub const fn controllers(&self) -> &ControllerHandles { &self.controllers }
+
+    /// Gets the pre-examined layout of each stat file
+    #[must_use]
+    pub fn layouts(&self) -> Vec<(&'static str, &read::StatFileLayout)> {
+        vec![
+            ("cpu.stat", &self.cpu_layout),
+            ("memory.stat", &self.memory_layout),
+        ]
+    }
+}
+
+/// Original entries in the `cpu.stat` file that map to columns (in the same
+/// order) in the final output. The throttling entries are only present if the
+/// `cpu` controller is enabled for the cgroup
+const CPU_STAT_ENTRIES: &[&[u8]] = &[
+    b"usage_usec",
+    b"user_usec",
+    b"system_usec",
+    b"nr_periods",
+    b"nr_throttled",
+    b"throttled_usec",
+];
+
+/// Columns in the output that each entry in `CPU_STAT_ENTRIES` maps to
+const CPU_STAT_COLUMNS: &[&str] = &[
+    "cpu.usage.total",
+    "cpu.usage.user",
+    "cpu.usage.system",
+    "cpu.throttling.periods",
+    "cpu.throttling.throttled.count",
+    "cpu.throttling.throttled.time",
+];
+
+/// Original entries in the `memory.stat` file that map to columns (in the same
+/// order) in the final output. Unlike cgroup v1, these are always hierarchical
+const MEMORY_STAT_ENTRIES: &[&[u8]] = &[
+    b"anon",
+    b"file",
+    b"kernel_stack",
+    b"sock",
+    b"shmem",
+    b"file_mapped",
+    b"file_dirty",
+    b"file_writeback",
+    b"anon_thp",
+    b"inactive_anon",
+    b"active_anon",
+    b"inactive_file",
+    b"active_file",
+    b"unevictable",
+    b"slab",
+    b"pgfault",
+    b"pgmajfault",
+];
+
+/// Columns in the output that each entry in `MEMORY_STAT_ENTRIES` maps to
+const MEMORY_STAT_COLUMNS: &[&str] = &[
+    "memory.anon",
+    "memory.file",
+    "memory.kernel_stack",
+    "memory.sock",
+    "memory.shmem",
+    "memory.mapped",
+    "memory.dirty",
+    "memory.writeback",
+    "memory.rss.huge",
+    "memory.anon.inactive",
+    "memory.anon.active",
+    "memory.file.inactive",
+    "memory.file.active",
+    "memory.unevictable",
+    "memory.slab",
+    "memory.fault.total",
+    "memory.fault.major",
+];
+
+/// Keys in each line of the `io.stat` file that get aggregated (across all
+/// devices) into columns (in the same order) in the final output
+const IO_STAT_KEYS: &[&[u8]] = &[b"rbytes", b"wbytes", b"rios", b"wios", b"dbytes", b"dios"];
+
+/// Columns in the output that each key in `IO_STAT_KEYS` maps to
+const IO_STAT_COLUMNS: &[&str] = &[
+    "io.service.bytes.read",
+    "io.service.bytes.write",
+    "io.service.ios.read",
+    "io.service.ios.write",
+    "io.discard.bytes",
+    "io.discard.ios",
+];
+
+/// Collects the per-device stats for the given cgroup v2 target, if enabled
+#[inline]
+pub fn collect_devices(buffers: &mut WorkingBuffers, source: &CgroupV2Source) {
+    if let Some(layout) = &source.device_layout {
+        devices::collect_v2(buffers, &source.file_handles, layout);
+    }
+}
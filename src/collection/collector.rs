@@ -1,123 +1,232 @@
-use crate::cli;
-use crate::collection::collect;
-use crate::collection::collect::files::ProcFileHandles;
-use crate::collection::collect::read::StatFileLayout;
-use crate::collection::flush::{FlushLog, FlushLogger};
+use crate::cli::{self, CollectionOptions};
+use crate::collection::collect::controllers::Cpuset;
+use crate::collection::collect::devices::DeviceLayout;
+use crate::collection::collect::files::{PressureFileHandles, ProcessFileHandles};
+use crate::collection::collect::host::HostSource;
+use crate::collection::collect::v2::CgroupV2Source;
+use crate::collection::collect::{self, CgroupV1Source, CollectionSource, Schemas};
+use crate::collection::events::EventWatch;
 use crate::collection::perf_table::TableMetadata;
+use crate::collection::process_table::{ProcessSampler, ProcessTable, ProcessTableWatch};
+use crate::collection::sink::{Sink, SinkHandles, SinkTarget};
 use crate::collection::system_info::SystemInfo;
-use crate::shared::CollectionTarget;
-use crate::util::{self, CgroupDriver, CgroupPath};
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, ErrorKind, Write};
+use crate::shared::{CollectionMethod, CollectionTarget};
+use crate::util::{self, CgroupDriver, CgroupMode, CgroupVersion};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use csv::{Writer, WriterBuilder};
 use failure::Error;
 use serde::Serialize;
 
-/// Contains the file handle for the open stats file as well as the file handles
+/// Contains the open output sinks for the target as well as the file handles
 /// for /proc virtual files used during reading the system stats. `active` is
 /// used during difference resolution to mark inactive collectors for
 /// teardown/removal.
 pub struct Collector {
-    pub writer:        Writer<FlushLogger<File>>,
-    pub file_handles:  ProcFileHandles,
-    pub active:        bool,
-    pub memory_layout: StatFileLayout,
-    pub target:        CollectionTarget,
+    /// Sinks that each record is written to, one for each `--output` kind
+    pub sinks:     Vec<Box<dyn Sink>>,
+    pub source:    CollectionSource,
+    pub schemas:   Arc<Schemas>,
+    /// Registration of the target's memory event notifications, if enabled
+    pub events:    Option<EventWatch>,
+    /// Registration of the target's process table with the sampler, if enabled
+    pub processes: Option<ProcessTableWatch>,
+    pub active:    bool,
+    pub target:    CollectionTarget,
 }
 
 /// Bundles together all information stored in log file headers
 #[derive(Clone, Debug, PartialEq, Serialize)]
 #[serde(rename_all = "PascalCase")]
-struct LogFileHeader<'a> {
-    version:        &'static str,
-    provider:       &'static str,
-    metadata:       &'a Option<serde_yaml::Value>,
-    perf_table:     &'a TableMetadata,
-    system:         SystemInfo,
-    cgroup:         &'a PathBuf,
-    cgroup_driver:  &'a CgroupDriver,
-    polled_at:      u128,
-    initialized_at: u128,
+pub struct LogFileHeader<'a> {
+    pub version:           &'static str,
+    pub provider:          &'static str,
+    pub metadata:          &'a Option<serde_yaml::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter:            Option<&'a str>,
+    pub perf_table:        &'a TableMetadata,
+    pub system:            SystemInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup:            Option<&'a PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_driver:     Option<&'a CgroupDriver>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_version:    Option<&'a CgroupVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid:               Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devices:           Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown_stat_keys: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpuset:            Option<&'a Cpuset>,
+    /// Name of the process table log file of the target, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process_table:     Option<&'a str>,
+    pub cgroup_mode:       CgroupMode,
+    pub polled_at:         u128,
+    pub initialized_at:    u128,
 }
 
 impl Collector {
     /// Creates a new collector at the given log file destination, making all
     /// intermediate directories as necessary. Then, opens up all required
-    /// read and write file handles and writes the file header for the log
-    /// file.
+    /// read file handles and opens each output sink. Cgroup targets also get a
+    /// process table if the process sampler is given
     pub fn create(
         logs_location: &Path,
         target: CollectionTarget,
-        cgroup: &CgroupPath,
-        buffer_capacity: usize,
-        perf_table: &Arc<TableMetadata>,
-        event_log: Option<Arc<Mutex<FlushLog>>>,
+        method: &CollectionMethod,
+        options: &CollectionOptions,
+        schemas: &Arc<Schemas>,
+        handles: &SinkHandles,
+        process_sampler: Option<&Arc<ProcessSampler>>,
     ) -> Result<Self, Error> {
         // Ensure directories exist before creating the collector
         fs::create_dir_all(logs_location)?;
         let path = construct_log_path(&target.id, logs_location)?;
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(path)?;
-        let collector = Self::new(file, target, cgroup, buffer_capacity, perf_table, event_log)?;
+
+        // Create the process table alongside the main log if it's enabled
+        let processes = match (process_sampler, method) {
+            (
+                Some(sampler),
+                CollectionMethod::LinuxCgroups(cgroup) | CollectionMethod::LinuxCgroupsV2(cgroup),
+            ) => {
+                let id = format!("{}_processes", target.id);
+                let log = Path::new(&path).file_name().unwrap_or_default();
+                let table = ProcessTable::create(
+                    Path::new(&construct_log_path(&id, logs_location)?),
+                    &target,
+                    cgroup,
+                    &log.to_string_lossy(),
+                    sampler.interval,
+                    options.compression(),
+                )?;
+                Some(ProcessSampler::add(sampler, &target.id, table))
+            },
+            _ => None,
+        };
+
+        let collector = Self::new(
+            Path::new(&path),
+            target,
+            method,
+            options,
+            schemas,
+            handles,
+            processes,
+        )?;
         Ok(collector)
     }
 
-    /// Collects the current statistics for the given target, writing the CSV
-    /// entries to the writer. Utilizes /proc and cgroups (Linux-only)
-    pub fn collect(
-        &mut self,
-        working_buffers: &mut collect::WorkingBuffers,
-    ) -> Result<(), csv::Error> {
+    /// Collects the current statistics for the given target, writing the
+    /// entries to each sink. Utilizes /proc and cgroups (Linux-only)
+    pub fn collect(&mut self, working_buffers: &mut collect::WorkingBuffers) -> Result<(), Error> {
         collect::run(self, working_buffers)
     }
 
-    /// Initializes a new collector given the destination file and target
-    /// metadata. Writes the file header and then opens up read file handles
-    /// for all of the /proc cgroup virtual files
+    /// Initializes a new collector given the log file destination and target
+    /// metadata. Opens up read file handles for all of the /proc cgroup virtual
+    /// files and then opens each sink with the log header
     fn new(
-        file: File,
+        path: &Path,
         target: CollectionTarget,
-        cgroup: &CgroupPath,
-        buffer_capacity: usize,
-        perf_table: &Arc<TableMetadata>,
-        event_log: Option<Arc<Mutex<FlushLog>>>,
+        method: &CollectionMethod,
+        options: &CollectionOptions,
+        schemas: &Arc<Schemas>,
+        handles: &SinkHandles,
+        processes: Option<ProcessTableWatch>,
     ) -> Result<Self, Error> {
+        let (cgroup, pid, source, perf_table) = match method {
+            CollectionMethod::LinuxCgroups(cgroup) => (
+                Some(cgroup),
+                None,
+                CollectionSource::CgroupV1(CgroupV1Source::new(
+                    cgroup,
+                    &schemas.cgroup_v1.plan,
+                    options.per_device,
+                    &options.controllers,
+                )),
+                &schemas.cgroup_v1.table,
+            ),
+            CollectionMethod::LinuxCgroupsV2(cgroup) => (
+                Some(cgroup),
+                None,
+                CollectionSource::CgroupV2(CgroupV2Source::new(
+                    cgroup,
+                    &schemas.cgroup_v2.plan,
+                    options.per_device,
+                    &options.controllers,
+                )),
+                &schemas.cgroup_v2.table,
+            ),
+            CollectionMethod::Process(pid) => (
+                None,
+                Some(*pid),
+                CollectionSource::Process(ProcessFileHandles::new(*pid, |file| {
+                    schemas.process.plan.opens(file)
+                })),
+                &schemas.process.table,
+            ),
+            CollectionMethod::Host => (
+                None,
+                None,
+                CollectionSource::Host(HostSource::new(&schemas.host.plan)),
+                &schemas.host.table,
+            ),
+            CollectionMethod::HostPressure => (
+                None,
+                None,
+                CollectionSource::HostPressure(PressureFileHandles::host()),
+                &schemas.host_pressure,
+            ),
+        };
+
         let header = LogFileHeader {
             version: cli::VERSION.unwrap_or("unknown"),
             provider: target.provider,
             metadata: &target.metadata,
+            filter: target.filter.as_deref(),
             system: SystemInfo::get(),
-            cgroup: &cgroup.path,
-            cgroup_driver: &cgroup.driver,
+            cgroup: cgroup.map(|c| &c.path),
+            cgroup_driver: cgroup.map(|c| &c.driver),
+            cgroup_version: cgroup.map(|c| &c.version),
+            pid,
+            devices: source.device_layout().map(DeviceLayout::device_map),
+            unknown_stat_keys: source.unknown_stat_keys(),
+            cpuset: source.cpuset(),
+            process_table: processes.as_ref().map(|p| p.file_name.as_str()),
+            cgroup_mode: util::cgroup_mounts().mode,
             polled_at: target.poll_time,
             initialized_at: util::nano_ts(),
             perf_table,
         };
 
-        // Write the YAML header to the file before initializing the CSV writer
-        let header_str = serde_yaml::to_string(&header)?;
-        writeln!(&file, "{}", header_str)?;
-        writeln!(&file, "---")?;
-
-        // Initialize the CSV writer and then write the header row
-        let mut writer = WriterBuilder::new()
-            .buffer_capacity(buffer_capacity)
-            .from_writer(FlushLogger::new(file, target.id.clone(), event_log));
-        writer.write_byte_record(collect::get_header())?;
+        let sink_target = SinkTarget {
+            id: &target.id,
+            name: &target.name,
+            path,
+            header: &header,
+            columns: &source.header(schemas),
+            options,
+            handles,
+        };
+        let sinks = handles
+            .kinds(options)
+            .into_iter()
+            .map(|kind| kind.open(&sink_target))
+            .collect::<Result<Vec<_>, Error>>()?;
 
-        let file_handles = ProcFileHandles::new(&cgroup.path);
-        let memory_layout = collect::examine_memory(&file_handles);
         Ok(Self {
-            writer,
+            sinks,
             active: true,
-            file_handles,
-            memory_layout,
+            source,
+            schemas: Arc::clone(schemas),
+            events: None,
+            processes,
             target,
         })
     }
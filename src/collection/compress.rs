@@ -0,0 +1,147 @@
+//! Streaming compression of log files, enabled with `--compress`. Compressed
+//! files are made up of independently decodable frames (or gzip members), so
+//! their header can be written as its own frame and read without decompressing
+//! the records that follow it. The compressors require the `compress` feature,
+//! without which log files are always written directly.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "compress")]
+use flate2::write::GzEncoder;
+use strum_macros::{EnumString, IntoStaticStr};
+#[cfg(feature = "compress")]
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+/// Compression level given to zstd, where 0 uses its default level
+#[cfg(feature = "compress")]
+const ZSTD_LEVEL: i32 = 0;
+
+/// Streaming compressor that log files can be written through. Has no variants
+/// without the `compress` feature
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Compression {
+    /// Zstandard (<https://facebook.github.io/zstd/>), in `.zst` files
+    #[cfg(feature = "compress")]
+    Zstd,
+    /// gzip, in `.gz` files
+    #[cfg(feature = "compress")]
+    Gzip,
+}
+
+impl Compression {
+    /// Gets the extension that is appended to the names of compressed files
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compress")]
+            Self::Zstd => "zst",
+            #[cfg(feature = "compress")]
+            Self::Gzip => "gz",
+        }
+    }
+}
+
+/// Log file that is written to directly or through a streaming compressor
+pub enum LogFile {
+    Plain(File),
+    #[cfg(feature = "compress")]
+    Zstd(ZstdEncoder<'static, File>),
+    #[cfg(feature = "compress")]
+    Gzip(GzEncoder<File>),
+}
+
+impl LogFile {
+    /// Gets the path of a log file, with the extension of its compression (if
+    /// any) appended to it
+    #[must_use]
+    pub fn path(path: &Path, compression: Option<Compression>) -> PathBuf {
+        match compression {
+            None => path.to_owned(),
+            Some(compression) => {
+                let mut path = OsString::from(path);
+                path.push(".");
+                path.push(compression.extension());
+                PathBuf::from(path)
+            },
+        }
+    }
+
+    /// Opens a log file for appending, first writing the given header. When
+    /// compressed, the header is written as a complete frame of its own,
+    /// followed by a new frame that all later writes are compressed into
+    pub fn open(path: &Path, compression: Option<Compression>, header: &[u8]) -> IoResult<Self> {
+        #[cfg_attr(not(feature = "compress"), allow(unused_mut))]
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(true)
+            .open(Self::path(path, compression))?;
+
+        match compression {
+            None => {
+                file.write_all(header)?;
+                Ok(Self::Plain(file))
+            },
+            #[cfg(feature = "compress")]
+            Some(Compression::Zstd) => {
+                if !header.is_empty() {
+                    let mut encoder = ZstdEncoder::new(file, ZSTD_LEVEL)?;
+                    encoder.write_all(header)?;
+                    file = encoder.finish()?;
+                }
+                Ok(Self::Zstd(ZstdEncoder::new(file, ZSTD_LEVEL)?))
+            },
+            #[cfg(feature = "compress")]
+            Some(Compression::Gzip) => {
+                if !header.is_empty() {
+                    let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                    encoder.write_all(header)?;
+                    file = encoder.finish()?;
+                }
+                Ok(Self::Gzip(GzEncoder::new(
+                    file,
+                    flate2::Compression::default(),
+                )))
+            },
+        }
+    }
+
+    /// Ends the current compressed frame, writing all remaining data and the
+    /// frame footer to the file, without which the file can't be fully
+    /// decoded. Should be called once, after the last write to the file
+    pub fn finish(&mut self) -> IoResult<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            #[cfg(feature = "compress")]
+            Self::Zstd(encoder) => encoder.do_finish(),
+            #[cfg(feature = "compress")]
+            Self::Gzip(encoder) => encoder.try_finish(),
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            #[cfg(feature = "compress")]
+            Self::Zstd(encoder) => encoder.write(buf),
+            #[cfg(feature = "compress")]
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            #[cfg(feature = "compress")]
+            Self::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "compress")]
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
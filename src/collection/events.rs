@@ -0,0 +1,379 @@
+//! Capture of discrete memory events (such as OOM kills and memory pressure
+//! notifications), which are otherwise only visible in the sampled counters
+//! after the fact. Cgroup v1 targets register eventfd-based notifications on
+//! `memory.oom_control` and `memory.pressure_level` through
+//! `cgroup.event_control`, while cgroup v2 targets watch `memory.events` with
+//! inotify. A single background thread waits on all notifications with epoll,
+//! writing each event to the event log as soon as it is received.
+//! See <https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/memory.html#memory-thresholds>
+
+#![allow(clippy::module_name_repetitions)]
+
+use crate::shared::CollectionMethod;
+use crate::util::{self, CgroupPath, CgroupVersion};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Result as IoResult, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+
+/// Maximum number of notifications handled per wake-up of the event thread
+const EPOLL_BATCH: usize = 32;
+
+/// Notifications registered for each cgroup v1 target, as the file in the
+/// memory hierarchy, the arguments written after it to `cgroup.event_control`,
+/// and the name of the resultant event. Only the critical pressure level is
+/// used, since the lower levels are notified on nearly every page reclaim
+const V1_NOTIFICATIONS: &[(&str, &str, &str)] = &[
+    ("memory.oom_control", "", "oom"),
+    ("memory.pressure_level", " critical", "pressure.critical"),
+];
+
+/// Single memory event written to the event log
+#[derive(Debug, Serialize)]
+pub struct MemoryEvent<'a> {
+    timestamp: u128,
+    target_id: &'a str,
+    event:     &'a str,
+    count:     u64,
+}
+
+/// Watches for memory events of all targets, writing each one to the event log
+/// as it is received
+pub struct EventLog {
+    pub path:   PathBuf,
+    epoll:      File,
+    watches:    Mutex<HashMap<u64, Watch>>,
+    next_token: AtomicU64,
+    writer:     Mutex<Writer<File>>,
+    written:    AtomicUsize,
+}
+
+/// Notification source for a single target that is waited on by the event log
+struct Watch {
+    target_id: String,
+    source:    WatchSource,
+}
+
+/// Kernel interface that a watch receives notifications from
+enum WatchSource {
+    /// Eventfd registered through `cgroup.event_control` (cgroup v1). The
+    /// `memory.oom_control` handle is used to tell events apart from the
+    /// notification sent once the cgroup is removed
+    EventFd {
+        eventfd:     File,
+        _target:     File,
+        oom_control: File,
+        event:       &'static str,
+    },
+    /// Inotify instance watching `memory.events` (cgroup v2), along with the
+    /// last seen value of each of its counters
+    Inotify {
+        inotify: File,
+        events:  File,
+        counts:  Vec<(String, u64)>,
+    },
+}
+
+/// Registration of a target's watches with the event log, which removes them
+/// (closing their file descriptors) once dropped
+pub struct EventWatch {
+    log:    Arc<EventLog>,
+    tokens: Vec<u64>,
+}
+
+impl Drop for EventWatch {
+    fn drop(&mut self) {
+        let mut watches = self.log.watches.lock().unwrap();
+        for token in &self.tokens {
+            watches.remove(token);
+        }
+    }
+}
+
+impl EventLog {
+    /// Creates the event log at the given path (writing its header row), and
+    /// starts the background thread that waits on all notifications
+    pub fn new<A: AsRef<Path>>(event_log_path: A) -> IoResult<Arc<Self>> {
+        let path = event_log_path.as_ref().to_path_buf();
+        let epoll = owned(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        // Write the header row up front, so that it is present even if no
+        // events are received
+        let mut writer = WriterBuilder::new().has_headers(false).from_path(&path)?;
+        writer.write_record(["timestamp", "target_id", "event", "count"])?;
+        writer.flush()?;
+
+        let log = Arc::new(Self {
+            path,
+            epoll,
+            watches: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+            writer: Mutex::new(writer),
+            written: AtomicUsize::new(0),
+        });
+        let log_c = Arc::clone(&log);
+        thread::Builder::new()
+            .name(String::from("collect-events"))
+            .spawn(move || log_c.run())?;
+        Ok(log)
+    }
+
+    /// Gets the number of events written to the event log so far
+    #[must_use]
+    pub fn written(&self) -> usize { self.written.load(Ordering::Relaxed) }
+
+    /// Registers the notifications for the given target, depending on its
+    /// collection method. Returns None if the collection method doesn't
+    /// support memory events
+    pub fn watch(
+        log: &Arc<Self>,
+        target_id: &str,
+        method: &CollectionMethod,
+    ) -> IoResult<Option<EventWatch>> {
+        let sources = match method {
+            CollectionMethod::LinuxCgroups(cgroup) => v1_sources(cgroup)?,
+            CollectionMethod::LinuxCgroupsV2(cgroup) => vec![v2_source(cgroup)?],
+            CollectionMethod::Process(_)
+            | CollectionMethod::Host
+            | CollectionMethod::HostPressure => return Ok(None),
+        };
+
+        let mut tokens = Vec::with_capacity(sources.len());
+        let mut watches = log.watches.lock().unwrap();
+        for source in sources {
+            let token = log.next_token.fetch_add(1, Ordering::Relaxed);
+            let fd = match &source {
+                WatchSource::EventFd { eventfd, .. } => eventfd.as_raw_fd(),
+                WatchSource::Inotify { inotify, .. } => inotify.as_raw_fd(),
+            };
+            let mut event = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64:    token,
+            };
+            check(unsafe {
+                libc::epoll_ctl(log.epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event)
+            })?;
+            watches.insert(token, Watch {
+                target_id: String::from(target_id),
+                source,
+            });
+            tokens.push(token);
+        }
+
+        Ok(Some(EventWatch {
+            log: Arc::clone(log),
+            tokens,
+        }))
+    }
+
+    /// Waits on all registered notifications until the process exits
+    fn run(&self) {
+        let mut ready = [libc::epoll_event {
+            events: 0,
+            u64:    0,
+        }; EPOLL_BATCH];
+        loop {
+            let count = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    ready.as_mut_ptr(),
+                    util::remap::<_, i32>(EPOLL_BATCH),
+                    -1,
+                )
+            };
+            if count < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+
+            for event in &ready[..util::remap::<_, usize>(count)] {
+                let token = event.u64;
+                self.handle(token);
+            }
+        }
+    }
+
+    /// Reads the notification with the given token, writing any resultant
+    /// events to the event log. Notifications for watches that have since
+    /// been removed are ignored
+    fn handle(&self, token: u64) {
+        let timestamp = util::nano_ts();
+        let mut watches = self.watches.lock().unwrap();
+        let watch = match watches.get_mut(&token) {
+            Some(watch) => watch,
+            None => return,
+        };
+
+        let events = match &mut watch.source {
+            WatchSource::EventFd {
+                eventfd,
+                oom_control,
+                event,
+                ..
+            } => read_eventfd(eventfd, oom_control, event),
+            WatchSource::Inotify {
+                inotify,
+                events,
+                counts,
+            } => read_inotify(inotify, events, counts),
+        };
+        if events.is_empty() {
+            return;
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        for (event, count) in &events {
+            // Ignore errors: the event is dropped, but watching continues
+            let _ = writer.serialize(MemoryEvent {
+                timestamp,
+                target_id: &watch.target_id,
+                event,
+                count: *count,
+            });
+        }
+        let _ = writer.flush();
+        self.written.fetch_add(events.len(), Ordering::Relaxed);
+    }
+}
+
+/// Registers the eventfd notifications for a cgroup v1 target
+fn v1_sources(cgroup: &CgroupPath) -> IoResult<Vec<WatchSource>> {
+    let dir = util::cgroup_mounts()
+        .controller("memory")
+        .map(|mount| mount.resolve(&cgroup.path))
+        .ok_or_else(|| not_found("memory cgroup hierarchy"))?;
+    let mut control = OpenOptions::new()
+        .write(true)
+        .open(dir.join("cgroup.event_control"))?;
+
+    let mut sources = Vec::with_capacity(V1_NOTIFICATIONS.len());
+    for (file, args, event) in V1_NOTIFICATIONS {
+        let target = File::open(dir.join(file))?;
+        let eventfd = owned(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) })?;
+        let registration = format!("{} {}{}", eventfd.as_raw_fd(), target.as_raw_fd(), args);
+        control.write_all(registration.as_bytes())?;
+        sources.push(WatchSource::EventFd {
+            eventfd,
+            _target: target,
+            oom_control: File::open(dir.join("memory.oom_control"))?,
+            event,
+        });
+    }
+    Ok(sources)
+}
+
+/// Registers the inotify watch on `memory.events` for a cgroup v2 target
+fn v2_source(cgroup: &CgroupPath) -> IoResult<WatchSource> {
+    let path = util::cgroup_dir(&cgroup.path, CgroupVersion::V2)
+        .ok_or_else(|| not_found("unified cgroup hierarchy"))?
+        .join("memory.events");
+    let mut events = File::open(&path)?;
+    let inotify = owned(unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) })?;
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    check(unsafe {
+        libc::inotify_add_watch(inotify.as_raw_fd(), c_path.as_ptr(), libc::IN_MODIFY)
+    })?;
+
+    let counts = read_counts(&mut events).unwrap_or_default();
+    Ok(WatchSource::Inotify {
+        inotify,
+        events,
+        counts,
+    })
+}
+
+/// Reads the counter of a cgroup v1 eventfd, producing a single event unless
+/// the notification was sent because the cgroup was removed
+fn read_eventfd(
+    eventfd: &mut File,
+    oom_control: &mut File,
+    event: &'static str,
+) -> Vec<(String, u64)> {
+    let mut counter = [0_u8; 8];
+    if eventfd.read_exact(&mut counter).is_err() {
+        return Vec::new();
+    }
+
+    // Reads to the files of a removed cgroup fail
+    let mut byte = [0_u8; 1];
+    let alive = matches!(oom_control.read(&mut byte), Ok(1));
+    let _ = oom_control.seek(SeekFrom::Start(0));
+    match alive {
+        true => vec![(String::from(event), u64::from_ne_bytes(counter))],
+        false => Vec::new(),
+    }
+}
+
+/// Drains the inotify instance of a cgroup v2 target and re-reads
+/// `memory.events`, producing an event for each counter that increased (with
+/// the amount it increased by)
+fn read_inotify(
+    inotify: &mut File,
+    events: &mut File,
+    counts: &mut Vec<(String, u64)>,
+) -> Vec<(String, u64)> {
+    let mut buffer = [0_u8; 1024];
+    while matches!(inotify.read(&mut buffer), Ok(len) if len > 0) {}
+
+    let current = match read_counts(events) {
+        Ok(current) => current,
+        Err(_) => return Vec::new(),
+    };
+    let increased = current
+        .iter()
+        .filter_map(|(key, value)| {
+            let previous = counts.iter().find(|(k, _)| k == key).map_or(0, |(_, v)| *v);
+            match *value > previous {
+                true => Some((key.clone(), value - previous)),
+                false => None,
+            }
+        })
+        .collect();
+    *counts = current;
+    increased
+}
+
+/// Reads each `<key> <value>` counter in a flat-keyed cgroup v2 file
+fn read_counts(file: &mut File) -> IoResult<Vec<(String, u64)>> {
+    let mut content = String::new();
+    let result = file.read_to_string(&mut content);
+    let _ = file.seek(SeekFrom::Start(0));
+    result?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((String::from(key), value.trim().parse().ok()?))
+        })
+        .collect())
+}
+
+/// Wraps a file descriptor returned by libc, failing if it is negative
+fn owned(fd: RawFd) -> IoResult<File> {
+    check(fd)?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Converts a negative return value from libc to the last OS error
+fn check(result: i32) -> IoResult<()> {
+    match result < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(()),
+    }
+}
+
+/// Creates an error for a cgroup hierarchy that isn't mounted
+fn not_found(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} is not mounted", what))
+}
@@ -78,6 +78,9 @@ impl<T: Write> FlushLogger<T> {
     pub fn new(writer: T, id: String, log: Option<Arc<Mutex<FlushLog>>>) -> Self {
         Self { log, id, writer }
     }
+
+    /// Gets a mutable reference to the destination writer
+    pub fn get_mut(&mut self) -> &mut T { &mut self.writer }
 }
 
 impl<T: Write> Write for FlushLogger<T> {
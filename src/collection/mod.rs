@@ -1,28 +1,38 @@
 pub mod collect;
 pub mod collector;
+pub mod compress;
+pub mod events;
 pub mod flush;
 mod perf_table;
+pub mod process_table;
+pub mod sink;
 pub mod system_info;
 
 use crate::cli::CollectionOptions;
-use crate::collection::collect::WorkingBuffers;
+use crate::collection::collect::{Schemas, WorkingBuffers};
 use crate::collection::collector::Collector;
+use crate::collection::events::EventLog;
 use crate::collection::flush::FlushLog;
-use crate::collection::perf_table::TableMetadata;
+use crate::collection::process_table::ProcessSampler;
+use crate::collection::sink::prometheus::Exporter;
+use crate::collection::sink::SinkHandles;
 use crate::shared::{CollectionEvent, CollectionMethod, IntervalWorkerContext};
 use crate::shell::Shell;
 use crate::timer::{Stoppable, Timer};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::path::Path;
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
 /// Length of the buffer that contains buffer flush events
 const EVENT_BUFFER_LENGTH: usize = 8 * 1024;
 
+/// Ids of the built-in targets, which are reserved so that targets from
+/// providers (such as the cgroup `/host`) can't replace or stop them
+const RESERVED_IDS: &[&str] = &[collect::host::TARGET_ID, collect::psi::TARGET_ID];
+
 /// Synchronization status struct used to handle termination and buffer flushing
 struct CollectStatus {
     terminating: bool,
@@ -39,10 +49,9 @@ pub fn run(
     rx: &Receiver<CollectionEvent>,
     context: IntervalWorkerContext,
     options: &CollectionOptions,
+    schemas: Schemas,
+    exporter: Option<Arc<Exporter>>,
 ) {
-    let location = &options.directory;
-    let buffer_size = usize::try_from(options.buffer_size.get_bytes()).unwrap();
-
     context.shell.status(
         "Beginning",
         format!(
@@ -54,8 +63,8 @@ pub fn run(
     let (timer, stop_handle) = Timer::new(context.interval, "collect");
     let collectors: CollectorMap = Arc::new(Mutex::new(HashMap::new()));
 
-    // Initialize the table metadata
-    let table_metadata = Arc::new(collect::get_table_metadata());
+    // Share the read plans and table metadata between all collectors
+    let schemas = Arc::new(schemas);
 
     // If we are monitoring events, initialize the event log
     let flush_log = match &options.flush_log {
@@ -66,6 +75,45 @@ pub fn run(
         None => None,
     };
 
+    // Share the flush log and the Prometheus exporter between the sinks of all
+    // targets
+    let sink_handles = SinkHandles {
+        flush_log: flush_log.as_ref().map(|r| Arc::clone(r)),
+        exporter,
+    };
+
+    // If we are capturing memory events, start watching for them
+    let event_log = match &options.event_log {
+        Some(log_path) => match EventLog::new(log_path) {
+            Ok(event_log) => Some(event_log),
+            Err(err) => {
+                context.shell.warn(format!(
+                    "Could not create memory event log at {}: {}",
+                    log_path.display(),
+                    err
+                ));
+                None
+            },
+        },
+        None => None,
+    };
+
+    // If we are writing process tables, start sampling them off the collection
+    // thread
+    let process_sampler = match options.process_table {
+        Some(interval) => match ProcessSampler::new(interval, Arc::clone(&context.shell)) {
+            Ok(sampler) => Some(sampler),
+            Err(err) => {
+                context.shell.warn(format!(
+                    "Could not start the process table sampler: {}",
+                    err
+                ));
+                None
+            },
+        },
+        None => None,
+    };
+
     // Track when the collector is running and when SIGTERM/SIGINT are being handled
     let status_mutex = Arc::new(Mutex::new(CollectStatus {
         terminating: false,
@@ -77,6 +125,7 @@ pub fn run(
     let status_mutex_c = Arc::clone(&status_mutex);
     let shell_c = Arc::clone(&context.shell);
     let flush_log_c = flush_log.as_ref().map(|c| Arc::clone(c));
+    let event_log_c = event_log.as_ref().map(|c| Arc::clone(c));
     let stop_handle_c = stop_handle.clone();
     let mut term_rx = context.term_rx;
     thread::Builder::new()
@@ -103,15 +152,53 @@ pub fn run(
 
                     // The collection thread is yielding to the sleep; flush the buffers now
                     let collectors = collectors_c.lock().unwrap();
-                    flush_buffers(&collectors, &shell_c, flush_log_c);
+                    flush_buffers(&collectors, &shell_c, flush_log_c, event_log_c.as_deref());
                     stop_handle_c.stop();
                 },
             }
         })
         .unwrap();
 
+    // Start the built-in target that writes system-wide statistics alongside the
+    // per-target logs, providing a baseline for what the whole machine was doing
+    {
+        let mut collectors = collectors.lock().unwrap();
+        handle_event(
+            CollectionEvent::Start {
+                target: collect::host::host_target(),
+                method: CollectionMethod::Host,
+            },
+            &mut collectors,
+            options,
+            &schemas,
+            &sink_handles,
+            &event_log,
+            process_sampler.as_ref(),
+            &context.shell,
+        );
+    }
+
+    // Start the built-in target that writes system-wide pressure information
+    // alongside the per-target logs, if it's available
+    if collect::psi::host_available() {
+        let mut collectors = collectors.lock().unwrap();
+        handle_event(
+            CollectionEvent::Start {
+                target: collect::psi::host_target(),
+                method: CollectionMethod::HostPressure,
+            },
+            &mut collectors,
+            options,
+            &schemas,
+            &sink_handles,
+            &event_log,
+            process_sampler.as_ref(),
+            &context.shell,
+        );
+    }
+
     // Re-use working buffers
-    let mut working_buffers = WorkingBuffers::new();
+    let mut working_buffers = WorkingBuffers::new(&schemas);
 
     for _ in timer {
         // Update status
@@ -127,15 +214,16 @@ pub fn run(
         let mut collectors = collectors.lock().unwrap();
 
         // Check to see if update thread has sent any new start/stop events
-        let flush_log_ref = flush_log.as_ref().map(|r| Arc::clone(r));
+        let tick_start = Instant::now();
         for event in rx.try_iter() {
             handle_event(
                 event,
                 &mut collectors,
-                location,
-                buffer_size,
-                &table_metadata,
-                &flush_log_ref,
+                options,
+                &schemas,
+                &sink_handles,
+                &event_log,
+                process_sampler.as_ref(),
                 &context.shell,
             );
         }
@@ -153,6 +241,9 @@ pub fn run(
                 },
             };
         }
+        if let Some(exporter) = &sink_handles.exporter {
+            exporter.record_tick(tick_start.elapsed());
+        }
 
         // Update status
         let mut status = status_mutex.lock().unwrap();
@@ -167,7 +258,12 @@ pub fn run(
             // If termination signaled during collection, then the collection thread
             // needs to tear down the buffers
             let flush_log_ref = flush_log.map(|r| Arc::clone(&r));
-            flush_buffers(&collectors, &context.shell, flush_log_ref);
+            flush_buffers(
+                &collectors,
+                &context.shell,
+                flush_log_ref,
+                event_log.as_deref(),
+            );
             stop_handle.stop();
             break;
         } else {
@@ -184,17 +280,12 @@ fn flush_buffers(
     collectors: &HashMap<String, RefCell<Collector>>,
     shell: &Arc<Shell>,
     flush_log_option: Option<Arc<Mutex<FlushLog>>>,
+    event_log_option: Option<&EventLog>,
 ) {
     shell.status("Stopping", "collecting and flushing buffers");
 
     for (id, c) in collectors.iter() {
-        let mut collector = c.borrow_mut();
-        if let Err(err) = collector.writer.flush() {
-            shell.warn(format!(
-                "Could not flush buffer on termination for target {}: {}",
-                id, err
-            ));
-        }
+        close_collector(id, &mut c.borrow_mut(), shell);
     }
 
     // Write the event log if it's enabled
@@ -218,18 +309,29 @@ fn flush_buffers(
             )),
         }
     }
+
+    // Memory events are written as they are received, so only report them
+    if let Some(event_log) = event_log_option {
+        shell.info(format!(
+            "Wrote {} memory events to {}",
+            event_log.written(),
+            event_log.path.display()
+        ));
+    }
 }
 
 /// Applies the collector update algorithm that finds all inactive target
 /// collectors and tears them down. In addition, it will initialize collectors
 /// for newly monitored targets
+#[allow(clippy::too_many_arguments)]
 fn handle_event(
     event: CollectionEvent,
     collectors: &mut HashMap<String, RefCell<Collector>>,
-    logs_location: &Path,
-    buffer_capacity: usize,
-    table_metadata: &Arc<TableMetadata>,
-    flush_log: &Option<Arc<Mutex<FlushLog>>>,
+    options: &CollectionOptions,
+    schemas: &Arc<Schemas>,
+    sink_handles: &SinkHandles,
+    event_log: &Option<Arc<EventLog>>,
+    process_sampler: Option<&Arc<ProcessSampler>>,
     shell: &Shell,
 ) {
     match event {
@@ -241,33 +343,57 @@ fn handle_event(
                 ))
             });
 
-            match method {
-                CollectionMethod::LinuxCgroups(path) => {
-                    let id = target.id.clone();
-                    let flush_log_c = flush_log.as_ref().map(|r| Arc::clone(r));
-                    match Collector::create(
-                        logs_location,
-                        target,
-                        &path,
-                        buffer_capacity,
-                        &Arc::clone(table_metadata),
-                        flush_log_c,
-                    ) {
-                        Ok(new_collector) => {
-                            collectors.insert(id, RefCell::new(new_collector));
-                        },
-                        Err(err) => {
-                            // Back off until next iteration if the target is still running
-                            shell.error(format!(
-                                "Could not initialize collector for target id {}: {}",
+            let id = target.id.clone();
+            if RESERVED_IDS.contains(&id.as_str())
+                && target.provider != collect::host::PROVIDER_TYPE
+            {
+                shell.error(format!(
+                    "Could not initialize collector for target '{}': its id {} is reserved for a \
+                     built-in target",
+                    target.name, id
+                ));
+                return;
+            }
+
+            match Collector::create(
+                &options.directory,
+                target,
+                &method,
+                options,
+                schemas,
+                sink_handles,
+                process_sampler,
+            ) {
+                Ok(mut new_collector) => {
+                    if let Some(event_log) = event_log {
+                        match EventLog::watch(event_log, &id, &method) {
+                            Ok(watch) => new_collector.events = watch,
+                            Err(err) => shell.warn(format!(
+                                "Could not watch memory events for target id {}: {}",
                                 id, err
-                            ));
-                        },
+                            )),
+                        }
                     }
+                    // Close the previous collector if the target was already collected
+                    if let Some(old) = collectors.insert(id.clone(), RefCell::new(new_collector)) {
+                        close_collector(&id, &mut old.borrow_mut(), shell);
+                    }
+                },
+                Err(err) => {
+                    // Back off until next iteration if the target is still running
+                    shell.error(format!(
+                        "Could not initialize collector for target id {}: {}",
+                        id, err
+                    ));
                 },
             }
         },
         CollectionEvent::Stop(id) => {
+            // Built-in targets are only stopped once collection ends
+            if RESERVED_IDS.contains(&id.as_str()) {
+                return;
+            }
+
             shell.verbose(|sh| {
                 sh.info(format!(
                     "Received stop event for target '{}' from the collection thread",
@@ -279,8 +405,27 @@ fn handle_event(
                 ))
             });
 
-            let collector = collectors.remove(&id);
-            drop(collector);
+            if let Some(collector) = collectors.remove(&id) {
+                close_collector(&id, &mut collector.borrow_mut(), shell);
+            }
         },
     }
 }
+
+/// Closes each sink of the given collector and its process table, once
+/// collection for its target has ended
+fn close_collector(id: &str, collector: &mut Collector, shell: &Shell) {
+    for sink in &mut collector.sinks {
+        if let Err(err) = sink.close() {
+            shell.warn(format!("Could not flush output for target {}: {}", id, err));
+        }
+    }
+    if let Some(table) = &mut collector.processes {
+        if let Err(err) = table.close() {
+            shell.warn(format!(
+                "Could not flush process table for target {}: {}",
+                id, err
+            ));
+        }
+    }
+}
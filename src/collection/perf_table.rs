@@ -8,6 +8,10 @@ use serde::Serialize;
 pub struct TableMetadata {
     pub delimiter: &'static str,
     pub columns:   BTreeMap<String, Column>,
+    /// Metric selectors that determined the columns in the table, if not every
+    /// column was collected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics:   Option<Vec<String>>,
 }
 
 /// Contains the definitions for a single column
@@ -16,8 +20,13 @@ pub struct TableMetadata {
 pub enum Column {
     #[serde(rename_all = "PascalCase")]
     Scalar { r#type: ColumnType },
+    /// Column with a space-delimited entry for each element, where `count` is
+    /// None if the number of elements can vary between rows
     #[serde(rename_all = "PascalCase")]
-    Vector { r#type: ColumnType, count: usize },
+    Vector {
+        r#type: ColumnType,
+        count:  Option<usize>,
+    },
 }
 
 /// Enum representing known variants of a column
@@ -28,4 +37,8 @@ pub enum ColumnType {
     Int,
     /// Nanosecond timestamp
     Epoch19,
+    /// Generic decimal number type
+    Float,
+    /// Generic string type, without any whitespace
+    String,
 }
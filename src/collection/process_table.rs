@@ -0,0 +1,350 @@
+//! Optional per-process breakdown of cgroup targets, enabled with
+//! `--process-table <interval>`. At the given (usually slower) interval, the
+//! processes in the target's cgroup (and in any of its descendants, such as
+//! the container cgroups of a pod) are listed from `cgroup.procs`, and a row is
+//! written for each process to a secondary log file alongside the target's
+//! main log, using the fields of `/proc/<pid>/stat` (compressed the same way as
+//! the main log with `--compress`). Walking the cgroup
+//! subtrees and reading the stat file of every process scales with the number
+//! of processes, so the tables of all targets are sampled by a single
+//! background thread rather than on the collection tick.
+//! See <https://man7.org/linux/man-pages/man5/proc.5.html>
+
+use crate::cli;
+use crate::collection::compress::{Compression, LogFile};
+use crate::collection::perf_table::{Column, ColumnType, TableMetadata};
+use crate::collection::system_info::SystemInfo;
+use crate::shared::CollectionTarget;
+use crate::shell::Shell;
+use crate::util::{self, CgroupPath};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use csv::Writer;
+use failure::Error;
+use serde::Serialize;
+
+/// Columns of the process table, in order
+const COLUMNS: &[&str] = &[
+    "read", "pid", "comm", "utime", "stime", "rss", "threads", "state",
+];
+
+/// Indices of the fields in `/proc/<pid>/stat` that map to the `utime`,
+/// `stime`, `threads`, and `rss` columns, counted from the field after the
+/// command name (the process state, which is field 3 in proc(5))
+const STAT_FIELDS: [usize; 4] = [11, 12, 17, 21];
+
+/// Secondary log of the processes in a single cgroup target, sampled at a
+/// slower interval than the main log
+pub struct ProcessTable {
+    /// Name of the log file, which is included in the header of the main log
+    pub file_name: String,
+    writer:        Writer<LogFile>,
+    /// Directory of the target's cgroup, or None if the hierarchy isn't mounted
+    cgroup_dir:    Option<PathBuf>,
+    /// Buffer re-used for the contents of each `/proc/<pid>/stat` file
+    stat:          String,
+}
+
+/// Process table of a single target that is shared with the sampler thread,
+/// which is taken once the table is closed
+type SharedTable = Arc<Mutex<Option<ProcessTable>>>;
+
+/// Samples the process tables of all targets at the process table interval,
+/// on a background thread that is separate from the collection thread. Tables
+/// are sampled outside of the lock on the registered tables, so registering or
+/// removing a table never waits for a pass to finish
+pub struct ProcessSampler {
+    pub interval: Duration,
+    tables:       Mutex<HashMap<u64, (Arc<str>, SharedTable)>>,
+    next_token:   AtomicU64,
+    shell:        Arc<Shell>,
+}
+
+/// Registration of a target's process table with the sampler, which removes
+/// it once closed or dropped
+pub struct ProcessTableWatch {
+    /// Name of the process table's log file
+    pub file_name: String,
+    sampler:       Arc<ProcessSampler>,
+    token:         u64,
+}
+
+impl Drop for ProcessTableWatch {
+    fn drop(&mut self) { self.sampler.tables.lock().unwrap().remove(&self.token); }
+}
+
+impl ProcessTableWatch {
+    /// Stops sampling the process table, flushing its buffered rows to the
+    /// log file and finishing its compressed frame (if any)
+    pub fn close(&mut self) -> IoResult<()> {
+        let removed = self.sampler.tables.lock().unwrap().remove(&self.token);
+        // Only waits for the sampler if it's currently sampling this table
+        let table = removed.and_then(|(_, table)| table.lock().unwrap().take());
+        match table {
+            Some(table) => table.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ProcessSampler {
+    /// Creates a new sampler, and starts the background thread that samples
+    /// all registered process tables at the given interval
+    pub fn new(interval: Duration, shell: Arc<Shell>) -> IoResult<Arc<Self>> {
+        let sampler = Arc::new(Self {
+            interval,
+            tables: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+            shell,
+        });
+        let sampler_c = Arc::clone(&sampler);
+        thread::Builder::new()
+            .name(String::from("collect-processes"))
+            .spawn(move || sampler_c.run())?;
+        Ok(sampler)
+    }
+
+    /// Registers the process table of the given target, which is first
+    /// sampled on the next pass of the background thread
+    #[must_use]
+    pub fn add(sampler: &Arc<Self>, target_id: &str, table: ProcessTable) -> ProcessTableWatch {
+        let token = sampler.next_token.fetch_add(1, Ordering::Relaxed);
+        let file_name = table.file_name.clone();
+        sampler.tables.lock().unwrap().insert(
+            token,
+            (Arc::from(target_id), Arc::new(Mutex::new(Some(table)))),
+        );
+        ProcessTableWatch {
+            file_name,
+            sampler: Arc::clone(sampler),
+            token,
+        }
+    }
+
+    /// Samples all registered process tables once per interval until the
+    /// process exits
+    fn run(&self) {
+        loop {
+            thread::sleep(self.interval);
+            let tables = self
+                .tables
+                .lock()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            for (target_id, table) in tables {
+                let mut table = table.lock().unwrap();
+                // Skip tables that were closed since the list was taken
+                let result = match table.as_mut() {
+                    Some(table) => table.collect(),
+                    None => continue,
+                };
+                if let Err(err) = result {
+                    self.shell.error(format!(
+                        "Could not write process table for target {}: {}",
+                        target_id, err
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Bundles together all information stored in process table log file headers
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProcessTableHeader<'a> {
+    version:        &'static str,
+    provider:       &'static str,
+    /// Id of the target, which is shared with its main log
+    target_id:      &'a str,
+    /// Name of the main log file of the target
+    log:            &'a str,
+    cgroup:         &'a PathBuf,
+    interval:       String,
+    perf_table:     TableMetadata,
+    system:         SystemInfo,
+    initialized_at: u128,
+}
+
+impl ProcessTable {
+    /// Creates the process table log file at the given path for the given
+    /// cgroup target, writing the file header (which links back to the main log
+    /// with the given file name) and the CSV header row. When compressed, the
+    /// extension of the compression is appended to the path
+    pub fn create(
+        path: &Path,
+        target: &CollectionTarget,
+        cgroup: &CgroupPath,
+        log: &str,
+        interval: Duration,
+        compression: Option<Compression>,
+    ) -> Result<Self, Error> {
+        let header = ProcessTableHeader {
+            version: cli::VERSION.unwrap_or("unknown"),
+            provider: target.provider,
+            target_id: &target.id,
+            log,
+            cgroup: &cgroup.path,
+            interval: humantime::Duration::from(interval).to_string(),
+            perf_table: get_table_metadata(),
+            system: SystemInfo::get(),
+            initialized_at: util::nano_ts(),
+        };
+
+        // Write the YAML header to the file before initializing the CSV writer
+        let header_str = serde_yaml::to_string(&header)?;
+        let header = format!("{}\n---\n", header_str);
+        let file = LogFile::open(path, compression, header.as_bytes())?;
+
+        let mut writer = Writer::from_writer(file);
+        writer.write_record(COLUMNS)?;
+
+        Ok(Self {
+            file_name: file_name(&LogFile::path(path, compression)),
+            writer,
+            cgroup_dir: util::cgroup_dir(&cgroup.path, cgroup.version),
+            stat: String::new(),
+        })
+    }
+
+    /// Writes a row for each process in the cgroup. Processes that exit while
+    /// being read are skipped
+    fn collect(&mut self) -> Result<(), csv::Error> {
+        let read = util::nano_ts().to_string();
+        let mut pids = Vec::new();
+        if let Some(dir) = &self.cgroup_dir {
+            find_processes(dir, &mut pids);
+        }
+
+        let page_size = util::page_size();
+        for pid in pids {
+            self.stat.clear();
+            if read_stat(pid, &mut self.stat).is_err() {
+                continue;
+            }
+            if let Some(stat) = parse_stat(&self.stat) {
+                let rss = stat.rss.parse::<u64>().unwrap_or(0) * page_size;
+                self.writer.write_record([
+                    read.as_str(),
+                    &pid.to_string(),
+                    stat.comm,
+                    stat.utime,
+                    stat.stime,
+                    &rss.to_string(),
+                    stat.threads,
+                    stat.state,
+                ])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the buffered rows to the log file and finishes its compressed
+    /// frame (if any), consuming the process table
+    fn finish(self) -> IoResult<()> {
+        let mut file = self
+            .writer
+            .into_inner()
+            .map_err(|err| io::Error::new(err.error().kind(), err.error().to_string()))?;
+        file.finish()
+    }
+}
+
+/// Gets the metadata for all columns in the process table with non-default
+/// types
+fn get_table_metadata() -> TableMetadata {
+    let mut columns: BTreeMap<String, Column> = BTreeMap::new();
+    columns.insert(String::from("read"), Column::Scalar {
+        r#type: ColumnType::Epoch19,
+    });
+    columns.insert(String::from("comm"), Column::Scalar {
+        r#type: ColumnType::String,
+    });
+    columns.insert(String::from("state"), Column::Scalar {
+        r#type: ColumnType::String,
+    });
+    TableMetadata {
+        delimiter: ",",
+        columns,
+        metrics: None,
+    }
+}
+
+/// Gets the file name of the given path, falling back to the entire path
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Appends the processes in the `cgroup.procs` file of the given cgroup
+/// directory and of each of its descendants to the given list
+fn find_processes(dir: &Path, pids: &mut Vec<u32>) {
+    if let Ok(procs) = fs::read_to_string(dir.join("cgroup.procs")) {
+        pids.extend(
+            procs
+                .lines()
+                .filter_map(|line| line.trim().parse::<u32>().ok()),
+        );
+    }
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            if matches!(entry.file_type(), Ok(t) if t.is_dir()) {
+                find_processes(&entry.path(), pids);
+            }
+        }
+    }
+}
+
+/// Reads the `/proc/<pid>/stat` file of the given process into the buffer
+fn read_stat(pid: u32, buffer: &mut String) -> IoResult<()> {
+    File::open(format!("/proc/{}/stat", pid))?.read_to_string(buffer)?;
+    Ok(())
+}
+
+/// Fields of a single `/proc/<pid>/stat` file that are written to the process
+/// table
+struct StatFields<'a> {
+    comm:    &'a str,
+    state:   &'a str,
+    utime:   &'a str,
+    stime:   &'a str,
+    threads: &'a str,
+    rss:     &'a str,
+}
+
+/// Parses the fields of a `/proc/<pid>/stat` file, which is in the form of:
+/// ```txt
+/// 1234 (nginx: worker) S 1233 1233 1233 0 -1 4194624 2131 0 3 0 43 12 ...
+/// ```
+/// The command name can itself contain spaces and parentheses, so the fields
+/// after it are counted from the last closing parenthesis
+fn parse_stat(stat: &str) -> Option<StatFields<'_>> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let fields = stat
+        .get((close + 1)..)?
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let field = |i: usize| fields.get(i).copied();
+    Some(StatFields {
+        comm:    stat.get((open + 1)..close)?,
+        state:   field(0)?,
+        utime:   field(STAT_FIELDS[0])?,
+        stime:   field(STAT_FIELDS[1])?,
+        threads: field(STAT_FIELDS[2])?,
+        rss:     field(STAT_FIELDS[3])?,
+    })
+}
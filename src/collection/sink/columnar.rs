@@ -0,0 +1,342 @@
+//! Sinks that write each target to its own columnar file in the target
+//! directory, enabled with `--output parquet` (Apache Parquet) or
+//! `--output arrow` (Arrow IPC). Records are buffered in memory as columns
+//! typed after the perf table metadata, and are written as a row group (or
+//! record batch) once they reach `--buffer`. The YAML header that begins CSVY
+//! log files is stored as file-level key/value metadata.
+//! See <https://parquet.apache.org/docs/file-format/> and
+//! <https://arrow.apache.org/docs/format/Columnar.html#ipc-file-format>
+
+use crate::collection::flush::FlushLogger;
+use crate::collection::perf_table::{Column, ColumnType};
+use crate::collection::sink::{Sink, SinkTarget};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::str;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Builder, Int64Builder, ListArray, NullBufferBuilder,
+                   StringBuilder, TimestampNanosecondBuilder};
+use arrow::buffer::OffsetBuffer;
+use arrow::datatypes::{DataType, Field, FieldRef, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use csv::ByteRecord;
+use failure::Error;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+
+/// Key of the file-level metadata entry that contains the YAML header
+pub const HEADER_METADATA_KEY: &str = "radvisor.header";
+
+/// Sink that writes records to an Apache Parquet file, with a row group per
+/// buffer flush
+pub type ParquetSink = ColumnarSink<ArrowWriter<FlushLogger<File>>>;
+
+/// Sink that writes records to an Arrow IPC file, with a record batch per
+/// buffer flush
+pub type ArrowSink = ColumnarSink<FileWriter<BufWriter<FlushLogger<File>>>>;
+
+/// Sink that buffers records as typed columns, writing them as a single batch
+/// to the underlying columnar file writer once they reach `--buffer`
+pub struct ColumnarSink<W: BatchWriter> {
+    schema:   SchemaRef,
+    columns:  Vec<ColumnBuilder>,
+    /// Number of bytes in the buffered records (as they appear in CSV)
+    buffered: usize,
+    capacity: usize,
+    /// Open file writer, which is taken when the sink is closed
+    writer:   Option<W>,
+}
+
+/// Columnar file writer that batches of records are written to
+pub trait BatchWriter: Send + Sized {
+    /// File extension of the written files
+    const EXTENSION: &'static str;
+
+    /// Creates a new writer for the given file, storing the YAML header as
+    /// file-level metadata
+    fn create(
+        file: FlushLogger<File>,
+        schema: SchemaRef,
+        header: String,
+        capacity: usize,
+    ) -> Result<Self, Error>;
+
+    /// Writes a single batch of records
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Error>;
+
+    /// Writes the file footer, after which no more batches can be written
+    fn finish(self) -> Result<(), Error>;
+}
+
+impl BatchWriter for ArrowWriter<FlushLogger<File>> {
+    const EXTENSION: &'static str = "parquet";
+
+    fn create(
+        file: FlushLogger<File>,
+        schema: SchemaRef,
+        header: String,
+        _capacity: usize,
+    ) -> Result<Self, Error> {
+        // Row groups are only ever ended by the sink itself, when its buffer is
+        // flushed
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(usize::MAX)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                String::from(HEADER_METADATA_KEY),
+                header,
+            )]))
+            .build();
+        Ok(Self::try_new(file, schema, Some(properties))?)
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        Self::write(self, batch)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        self.close()?;
+        Ok(())
+    }
+}
+
+impl BatchWriter for FileWriter<BufWriter<FlushLogger<File>>> {
+    const EXTENSION: &'static str = "arrow";
+
+    fn create(
+        file: FlushLogger<File>,
+        schema: SchemaRef,
+        header: String,
+        capacity: usize,
+    ) -> Result<Self, Error> {
+        let mut writer = Self::try_new(BufWriter::with_capacity(capacity, file), &schema)?;
+        writer.write_metadata(HEADER_METADATA_KEY, header);
+        Ok(writer)
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        Self::write(self, batch)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        Self::finish(&mut self)?;
+        Ok(())
+    }
+}
+
+impl<W: BatchWriter> Sink for ColumnarSink<W> {
+    fn open(target: &SinkTarget<'_>) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(target.path.with_extension(W::EXTENSION))?;
+
+        let metadata = &target.header.perf_table.columns;
+        let fields = target
+            .column_names()
+            .into_iter()
+            .map(|name| {
+                let data_type = data_type(metadata.get(&name));
+                Field::new(name, data_type, true)
+            })
+            .collect::<Vec<_>>();
+        let schema = Arc::new(Schema::new(fields));
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| ColumnBuilder::new(field.data_type()))
+            .collect();
+
+        let capacity = usize::try_from(target.options.buffer_size.get_bytes()).unwrap();
+        let writer = W::create(
+            FlushLogger::new(file, target.id.to_owned(), target.handles.flush_log.clone()),
+            Arc::clone(&schema),
+            serde_yaml::to_string(target.header)?,
+            capacity,
+        )?;
+
+        Ok(Self {
+            schema,
+            columns,
+            buffered: 0,
+            capacity,
+            writer: Some(writer),
+        })
+    }
+
+    fn write(&mut self, record: &ByteRecord) -> Result<(), Error> {
+        for (i, column) in self.columns.iter_mut().enumerate() {
+            let value = record
+                .get(i)
+                .and_then(|v| str::from_utf8(v).ok())
+                .filter(|v| !v.is_empty());
+            column.append(value);
+        }
+
+        self.buffered += record.as_slice().len();
+        if self.buffered >= self.capacity {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        self.buffered = 0;
+
+        let arrays = self.columns.iter_mut().map(ColumnBuilder::finish).collect();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), arrays)?;
+        if let Some(writer) = &mut self.writer {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        match self.writer.take() {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Gets the Arrow data type of a column from its metadata, where vector
+/// columns become lists of their element type. Columns without metadata are
+/// integers
+fn data_type(column: Option<&Column>) -> DataType {
+    let scalar = |r#type: &ColumnType| match r#type {
+        ColumnType::Int => DataType::Int64,
+        ColumnType::Epoch19 => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        ColumnType::Float => DataType::Float64,
+        ColumnType::String => DataType::Utf8,
+    };
+    match column {
+        None => DataType::Int64,
+        Some(Column::Scalar { r#type }) => scalar(r#type),
+        Some(Column::Vector { r#type, .. }) => {
+            DataType::List(Arc::new(Field::new("item", scalar(r#type), true)))
+        },
+    }
+}
+
+/// Buffers the values of a single column until they are written as an array
+enum ColumnBuilder {
+    Scalar(ValueBuilder),
+    /// Vector column, where each value is a list of its space-delimited
+    /// entries
+    List {
+        field:   FieldRef,
+        values:  ValueBuilder,
+        lengths: Vec<usize>,
+        nulls:   NullBufferBuilder,
+    },
+}
+
+/// Buffers single values of a scalar type, appending null for values that
+/// can't be parsed (such as `max` in an integer column)
+enum ValueBuilder {
+    Int(Int64Builder),
+    Epoch(TimestampNanosecondBuilder),
+    Float(Float64Builder),
+    String(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::List(field) => Self::List {
+                field:   Arc::clone(field),
+                values:  ValueBuilder::new(field.data_type()),
+                lengths: Vec::new(),
+                nulls:   NullBufferBuilder::new(0),
+            },
+            _ => Self::Scalar(ValueBuilder::new(data_type)),
+        }
+    }
+
+    fn append(&mut self, value: Option<&str>) {
+        match self {
+            Self::Scalar(values) => values.append(value),
+            Self::List {
+                values,
+                lengths,
+                nulls,
+                ..
+            } => match value {
+                Some(value) => {
+                    let mut length = 0;
+                    for entry in value.split_whitespace() {
+                        values.append(Some(entry));
+                        length += 1;
+                    }
+                    lengths.push(length);
+                    nulls.append_non_null();
+                },
+                None => {
+                    lengths.push(0);
+                    nulls.append_null();
+                },
+            },
+        }
+    }
+
+    /// Builds an array of the buffered values, resetting the builder
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Scalar(values) => values.finish(),
+            Self::List {
+                field,
+                values,
+                lengths,
+                nulls,
+            } => Arc::new(ListArray::new(
+                Arc::clone(field),
+                OffsetBuffer::from_lengths(lengths.drain(..)),
+                values.finish(),
+                nulls.finish(),
+            )),
+        }
+    }
+}
+
+impl ValueBuilder {
+    fn new(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Timestamp(..) => Self::Epoch(TimestampNanosecondBuilder::new()),
+            DataType::Float64 => Self::Float(Float64Builder::new()),
+            DataType::Utf8 => Self::String(StringBuilder::new()),
+            _ => Self::Int(Int64Builder::new()),
+        }
+    }
+
+    fn append(&mut self, value: Option<&str>) {
+        match self {
+            Self::Int(builder) => builder.append_option(value.and_then(|v| v.parse().ok())),
+            Self::Epoch(builder) => builder.append_option(value.and_then(|v| v.parse().ok())),
+            Self::Float(builder) => builder.append_option(value.and_then(|v| v.parse().ok())),
+            Self::String(builder) => builder.append_option(value),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Int(builder) => Arc::new(builder.finish()),
+            Self::Epoch(builder) => Arc::new(builder.finish()),
+            Self::Float(builder) => Arc::new(builder.finish()),
+            Self::String(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
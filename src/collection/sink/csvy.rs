@@ -0,0 +1,67 @@
+//! Default sink that writes each target to its own log file in the target
+//! directory, made up of a YAML header followed by CSV records
+//! (see <https://csvy.org/>)
+
+use crate::collection::compress::LogFile;
+use crate::collection::flush::FlushLogger;
+use crate::collection::sink::{Sink, SinkTarget};
+use std::convert::TryFrom;
+
+use csv::{ByteRecord, Writer, WriterBuilder};
+use failure::{format_err, Error};
+
+/// Sink that writes records to a CSVY log file, buffering them in memory (up to
+/// `--buffer`) between flushes
+pub struct CsvySink {
+    /// Open CSV writer, which is taken when the sink is closed
+    writer: Option<Writer<FlushLogger<LogFile>>>,
+}
+
+impl Sink for CsvySink {
+    fn open(target: &SinkTarget<'_>) -> Result<Self, Error> {
+        // Write the YAML header to the file before initializing the CSV writer
+        let header_str = serde_yaml::to_string(target.header)?;
+        let header = format!("{}\n---\n", header_str);
+        let file = LogFile::open(target.path, target.options.compression(), header.as_bytes())?;
+
+        // Initialize the CSV writer and then write the header row
+        let buffer_capacity = usize::try_from(target.options.buffer_size.get_bytes()).unwrap();
+        let mut writer = WriterBuilder::new()
+            .buffer_capacity(buffer_capacity)
+            .from_writer(FlushLogger::new(
+                file,
+                target.id.to_owned(),
+                target.handles.flush_log.clone(),
+            ));
+        writer.write_byte_record(target.columns)?;
+
+        Ok(Self {
+            writer: Some(writer),
+        })
+    }
+
+    fn write(&mut self, record: &ByteRecord) -> Result<(), Error> {
+        if let Some(writer) = &mut self.writer {
+            writer.write_byte_record(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        // Finish the compressed frame (if any) so that the file can be decoded
+        if let Some(writer) = self.writer.take() {
+            let mut file = writer
+                .into_inner()
+                .map_err(|err| format_err!("could not flush log file: {}", err.error()))?;
+            file.get_mut().finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,612 @@
+//! Sink that writes each record as a single line of InfluxDB line protocol,
+//! enabled with `--output influx`. The measurement is the target's name, the
+//! tags identify the target (see `SinkTarget::tags`), the fields are the
+//! columns of the record, and the timestamp is the `read` column. Lines are
+//! written to rotated files in the target directory or streamed to a UDP or
+//! TCP listener (such as Telegraf's `socket_listener`), depending on
+//! `--influx-destination`. Sockets never block the collection thread: lines
+//! that can't be sent right away are dropped (and counted), and the socket is
+//! reconnected with backoff after an error.
+//! See <https://docs.influxdata.com/influxdb/v1.8/write_protocols/line_protocol_reference/>
+
+use crate::collection::collect::plan::READ_COLUMN;
+use crate::collection::compress::{Compression, LogFile};
+use crate::collection::flush::{FlushLog, FlushLogger};
+use crate::collection::perf_table::{Column, ColumnType};
+use crate::collection::sink::{self, Sink, SinkTarget};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::{self, FromStr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use csv::ByteRecord;
+use failure::{format_err, Error};
+
+/// Timeout of connecting to a TCP destination and of sending the remaining
+/// lines once the sink is closed, which bounds how long either can delay the
+/// collection thread
+const SOCKET_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Delay before reconnecting after the first error, which doubles after each
+/// failed attempt (up to `MAX_RECONNECT_DELAY`)
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between attempts to reconnect
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Destination of the line protocol output, given with `--influx-destination`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InfluxDestination {
+    /// Files in the target directory, rotated once they reach
+    /// `--influx-rotate`
+    File,
+    /// Datagrams sent to the given address, one per line
+    Udp(SocketAddr),
+    /// Stream to the given address
+    Tcp(SocketAddr),
+}
+
+impl FromStr for InfluxDestination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_address = |address: &str| {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|err| format!("invalid address '{}': {}", address, err))
+        };
+        match s {
+            "file" => Ok(Self::File),
+            _ => match (s.strip_prefix("udp://"), s.strip_prefix("tcp://")) {
+                (Some(address), _) => Ok(Self::Udp(parse_address(address)?)),
+                (_, Some(address)) => Ok(Self::Tcp(parse_address(address)?)),
+                _ => Err(String::from(
+                    "expected file, udp://<host>:<port>, or tcp://<host>:<port>",
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for InfluxDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Udp(address) => write!(f, "udp://{}", address),
+            Self::Tcp(address) => write!(f, "tcp://{}", address),
+        }
+    }
+}
+
+/// Sink that writes records as InfluxDB line protocol
+pub struct InfluxSink {
+    /// Escaped measurement and tags, which start each line
+    prefix: String,
+    fields: Vec<Field>,
+    /// Buffer re-used for each line
+    line:   String,
+    output: Output,
+}
+
+/// Way that a single column is written as one or more fields
+enum Field {
+    /// Column that contains the timestamp of each line
+    Timestamp,
+    /// Column written as a single field with the given escaped key
+    Scalar { key: String, r#type: ColumnType },
+    /// Vector column written as a field per entry, with keys made up of the
+    /// given escaped key followed by the name of each entry (from the column at
+    /// the given index) or its position if there is no such column
+    Vector {
+        key:    String,
+        r#type: ColumnType,
+        names:  Option<usize>,
+    },
+    /// Column that names the entries of other vector columns, which isn't
+    /// written itself
+    Skip,
+}
+
+/// Open destination of the line protocol output
+enum Output {
+    File(Box<RotatingFile>),
+    Socket(SocketOutput),
+}
+
+/// Socket that lines are sent to without blocking, which is reconnected (after
+/// a delay) once it fails
+struct SocketOutput {
+    protocol:   Protocol,
+    address:    SocketAddr,
+    /// Open socket, or None while waiting to reconnect
+    connection: Option<Connection>,
+    /// Lines that are waiting to be written to the TCP stream, the first of
+    /// which may have been partially written
+    buffer:     Vec<u8>,
+    capacity:   usize,
+    /// Time at or after which the next attempt to connect is made
+    retry_at:   Instant,
+    delay:      Duration,
+    /// Number of lines that couldn't be sent
+    dropped:    u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Protocol {
+    Udp,
+    Tcp,
+}
+
+/// Connected non-blocking socket
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+/// Line protocol file that is rotated to a new file (with an increasing
+/// sequence number) once it reaches the maximum size (before compression)
+struct RotatingFile {
+    writer:      BufWriter<FlushLogger<LogFile>>,
+    /// Path of the first file, without its extension
+    base:        PathBuf,
+    sequence:    usize,
+    written:     u64,
+    max_size:    u64,
+    id:          String,
+    flush_log:   Option<Arc<Mutex<FlushLog>>>,
+    capacity:    usize,
+    compression: Option<Compression>,
+}
+
+impl Sink for InfluxSink {
+    fn open(target: &SinkTarget<'_>) -> Result<Self, Error> {
+        let output = match target.options.influx_destination {
+            InfluxDestination::File => Output::File(Box::new(RotatingFile::open(target)?)),
+            InfluxDestination::Udp(address) => Output::Socket(SocketOutput::open(
+                Protocol::Udp,
+                address,
+                buffer_capacity(target),
+            )),
+            InfluxDestination::Tcp(address) => Output::Socket(SocketOutput::open(
+                Protocol::Tcp,
+                address,
+                buffer_capacity(target),
+            )),
+        };
+
+        let mut prefix = escape(target.name, &[',', ' ']);
+        for (key, value) in target.tags() {
+            // Tags can't have empty values
+            if !value.is_empty() {
+                prefix.push(',');
+                prefix.push_str(&escape(&key, &[',', '=', ' ']));
+                prefix.push('=');
+                prefix.push_str(&escape(&value, &[',', '=', ' ']));
+            }
+        }
+
+        let names = target.column_names();
+        let metadata = &target.header.perf_table.columns;
+        let fields = names
+            .iter()
+            .map(|name| {
+                let key = escape(name, &[',', '=', ' ']);
+                match metadata.get(name) {
+                    _ if name == READ_COLUMN => Field::Timestamp,
+                    None => Field::Scalar {
+                        key,
+                        r#type: ColumnType::Int,
+                    },
+                    Some(Column::Scalar { r#type }) => Field::Scalar {
+                        key,
+                        r#type: r#type.clone(),
+                    },
+                    Some(Column::Vector {
+                        r#type: ColumnType::String,
+                        ..
+                    }) => Field::Skip,
+                    Some(Column::Vector { r#type, .. }) => Field::Vector {
+                        key,
+                        r#type: r#type.clone(),
+                        names: sink::names_column(name, &names, metadata),
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            prefix,
+            fields,
+            line: String::new(),
+            output,
+        })
+    }
+
+    fn write(&mut self, record: &ByteRecord) -> Result<(), Error> {
+        self.line.clear();
+        self.line.push_str(&self.prefix);
+        let mut separator = ' ';
+        let mut timestamp = None;
+        for (i, field) in self.fields.iter().enumerate() {
+            let value = match record.get(i).and_then(|v| str::from_utf8(v).ok()) {
+                Some(value) if !value.is_empty() => value,
+                _ => continue,
+            };
+            match field {
+                Field::Timestamp => timestamp = Some(value),
+                Field::Scalar { key, r#type } => {
+                    push_field(&mut self.line, &mut separator, key, None, r#type, value);
+                },
+                Field::Vector { key, r#type, names } => {
+                    let mut names = names
+                        .and_then(|n| record.get(n))
+                        .and_then(|n| str::from_utf8(n).ok())
+                        .map(str::split_whitespace);
+                    for (j, entry) in value.split_whitespace().enumerate() {
+                        let index = j.to_string();
+                        let name = names.as_mut().and_then(Iterator::next).unwrap_or(&index);
+                        let name = escape(name, &[',', '=', ' ']);
+                        push_field(
+                            &mut self.line,
+                            &mut separator,
+                            key,
+                            Some(&name),
+                            r#type,
+                            entry,
+                        );
+                    }
+                },
+                Field::Skip => {},
+            }
+        }
+
+        // Lines need at least one field
+        if separator == ' ' {
+            return Ok(());
+        }
+        if let Some(timestamp) = timestamp {
+            self.line.push(' ');
+            self.line.push_str(timestamp);
+        }
+        self.line.push('\n');
+
+        match &mut self.output {
+            Output::File(file) => file.write_line(self.line.as_bytes())?,
+            Output::Socket(socket) => socket.write_line(self.line.as_bytes()),
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.output {
+            Output::File(file) => file.writer.flush()?,
+            Output::Socket(socket) => {
+                socket.connect();
+                socket.send_buffer();
+            },
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        match &mut self.output {
+            Output::File(file) => file.finish()?,
+            Output::Socket(socket) => socket.close()?,
+        }
+        Ok(())
+    }
+}
+
+impl SocketOutput {
+    /// Creates the output for the given destination, connecting to it right
+    /// away. Failing to connect isn't an error, since the destination (such as
+    /// Telegraf) may only start listening later
+    fn open(protocol: Protocol, address: SocketAddr, capacity: usize) -> Self {
+        let mut output = Self {
+            protocol,
+            address,
+            connection: None,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            retry_at: Instant::now(),
+            delay: RECONNECT_DELAY,
+            dropped: 0,
+        };
+        output.connect();
+        output
+    }
+
+    /// Sends a single line, or drops it if the socket is waiting to reconnect
+    /// or can't accept it without blocking. Lines sent over TCP are buffered
+    /// (up to `--buffer`) before being written
+    fn write_line(&mut self, line: &[u8]) {
+        self.connect();
+        match &self.connection {
+            None => self.dropped += 1,
+            Some(Connection::Udp(socket)) => match socket.send(line) {
+                Ok(_) => {},
+                // Refused datagrams are reported on later sends, such as while the
+                // listener restarts, but the socket can still be used
+                Err(err)
+                    if err.kind() == ErrorKind::WouldBlock
+                        || err.kind() == ErrorKind::ConnectionRefused =>
+                {
+                    self.dropped += 1;
+                },
+                Err(_) => {
+                    self.dropped += 1;
+                    self.disconnect();
+                },
+            },
+            Some(Connection::Tcp(_)) => {
+                if self.buffer.len() + line.len() > self.capacity {
+                    self.send_buffer();
+                }
+
+                // Drop the line if the peer isn't reading the stream fast enough
+                let full =
+                    !self.buffer.is_empty() && self.buffer.len() + line.len() > self.capacity;
+                if self.connection.is_none() || full {
+                    self.dropped += 1;
+                } else {
+                    self.buffer.extend_from_slice(line);
+                }
+            },
+        }
+    }
+
+    /// Writes as many of the buffered lines to the TCP stream as it accepts
+    /// without blocking, disconnecting if the stream fails
+    fn send_buffer(&mut self) {
+        let stream = match &mut self.connection {
+            Some(Connection::Tcp(stream)) => stream,
+            _ => return,
+        };
+
+        let mut sent = 0;
+        let mut failed = false;
+        while sent < self.buffer.len() {
+            match stream.write(&self.buffer[sent..]) {
+                Ok(0) => failed = true,
+                Ok(written) => sent += written,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => failed = true,
+            }
+            if failed {
+                break;
+            }
+        }
+
+        self.buffer.drain(..sent);
+        if failed {
+            self.disconnect();
+        }
+    }
+
+    /// Connects to the destination if the socket isn't open and the delay
+    /// since the last failure has elapsed, doubling the delay if it fails
+    fn connect(&mut self) {
+        if self.connection.is_some() || Instant::now() < self.retry_at {
+            return;
+        }
+
+        match connect(self.protocol, self.address) {
+            Ok(connection) => {
+                self.connection = Some(connection);
+                self.delay = RECONNECT_DELAY;
+            },
+            Err(_) => self.back_off(),
+        }
+    }
+
+    /// Closes the socket after it failed, dropping any buffered lines, and
+    /// waits before reconnecting
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.dropped += count_lines(&self.buffer);
+        self.buffer.clear();
+        self.back_off();
+    }
+
+    fn back_off(&mut self) {
+        self.retry_at = Instant::now() + self.delay;
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+
+    /// Sends the remaining buffered lines (waiting up to the socket timeout),
+    /// and reports the number of lines that were dropped
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(Connection::Tcp(stream)) = &mut self.connection {
+            let buffer = &self.buffer;
+            let sent = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(Some(SOCKET_TIMEOUT)))
+                .and_then(|_| stream.write_all(buffer));
+            if sent.is_ok() {
+                self.buffer.clear();
+            }
+        }
+        self.dropped += count_lines(&self.buffer);
+        self.buffer.clear();
+        self.connection = None;
+
+        match self.dropped {
+            0 => Ok(()),
+            dropped => Err(format_err!(
+                "dropped {} lines that could not be sent to {}",
+                dropped,
+                self.destination()
+            )),
+        }
+    }
+
+    fn destination(&self) -> InfluxDestination {
+        match self.protocol {
+            Protocol::Udp => InfluxDestination::Udp(self.address),
+            Protocol::Tcp => InfluxDestination::Tcp(self.address),
+        }
+    }
+}
+
+/// Counts the lines in the given buffer of complete lines, where the first line
+/// may have been partially written
+fn count_lines(buffer: &[u8]) -> u64 { buffer.split(|&b| b == b'\n').skip(1).count() as u64 }
+
+/// Opens a non-blocking socket connected to the given address
+fn connect(protocol: Protocol, address: SocketAddr) -> io::Result<Connection> {
+    match protocol {
+        Protocol::Udp => {
+            let bind = match address {
+                SocketAddr::V4(_) => "0.0.0.0:0",
+                SocketAddr::V6(_) => "[::]:0",
+            };
+            let socket = UdpSocket::bind(bind)?;
+            socket.connect(address)?;
+            socket.set_nonblocking(true)?;
+            Ok(Connection::Udp(socket))
+        },
+        Protocol::Tcp => {
+            let stream = TcpStream::connect_timeout(&address, SOCKET_TIMEOUT)?;
+            stream.set_nonblocking(true)?;
+            Ok(Connection::Tcp(stream))
+        },
+    }
+}
+
+impl RotatingFile {
+    /// Opens the first line protocol file of the given target, named after its
+    /// log file (`{id}_{timestamp}.lp`)
+    fn open(target: &SinkTarget<'_>) -> Result<Self, io::Error> {
+        let base = target.path.with_extension("");
+        let capacity = buffer_capacity(target);
+        let flush_log = target.handles.flush_log.clone();
+        let compression = target.options.compression();
+        Ok(Self {
+            writer: open_file(&base, 0, target.id, &flush_log, capacity, compression)?,
+            base,
+            sequence: 0,
+            written: 0,
+            max_size: u64::try_from(target.options.influx_rotate.get_bytes()).unwrap_or(u64::MAX),
+            id: target.id.to_owned(),
+            flush_log,
+            capacity,
+            compression,
+        })
+    }
+
+    /// Writes a single line, first rotating to the next file if the line
+    /// would make the current one exceed the maximum size
+    fn write_line(&mut self, line: &[u8]) -> Result<(), io::Error> {
+        let length = line.len() as u64;
+        if self.written > 0 && self.written + length > self.max_size {
+            self.finish()?;
+            self.sequence += 1;
+            self.writer = open_file(
+                &self.base,
+                self.sequence,
+                &self.id,
+                &self.flush_log,
+                self.capacity,
+                self.compression,
+            )?;
+            self.written = 0;
+        }
+        self.writer.write_all(line)?;
+        self.written += length;
+        Ok(())
+    }
+
+    /// Flushes the current file, finishing its compressed frame (if any) so
+    /// that it can be decoded
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()?;
+        self.writer.get_mut().get_mut().finish()
+    }
+}
+
+/// Opens the line protocol file with the given sequence number, where the first
+/// file has no sequence number in its name, writing through the given
+/// compressor (if any)
+fn open_file(
+    base: &Path,
+    sequence: usize,
+    id: &str,
+    flush_log: &Option<Arc<Mutex<FlushLog>>>,
+    capacity: usize,
+    compression: Option<Compression>,
+) -> Result<BufWriter<FlushLogger<LogFile>>, io::Error> {
+    let mut path = base.as_os_str().to_owned();
+    if sequence > 0 {
+        path.push(format!(".{}", sequence));
+    }
+    path.push(".lp");
+    let file = LogFile::open(Path::new(&path), compression, &[])?;
+    Ok(BufWriter::with_capacity(
+        capacity,
+        FlushLogger::new(file, id.to_owned(), flush_log.clone()),
+    ))
+}
+
+/// Gets the size of the buffer that lines are written to between flushes
+fn buffer_capacity(target: &SinkTarget<'_>) -> usize {
+    usize::try_from(target.options.buffer_size.get_bytes()).unwrap()
+}
+
+/// Appends a single field to the line, formatting its value according to the
+/// type of its column. Integers get the `i` suffix and strings are quoted,
+/// while integer values that aren't numbers (such as `max`) are skipped
+fn push_field(
+    line: &mut String,
+    separator: &mut char,
+    key: &str,
+    entry: Option<&str>,
+    r#type: &ColumnType,
+    value: &str,
+) {
+    let valid = match r#type {
+        ColumnType::Int | ColumnType::Epoch19 => value.parse::<i64>().is_ok(),
+        ColumnType::Float => value.parse::<f64>().is_ok(),
+        ColumnType::String => true,
+    };
+    if !valid {
+        return;
+    }
+
+    line.push(*separator);
+    *separator = ',';
+    line.push_str(key);
+    if let Some(entry) = entry {
+        line.push('.');
+        line.push_str(entry);
+    }
+    line.push('=');
+    match r#type {
+        ColumnType::Int | ColumnType::Epoch19 => {
+            line.push_str(value);
+            line.push('i');
+        },
+        ColumnType::Float => line.push_str(value),
+        ColumnType::String => {
+            line.push('"');
+            line.push_str(&escape(value, &['"']));
+            line.push('"');
+        },
+    }
+}
+
+/// Escapes the given characters (and backslashes) with a backslash
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
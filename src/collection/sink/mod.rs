@@ -0,0 +1,209 @@
+//! Output sinks that collected records are written to. Each collector opens one
+//! sink of each kind given with `--output` when collection for its target
+//! starts, and then writes every record to all of them, so several output
+//! formats can be produced from the same collection run.
+
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod csvy;
+pub mod influx;
+pub mod prometheus;
+
+use crate::cli::CollectionOptions;
+use crate::collection::collector::LogFileHeader;
+use crate::collection::flush::FlushLog;
+use crate::collection::perf_table::{Column, ColumnType};
+#[cfg(feature = "columnar")]
+use crate::collection::sink::columnar::{ArrowSink, ParquetSink};
+use crate::collection::sink::csvy::CsvySink;
+use crate::collection::sink::influx::InfluxSink;
+use crate::collection::sink::prometheus::{Exporter, PrometheusSink};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use csv::ByteRecord;
+use failure::Error;
+use strum_macros::{EnumString, IntoStaticStr};
+
+/// Kind of output sink that can be chosen with `--output`
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum SinkKind {
+    /// CSV log file per target, preceded by a YAML header
+    Csvy,
+    /// InfluxDB line protocol, written to rotated files or streamed to a UDP or
+    /// TCP listener
+    Influx,
+    /// Apache Parquet file per target, with a row group per buffer flush
+    #[cfg(feature = "columnar")]
+    Parquet,
+    /// Arrow IPC file per target, with a record batch per buffer flush
+    #[cfg(feature = "columnar")]
+    Arrow,
+    /// Latest record of each target, served by the exporter started with
+    /// `--prometheus-listen` rather than given with `--output`
+    #[strum(disabled)]
+    Prometheus,
+}
+
+impl SinkKind {
+    /// Opens a sink of this kind for the given target
+    pub fn open(self, target: &SinkTarget<'_>) -> Result<Box<dyn Sink>, Error> {
+        match self {
+            Self::Csvy => Ok(Box::new(CsvySink::open(target)?)),
+            Self::Influx => Ok(Box::new(InfluxSink::open(target)?)),
+            #[cfg(feature = "columnar")]
+            Self::Parquet => Ok(Box::new(ParquetSink::open(target)?)),
+            #[cfg(feature = "columnar")]
+            Self::Arrow => Ok(Box::new(ArrowSink::open(target)?)),
+            Self::Prometheus => Ok(Box::new(PrometheusSink::open(target)?)),
+        }
+    }
+}
+
+/// Handles shared by the sinks of all targets
+#[derive(Clone, Default)]
+pub struct SinkHandles {
+    /// Buffer flush event log, if enabled
+    pub flush_log: Option<Arc<Mutex<FlushLog>>>,
+    /// Prometheus exporter, if enabled
+    pub exporter:  Option<Arc<Exporter>>,
+}
+
+impl SinkHandles {
+    /// Gets the kinds of sinks to open for each target: those given with
+    /// `--output` (at most once each), followed by the Prometheus sink if the
+    /// exporter is running
+    #[must_use]
+    pub fn kinds(&self, options: &CollectionOptions) -> Vec<SinkKind> {
+        let mut kinds: Vec<SinkKind> = Vec::new();
+        for &kind in &options.outputs {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        if self.exporter.is_some() {
+            kinds.push(SinkKind::Prometheus);
+        }
+        kinds
+    }
+}
+
+/// Information about a single target that is used to open each of its sinks
+pub struct SinkTarget<'a> {
+    /// Id of the target, which is unique among all targets
+    pub id:      &'a str,
+    /// Display name of the target
+    pub name:    &'a str,
+    /// Path of the target's log file (`{id}_{timestamp}.log` in the target
+    /// directory), which sinks that write a file per target are named after
+    pub path:    &'a Path,
+    /// Header of the target's log, which includes the target metadata and the
+    /// perf table metadata of each column
+    pub header:  &'a LogFileHeader<'a>,
+    /// Header row with the name of each column in the records
+    pub columns: &'a ByteRecord,
+    pub options: &'a CollectionOptions,
+    pub handles: &'a SinkHandles,
+}
+
+impl SinkTarget<'_> {
+    /// Gets the name of each column in the records
+    #[must_use]
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|c| String::from_utf8_lossy(c).into_owned())
+            .collect()
+    }
+
+    /// Gets the tags that identify the target in outputs without a header: its
+    /// provider, id, and name, along with its Kubernetes namespace and pod and
+    /// its container labels (as `label_<key>`) if they are in its metadata
+    #[must_use]
+    pub fn tags(&self) -> Vec<(String, String)> {
+        let mut tags = vec![
+            (String::from("provider"), self.header.provider.to_owned()),
+            (String::from("id"), self.id.to_owned()),
+            (String::from("name"), self.name.to_owned()),
+        ];
+
+        if let Some(metadata) = self.header.metadata {
+            // Kubernetes containers include the metadata of their pod
+            let pod = metadata.get("Pod").unwrap_or(metadata);
+            if self.header.provider == "kubernetes" {
+                if let Some(namespace) = pod.get("Namespace").and_then(|v| v.as_str()) {
+                    tags.push((String::from("namespace"), namespace.to_owned()));
+                }
+                if let Some(name) = pod.get("Name").and_then(|v| v.as_str()) {
+                    tags.push((String::from("pod"), name.to_owned()));
+                }
+            }
+            if let Some(mapping) = pod.get("Labels").and_then(|v| v.as_mapping()) {
+                for (key, value) in mapping {
+                    if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+                        tags.push((format!("label_{}", key), value.to_owned()));
+                    }
+                }
+            }
+        }
+
+        tags
+    }
+}
+
+/// Finds the column that names the entries of the vector column with the given
+/// name, which is the string vector column that shares the longest prefix with
+/// it (such as `network.interfaces` for `network.rx.bytes`)
+#[must_use]
+pub fn names_column(
+    name: &str,
+    names: &[String],
+    metadata: &BTreeMap<String, Column>,
+) -> Option<usize> {
+    names
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| {
+            matches!(
+                metadata.get(n.as_str()),
+                Some(Column::Vector {
+                    r#type: ColumnType::String,
+                    ..
+                })
+            )
+        })
+        .map(|(i, n)| (i, shared_prefix(name, n)))
+        .filter(|(_, shared)| *shared > 0)
+        .max_by_key(|(_, shared)| *shared)
+        .map(|(i, _)| i)
+}
+
+/// Gets the number of `.`-delimited segments shared at the start of the two
+/// column names
+fn shared_prefix(a: &str, b: &str) -> usize {
+    a.split('.')
+        .zip(b.split('.'))
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Destination that the records of a single target are written to
+pub trait Sink: Send {
+    /// Opens the sink for the given target, writing the log header if the
+    /// output format has one
+    fn open(target: &SinkTarget<'_>) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Writes a single record, which may be buffered until the next flush
+    fn write(&mut self, record: &ByteRecord) -> Result<(), Error>;
+
+    /// Flushes any buffered records to the destination
+    fn flush(&mut self) -> Result<(), Error>;
+
+    /// Flushes and then finalizes the output once collection for the target
+    /// ends (either because it stopped or because rAdvisor is exiting)
+    fn close(&mut self) -> Result<(), Error> { self.flush() }
+}
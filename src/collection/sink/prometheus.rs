@@ -0,0 +1,510 @@
+//! Optional Prometheus exporter, enabled with `--prometheus-listen <addr>`.
+//! Each target gets a sink that keeps the most recent record it was given, and
+//! a separate server thread renders the latest record of every active target
+//! in the OpenMetrics text format whenever `/metrics` is scraped, alongside
+//! metrics about rAdvisor itself. Sinks only ever try to lock the latest
+//! record, so a scrape in progress never blocks collection; if the lock is
+//! held, the record is skipped and the next one is kept instead.
+//! See <https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md>
+
+use crate::cli;
+use crate::collection::collect::plan;
+use crate::collection::perf_table::{Column, ColumnType};
+use crate::collection::sink::{self, Sink, SinkTarget};
+use crate::util;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use csv::ByteRecord;
+use failure::{format_err, Error};
+
+/// Prefix of every exported metric
+const PREFIX: &str = "radvisor_";
+
+/// Selectors (see `--metrics`) of the columns that are exported as counters,
+/// since they only ever increase. All other numeric columns are exported as
+/// gauges
+const COUNTER_SELECTORS: &[&str] = &[
+    "cpu.usage",
+    "cpu.stat",
+    "cpu.throttling",
+    "cpu.time",
+    "cpu.percpu.time",
+    "memory.failcnt",
+    "memory.paged",
+    "memory.fault",
+    "blkio.time",
+    "blkio.sectors",
+    "blkio.service",
+    "blkio.wait",
+    "blkio.merged",
+    "blkio.throttle",
+    "blkio.bfq",
+    "blkio.device",
+    "io.service",
+    "io.discard",
+    "io.chars",
+    "io.syscalls",
+    "io.cancelled",
+    "io.device",
+    "network.rx",
+    "network.tx",
+    "network.tcp",
+    "process.switches",
+    "system.switches",
+    "system.forks",
+    "vm",
+    "disk.ios",
+    "disk.sectors",
+    "disk.time",
+];
+
+/// Suffixes of the columns that are exported as counters, for the columns that
+/// can't be selected by a prefix (such as `cpu.pressure.some.total`)
+const COUNTER_SUFFIXES: &[&str] = &[".failcnt", ".some.total", ".full.total"];
+
+/// Content type of the OpenMetrics text format
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// How long to wait on a scraper to send its request or receive the response
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metrics server that exports the latest record of each active target
+pub struct Exporter {
+    pub address:     SocketAddr,
+    targets:         Mutex<HashMap<String, Arc<ExportedTarget>>>,
+    started_at:      u128,
+    records:         AtomicU64,
+    skipped:         AtomicU64,
+    scrapes:         AtomicU64,
+    ticks:           AtomicU64,
+    /// Duration of the last collection tick, in nanoseconds
+    last_tick_nanos: AtomicU64,
+}
+
+/// Exported state of a single target, shared between its sink and the server
+struct ExportedTarget {
+    /// Pre-formatted labels shared by every sample of the target
+    labels:  String,
+    columns: Vec<ExportedColumn>,
+    /// Most recent record written by the target's sink
+    latest:  Mutex<ByteRecord>,
+}
+
+/// Way that a single column of a target is exported
+struct ExportedColumn {
+    /// Name of the metric family that the column belongs to
+    family: String,
+    kind:   MetricKind,
+    value:  ValueKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+impl MetricKind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        }
+    }
+}
+
+/// Way that the value of a single column is read from a record
+#[derive(Clone, Debug, PartialEq)]
+enum ValueKind {
+    /// Single number
+    Scalar,
+    /// Nanosecond timestamp, exported in seconds
+    Timestamp,
+    /// Space-delimited entry for each element (such as each CPU), which are
+    /// labelled with the given label using the names in the column at the
+    /// given index, or using their position if there is no such column
+    Vector { label: String, names: Option<usize> },
+    /// Non-numeric column, which isn't exported
+    Skip,
+}
+
+impl Exporter {
+    /// Binds the metrics server to the given address and starts serving scrapes
+    /// on a separate thread
+    pub fn listen(address: SocketAddr) -> Result<Arc<Self>, io::Error> {
+        let listener = TcpListener::bind(address)?;
+        let exporter = Arc::new(Self {
+            address:         listener.local_addr()?,
+            targets:         Mutex::new(HashMap::new()),
+            started_at:      util::nano_ts(),
+            records:         AtomicU64::new(0),
+            skipped:         AtomicU64::new(0),
+            scrapes:         AtomicU64::new(0),
+            ticks:           AtomicU64::new(0),
+            last_tick_nanos: AtomicU64::new(0),
+        });
+
+        let exporter_c = Arc::clone(&exporter);
+        thread::Builder::new()
+            .name(String::from("prometheus"))
+            .spawn(move || {
+                for stream in listener.incoming().filter_map(Result::ok) {
+                    // Ignore errors: the scraper will retry on its next interval
+                    let _ = exporter_c.serve(stream);
+                }
+            })?;
+
+        Ok(exporter)
+    }
+
+    /// Records the duration of a single collection tick
+    pub fn record_tick(&self, duration: Duration) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.last_tick_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Responds to a single HTTP request, serving the metrics on `/metrics`
+    fn serve(&self, mut stream: TcpStream) -> Result<(), io::Error> {
+        stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+
+        // Read the request line, and then the headers until the blank line
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+                self.scrapes.fetch_add(1, Ordering::Relaxed);
+                ("200 OK", CONTENT_TYPE, self.render())
+            },
+            (Some("GET"), Some(_)) => (
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                String::from("Metrics are served on /metrics\n"),
+            ),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain; charset=utf-8",
+                String::new(),
+            ),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+
+    /// Renders the latest record of every active target, grouping the samples
+    /// of each metric family together, followed by the self-metrics
+    fn render(&self) -> String {
+        let targets = self
+            .targets
+            .lock()
+            .unwrap()
+            .values()
+            .map(Arc::clone)
+            .collect::<Vec<_>>();
+
+        let mut families: BTreeMap<&str, (MetricKind, String)> = BTreeMap::new();
+        let mut record = ByteRecord::new();
+        for target in &targets {
+            // Copy the record out so that the sink's lock is held briefly
+            record.clone_from(&target.latest.lock().unwrap());
+            if record.is_empty() {
+                continue;
+            }
+
+            for (i, column) in target.columns.iter().enumerate() {
+                let family = families
+                    .entry(&column.family)
+                    .or_insert_with(|| (column.kind, String::new()));
+                let sample = match column.kind {
+                    MetricKind::Counter => format!("{}_total", column.family),
+                    MetricKind::Gauge => column.family.clone(),
+                };
+                write_samples(&mut family.1, &sample, target, column, &record, i);
+            }
+        }
+
+        let mut out = String::new();
+        for (name, (kind, samples)) in &families {
+            if !samples.is_empty() {
+                let _ = writeln!(out, "# TYPE {} {}", name, kind.name());
+                out.push_str(samples);
+            }
+        }
+        self.render_self_metrics(&mut out, targets.len());
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Renders the metrics about rAdvisor itself
+    fn render_self_metrics(&self, out: &mut String, targets: usize) {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let _ = write!(
+            out,
+            "# TYPE {p}build_info gauge\n{p}build_info{{version=\"{}\"}} 1\n# TYPE \
+             {p}start_time_seconds gauge\n{p}start_time_seconds {}\n# TYPE {p}targets \
+             gauge\n{p}targets {}\n# TYPE {p}records counter\n{p}records_total {}\n# TYPE \
+             {p}records_skipped counter\n{p}records_skipped_total {}\n# TYPE {p}scrapes \
+             counter\n{p}scrapes_total {}\n# TYPE {p}collection_ticks \
+             counter\n{p}collection_ticks_total {}\n# TYPE {p}collection_tick_duration_seconds \
+             gauge\n{p}collection_tick_duration_seconds {}\n",
+            escape(cli::VERSION.unwrap_or("unknown")),
+            nanos_to_seconds(self.started_at),
+            targets,
+            load(&self.records),
+            load(&self.skipped),
+            load(&self.scrapes),
+            load(&self.ticks),
+            nanos_to_seconds(u128::from(load(&self.last_tick_nanos))),
+            p = PREFIX,
+        );
+    }
+}
+
+/// Writes the samples of a single column of a target's record
+fn write_samples(
+    out: &mut String,
+    sample: &str,
+    target: &ExportedTarget,
+    column: &ExportedColumn,
+    record: &ByteRecord,
+    index: usize,
+) {
+    let field = match record.get(index).and_then(|f| str::from_utf8(f).ok()) {
+        Some(field) => field,
+        None => return,
+    };
+
+    match &column.value {
+        ValueKind::Scalar => {
+            if let Ok(value) = field.parse::<f64>() {
+                let _ = writeln!(out, "{}{{{}}} {}", sample, target.labels, value);
+            }
+        },
+        ValueKind::Timestamp => {
+            if let Ok(value) = field.parse::<u128>() {
+                let _ = writeln!(
+                    out,
+                    "{}{{{}}} {}",
+                    sample,
+                    target.labels,
+                    nanos_to_seconds(value)
+                );
+            }
+        },
+        ValueKind::Vector { label, names } => {
+            let mut names = names
+                .and_then(|i| record.get(i))
+                .and_then(|f| str::from_utf8(f).ok())
+                .map(str::split_whitespace);
+            for (i, entry) in field.split_whitespace().enumerate() {
+                let name = names.as_mut().and_then(Iterator::next);
+                if let Ok(value) = entry.parse::<f64>() {
+                    let _ = writeln!(
+                        out,
+                        "{}{{{},{}=\"{}\"}} {}",
+                        sample,
+                        target.labels,
+                        label,
+                        escape(name.unwrap_or(&i.to_string())),
+                        value
+                    );
+                }
+            }
+        },
+        ValueKind::Skip => {},
+    }
+}
+
+/// Sink that keeps the most recent record of a target for the exporter
+pub struct PrometheusSink {
+    exporter: Arc<Exporter>,
+    id:       String,
+    target:   Arc<ExportedTarget>,
+}
+
+impl Sink for PrometheusSink {
+    fn open(target: &SinkTarget<'_>) -> Result<Self, Error> {
+        let exporter = match &target.handles.exporter {
+            Some(exporter) => Arc::clone(exporter),
+            None => return Err(format_err!("the Prometheus exporter isn't running")),
+        };
+
+        let names = target.column_names();
+        let state = Arc::new(ExportedTarget {
+            labels:  target_labels(target),
+            columns: names
+                .iter()
+                .map(|name| export_column(name, &names, &target.header.perf_table.columns))
+                .collect(),
+            latest:  Mutex::new(ByteRecord::new()),
+        });
+
+        exporter
+            .targets
+            .lock()
+            .unwrap()
+            .insert(target.id.to_owned(), Arc::clone(&state));
+        Ok(Self {
+            exporter,
+            id: target.id.to_owned(),
+            target: state,
+        })
+    }
+
+    fn write(&mut self, record: &ByteRecord) -> Result<(), Error> {
+        match self.target.latest.try_lock() {
+            Ok(mut latest) => {
+                latest.clear();
+                latest.extend(record);
+                self.exporter.records.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(_) => {
+                self.exporter.skipped.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+
+    /// Stops exporting the target, unless its entry has already been replaced
+    /// by a new sink for the same id (such as when a target is re-started
+    /// before its old collector is closed)
+    fn close(&mut self) -> Result<(), Error> {
+        let mut targets = self.exporter.targets.lock().unwrap();
+        if matches!(targets.get(&self.id), Some(entry) if Arc::ptr_eq(entry, &self.target)) {
+            targets.remove(&self.id);
+        }
+        Ok(())
+    }
+}
+
+/// Determines the way that the column with the given name is exported, given
+/// the names of all columns of the target and the metadata of the columns with
+/// non-default types
+fn export_column(
+    name: &str,
+    names: &[String],
+    metadata: &BTreeMap<String, Column>,
+) -> ExportedColumn {
+    let is_counter = COUNTER_SELECTORS.iter().any(|s| plan::selects(s, name))
+        || COUNTER_SUFFIXES.iter().any(|s| name.ends_with(s));
+    let kind = match is_counter {
+        true => MetricKind::Counter,
+        false => MetricKind::Gauge,
+    };
+
+    let value = match metadata.get(name) {
+        None
+        | Some(Column::Scalar {
+            r#type: ColumnType::Int | ColumnType::Float,
+        }) => ValueKind::Scalar,
+        Some(Column::Scalar {
+            r#type: ColumnType::Epoch19,
+        }) => ValueKind::Timestamp,
+        Some(Column::Vector {
+            r#type: ColumnType::Int | ColumnType::Float,
+            ..
+        }) => vector_value(name, names, metadata),
+        Some(_) => ValueKind::Skip,
+    };
+
+    let mut family = format!("{}{}", PREFIX, sanitize(name));
+    if value == ValueKind::Timestamp {
+        family.push_str("_timestamp_seconds");
+    }
+    // Counter samples get the _total suffix, which can't also be part of the
+    // family name
+    if kind == MetricKind::Counter && family.ends_with("_total") {
+        family.truncate(family.len() - "_total".len());
+    }
+
+    ExportedColumn {
+        family,
+        kind,
+        value,
+    }
+}
+
+/// Determines the way that the entries of the numeric vector column with the
+/// given name are labelled. Entries are named by the string vector column that
+/// shares the longest prefix with the column (such as `network.interfaces` for
+/// `network.rx.bytes`), or by their position if there is no such column
+fn vector_value(name: &str, names: &[String], metadata: &BTreeMap<String, Column>) -> ValueKind {
+    match sink::names_column(name, names, metadata) {
+        Some(i) => ValueKind::Vector {
+            label: entry_label(&names[i]),
+            names: Some(i),
+        },
+        None => ValueKind::Vector {
+            label: String::from("index"),
+            names: None,
+        },
+    }
+}
+
+/// Gets the label used for each entry of the vector columns named by the given
+/// column, which is the singular of its last segment (such as `interface` for
+/// `network.interfaces`)
+fn entry_label(names_column: &str) -> String {
+    let last = names_column.rsplit('.').next().unwrap_or(names_column);
+    sanitize(last.strip_suffix('s').unwrap_or(last))
+}
+
+/// Formats the labels shared by every sample of the given target (see
+/// `SinkTarget::tags`)
+fn target_labels(target: &SinkTarget<'_>) -> String {
+    target
+        .tags()
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", sanitize(key), escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Replaces each character that isn't valid in metric and label names with `_`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Converts a nanosecond timestamp or duration to seconds
+#[allow(clippy::cast_precision_loss)]
+fn nanos_to_seconds(nanos: u128) -> f64 { nanos as f64 / 1e9 }
@@ -52,6 +52,37 @@ fn run(opts: RunCommand, shell: Arc<Shell>) {
     let polling_opts = opts.provider.polling().clone();
     let collection_opts = opts.provider.collection().clone();
 
+    // Build the read plan for each CSV schema from the metric selection before
+    // starting any collection
+    let schemas = match collection::collect::Schemas::new(&collection_opts) {
+        Ok(schemas) => schemas,
+        Err(err) => {
+            shell.error(err.to_string());
+            std::process::exit(1);
+        },
+    };
+
+    // Start the Prometheus exporter before any collection, if it's enabled
+    let exporter = match collection_opts.prometheus_listen {
+        Some(address) => match collection::sink::prometheus::Exporter::listen(address) {
+            Ok(exporter) => {
+                shell.status(
+                    "Beginning",
+                    format!("Prometheus exporter on http://{}/metrics", exporter.address),
+                );
+                Some(exporter)
+            },
+            Err(err) => {
+                shell.error(format!(
+                    "Could not start Prometheus exporter on {}: {}",
+                    address, err
+                ));
+                std::process::exit(1);
+            },
+        },
+        None => None,
+    };
+
     // Create the thread worker contexts using the term bus lock
     let term_bus = initialize_term_handler(Arc::clone(&shell));
     let mut term_bus_handle = term_bus.lock().unwrap();
@@ -94,7 +125,9 @@ fn run(opts: RunCommand, shell: Arc<Shell>) {
         .unwrap();
     let collection_thread: thread::JoinHandle<()> = thread::Builder::new()
         .name(String::from("collect"))
-        .spawn(move || collection::run(&rx, collection_context, &collection_opts))
+        .spawn(move || {
+            collection::run(&rx, collection_context, &collection_opts, schemas, exporter)
+        })
         .unwrap();
 
     // Join the threads, which automatically exit upon termination
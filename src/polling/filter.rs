@@ -0,0 +1,201 @@
+use crate::cli::FilterOptions;
+use crate::polling::providers::InitializationError;
+use std::fmt;
+
+use glob::{Pattern, PatternError};
+use regex::Regex;
+
+/// Filter shared between providers that selects which targets statistics are
+/// collected for, based on their labels, name, namespace, and image. Providers
+/// apply the filter to the list of targets before updating their pools, so
+/// that targets that don't match are never collected
+#[derive(Clone, Debug, Default)]
+pub struct TargetFilter {
+    include_labels: Vec<LabelSelector>,
+    exclude_labels: Vec<LabelSelector>,
+    name_regex:     Option<Regex>,
+    namespaces:     Vec<String>,
+    images:         Vec<Pattern>,
+}
+
+/// Attributes of a single target that a filter is applied to
+pub struct Candidate<'a> {
+    pub name:      &'a str,
+    pub labels:    Vec<(&'a str, &'a str)>,
+    pub namespace: Option<&'a str>,
+    pub images:    Vec<&'a str>,
+}
+
+/// Label selector given on the command line, which either matches all targets
+/// that have the label (such as `app`) or only those where the label has the
+/// given value (such as `app=web`)
+#[derive(Clone, Debug, PartialEq)]
+struct LabelSelector {
+    key:   String,
+    value: Option<String>,
+}
+
+/// Possible errors that can occur while parsing the target filter options
+#[derive(Debug)]
+pub enum FilterError {
+    InvalidLabel(String),
+    InvalidNameRegex(regex::Error),
+    InvalidImagePattern(String, PatternError),
+}
+
+impl Into<InitializationError> for FilterError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::InvalidLabel(raw) => InitializationError {
+                original:   None,
+                suggestion: format!(
+                    "Invalid label selector '{}'. Label selectors are either a key (such as app) \
+                     or a key-value pair (such as app=web)",
+                    raw
+                ),
+            },
+            Self::InvalidNameRegex(error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: String::from("Invalid regular expression given for --name-regex"),
+            },
+            Self::InvalidImagePattern(raw, error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: format!(
+                    "Invalid image pattern '{}'. Image patterns use shell-style globs (such as \
+                     nginx:*)",
+                    raw
+                ),
+            },
+        }
+    }
+}
+
+impl TargetFilter {
+    /// Parses the target filter from the given command line options, failing
+    /// if any of the label selectors, regular expressions, or image patterns
+    /// are invalid
+    pub fn new(opts: &FilterOptions) -> Result<Self, FilterError> {
+        let name_regex = match &opts.name_regex {
+            Some(raw) => Some(Regex::new(raw).map_err(FilterError::InvalidNameRegex)?),
+            None => None,
+        };
+        let images = opts
+            .images
+            .iter()
+            .map(|raw| {
+                Pattern::new(raw).map_err(|err| FilterError::InvalidImagePattern(raw.clone(), err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            include_labels: parse_labels(&opts.include_labels)?,
+            exclude_labels: parse_labels(&opts.exclude_labels)?,
+            name_regex,
+            namespaces: opts.namespaces.clone(),
+            images,
+        })
+    }
+
+    /// Whether the filter selects every target
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.include_labels.is_empty()
+            && self.exclude_labels.is_empty()
+            && self.name_regex.is_none()
+            && self.namespaces.is_empty()
+            && self.images.is_empty()
+    }
+
+    /// Whether statistics should be collected for the given target. Targets
+    /// must have all included labels and none of the excluded labels, must have
+    /// a name that matches the name regex, must belong to one of the
+    /// namespaces, and must use at least one image that matches one of the
+    /// image patterns. Unset parts of the filter match every target
+    #[must_use]
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        let has_label = |selector: &LabelSelector| {
+            candidate.labels.iter().any(|&(key, value)| {
+                key == selector.key
+                    && match &selector.value {
+                        Some(selected) => selected == value,
+                        None => true,
+                    }
+            })
+        };
+        let name_matches = match &self.name_regex {
+            Some(regex) => regex.is_match(candidate.name),
+            None => true,
+        };
+        let namespace_matches = self.namespaces.is_empty()
+            || matches!(candidate.namespace, Some(ns) if self.namespaces.iter().any(|n| n == ns));
+        let image_matches = self.images.is_empty()
+            || candidate
+                .images
+                .iter()
+                .any(|image| self.images.iter().any(|p| p.matches(image)));
+
+        self.include_labels.iter().all(has_label)
+            && !self.exclude_labels.iter().any(has_label)
+            && name_matches
+            && namespace_matches
+            && image_matches
+    }
+
+    /// Gets the expression for the filter that gets included with each log
+    /// file, if the filter doesn't select every target
+    #[must_use]
+    pub fn expression(&self) -> Option<String> {
+        match self.is_empty() {
+            true => None,
+            false => Some(self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TargetFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut terms: Vec<String> = Vec::new();
+        terms.extend(self.include_labels.iter().map(|l| format!("label({})", l)));
+        terms.extend(self.exclude_labels.iter().map(|l| format!("!label({})", l)));
+        if let Some(regex) = &self.name_regex {
+            terms.push(format!("name =~ /{}/", regex));
+        }
+        if !self.namespaces.is_empty() {
+            terms.push(format!("namespace in ({})", self.namespaces.join(", ")));
+        }
+        if !self.images.is_empty() {
+            let images = self.images.iter().map(Pattern::as_str).collect::<Vec<_>>();
+            terms.push(format!("image in ({})", images.join(", ")));
+        }
+
+        write!(f, "{}", terms.join(" && "))
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.key, value),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Parses all label selectors given on the command line
+fn parse_labels(raw: &[String]) -> Result<Vec<LabelSelector>, FilterError> {
+    raw.iter()
+        .map(|raw| {
+            let (key, value) = match raw.split_once('=') {
+                Some((key, value)) => (key, Some(value.to_owned())),
+                None => (raw.as_str(), None),
+            };
+            match key.is_empty() {
+                true => Err(FilterError::InvalidLabel(raw.clone())),
+                false => Ok(LabelSelector {
+                    key: key.to_owned(),
+                    value,
+                }),
+            }
+        })
+        .collect()
+}
@@ -1,3 +1,4 @@
+pub mod filter;
 pub mod providers;
 
 use crate::polling::providers::Provider;
@@ -40,6 +41,7 @@ pub fn run(
         .unwrap();
     // Move to mutable
     let mut provider = provider;
+    provider.stream(tx);
 
     for _ in timer {
         let events: Vec<CollectionEvent> = match provider.poll() {
@@ -0,0 +1,320 @@
+use crate::cli::RunCommand;
+use crate::polling::providers::{InitializationError, Provider};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupDriver, CgroupPath, CgroupVersion, ItemPool};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use failure::Error;
+use glob::{glob, Pattern, PatternError};
+use serde::Serialize;
+
+/// Target Id used for the root cgroup of the hierarchy
+const ROOT_ID: &str = "root";
+
+const PROVIDER_TYPE: &str = "cgroups";
+
+pub struct Cgroups {
+    cgroup_pool: ItemPool<String>,
+    version:     CgroupVersion,
+    hierarchy:   Option<PathBuf>,
+    patterns:    Vec<CgroupPattern>,
+    shell:       Option<Arc<Shell>>,
+}
+
+/// Possible errors that can occur during cgroups provider initialization
+#[derive(Debug)]
+enum CgroupsInitError {
+    InvalidCgroupMount,
+    InvalidPattern(String, PatternError),
+}
+
+impl Into<InitializationError> for CgroupsInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+            Self::InvalidPattern(raw, error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: format!(
+                    "Invalid cgroup path pattern '{}'. Patterns use shell-style globs (such as \
+                     /system.slice/*.service)",
+                    raw
+                ),
+            },
+        }
+    }
+}
+
+/// Single cgroup path given on the command line, which is either a literal
+/// path or a glob pattern, relative to the root of the cgroup hierarchy
+#[derive(Clone, Debug, PartialEq)]
+struct CgroupPattern {
+    /// Original string given on the command line
+    raw:      String,
+    /// Path relative to the root of the cgroup hierarchy
+    relative: String,
+    /// Compiled glob pattern, if the path contains any wildcards
+    pattern:  Option<Pattern>,
+}
+
+impl Provider for Cgroups {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell()
+            .status("Initializing", "static cgroups provider");
+
+        let paths = opts.provider.clone().into_inner_cgroups().paths;
+        match self.try_init(&paths) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        // Map of relative cgroup paths that currently exist to the index of the
+        // first pattern they matched
+        let mut to_collect: BTreeMap<String, usize> = BTreeMap::new();
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            for cgroup in self.expand(pattern) {
+                to_collect.entry(cgroup).or_insert(i);
+            }
+        }
+
+        let cgroups = to_collect.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.cgroup_pool.update(cgroups);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed_len);
+        // Add all removed cgroups as Stop events
+        events.extend(
+            removed
+                .into_iter()
+                .map(|cgroup| CollectionEvent::Stop(make_id(&cgroup))),
+        );
+
+        // Add all added cgroups as Start events
+        let start_events = added
+            .into_iter()
+            .filter_map(|cgroup| {
+                // The pool only contains cgroups from the map, so this always exists
+                let pattern = &self.patterns[*to_collect.get(&cgroup)?];
+                match self.make_start_event(&cgroup, pattern) {
+                    Ok(start) => Some(start),
+                    Err(cause) => {
+                        self.shell()
+                            .warn(format!("Could not serialize cgroup metadata: {}", cause));
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        if processed_num != 0 || removed_len != 0 {
+            self.shell().verbose(|sh| {
+                sh.info(format!(
+                    "Found {} (+{}, -{}) cgroups matching {} path(s)",
+                    to_collect.len(),
+                    processed_num,
+                    removed_len,
+                    self.patterns.len()
+                ))
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Default for Cgroups {
+    fn default() -> Self { Self::new() }
+}
+
+impl Cgroups {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            cgroup_pool: ItemPool::new(),
+            version:     CgroupVersion::detect(),
+            hierarchy:   None,
+            patterns:    Vec::new(),
+            shell:       None,
+        }
+    }
+
+    /// Attempts to initialize the cgroups provider, failing if the needed
+    /// Cgroups aren't mounted properly or if any of the given paths are
+    /// invalid glob patterns
+    fn try_init(&mut self, paths: &[String]) -> Result<(), CgroupsInitError> {
+        // Make sure cgroups are mounted properly
+        if !util::cgroups_mounted_properly() {
+            return Err(CgroupsInitError::InvalidCgroupMount);
+        }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
+        self.hierarchy =
+            Some(util::cgroup_dir("", self.version).ok_or(CgroupsInitError::InvalidCgroupMount)?);
+
+        for raw in paths {
+            let relative = to_relative(raw);
+            let pattern = match is_glob(&relative) {
+                false => None,
+                true => Some(
+                    Pattern::new(&relative)
+                        .map_err(|err| CgroupsInitError::InvalidPattern(raw.clone(), err))?,
+                ),
+            };
+            self.patterns.push(CgroupPattern {
+                raw: raw.clone(),
+                relative,
+                pattern,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Expands the given cgroup path pattern to all matching cgroups that
+    /// currently exist, as paths relative to the root of the cgroup hierarchy
+    fn expand(&self, pattern: &CgroupPattern) -> Vec<String> {
+        let hierarchy = self.hierarchy();
+        if pattern.pattern.is_none() {
+            return match hierarchy.join(&pattern.relative).is_dir() {
+                true => vec![pattern.relative.clone()],
+                false => Vec::new(),
+            };
+        }
+
+        let full_pattern = format!(
+            "{}/{}",
+            Pattern::escape(&hierarchy.to_string_lossy()),
+            pattern.relative
+        );
+        match glob(&full_pattern) {
+            Err(_) => Vec::new(),
+            Ok(paths) => paths
+                .filter_map(Result::ok)
+                .filter(|path| path.is_dir())
+                .filter_map(|path| {
+                    let relative = path.strip_prefix(hierarchy).ok()?;
+                    Some(relative.to_string_lossy().into_owned())
+                })
+                .collect(),
+        }
+    }
+
+    /// Converts an existing cgroup to a collection start event, preparing all
+    /// serialization needed
+    fn make_start_event(
+        &self,
+        cgroup: &str,
+        pattern: &CgroupPattern,
+    ) -> Result<CollectionEvent, Error> {
+        let info = CgroupInfo {
+            cgroup:    format!("/{}", cgroup),
+            pattern:   &pattern.raw,
+            hierarchy: self.hierarchy().join(cgroup),
+        };
+        let metadata = serde_yaml::to_value(&info)?;
+        let path = CgroupPath {
+            path:    PathBuf::from(cgroup),
+            driver:  infer_driver(cgroup),
+            version: self.version,
+        };
+
+        Ok(CollectionEvent::Start {
+            method: CollectionMethod::from(path),
+            target: CollectionTarget {
+                provider:  PROVIDER_TYPE,
+                metadata:  Some(metadata),
+                name:      info.cgroup,
+                poll_time: util::nano_ts(),
+                filter:    None,
+                id:        make_id(cgroup),
+            },
+        })
+    }
+
+    /// Gets the absolute directory of the root of the cgroup hierarchy
+    fn hierarchy(&self) -> &Path {
+        self.hierarchy
+            .as_ref()
+            .expect("Hierarchy must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+/// Cgroup info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CgroupInfo<'a> {
+    cgroup:    String,
+    pattern:   &'a str,
+    hierarchy: PathBuf,
+}
+
+/// Converts a cgroup path given on the command line to a path relative to the
+/// root of the cgroup hierarchy. Paths in the cgroup virtual filesystem (such
+/// as `/sys/fs/cgroup/memory/system.slice`) have their mount point removed,
+/// while other absolute paths are treated as cgroup paths (such as
+/// `/system.slice`)
+fn to_relative(raw: &str) -> String {
+    let path = Path::new(raw);
+    let mounts = util::cgroup_mounts();
+    let mount_relative = mounts
+        .controllers
+        .values()
+        .chain(mounts.unified.iter())
+        .find_map(|mount| path.strip_prefix(&mount.mount_point).ok());
+    let relative = mount_relative.unwrap_or(path);
+
+    relative
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether the given path contains any glob wildcards
+fn is_glob(path: &str) -> bool { path.contains(&['*', '?', '['][..]) }
+
+/// Infers the cgroup driver that manages the cgroup from its name, since the
+/// cgroup wasn't resolved through a `CgroupManager`
+pub(super) fn infer_driver(cgroup: &str) -> CgroupDriver {
+    let is_systemd = Path::new(cgroup)
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().ends_with(".slice"));
+    match is_systemd {
+        true => CgroupDriver::Systemd,
+        false => CgroupDriver::Cgroupfs,
+    }
+}
+
+/// Creates a target Id for the given relative cgroup path that can be used in
+/// log file names
+pub(super) fn make_id(cgroup: &str) -> String {
+    match cgroup.is_empty() {
+        true => String::from(ROOT_ID),
+        false => cgroup.replace('/', "-"),
+    }
+}
@@ -0,0 +1,237 @@
+//! Minimal client for the Kubernetes Container Runtime Interface (CRI)
+//! `runtime.v1.RuntimeService` gRPC service, including only the messages and
+//! fields used by rAdvisor.
+//! see [`cri-api/pkg/apis/runtime/v1/api.proto`](https://github.com/kubernetes/cri-api/blob/c75ef5b/pkg/apis/runtime/v1/api.proto)
+
+// Message definitions mirror the upstream protobuf schema
+#![allow(clippy::missing_const_for_fn)]
+#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::enum_variant_names)]
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+/// Fully-qualified name of the CRI runtime service
+const RUNTIME_SERVICE: &str = "runtime.v1.RuntimeService";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VersionRequest {
+    #[prost(string, tag = "1")]
+    pub version: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VersionResponse {
+    #[prost(string, tag = "1")]
+    pub version:             String,
+    #[prost(string, tag = "2")]
+    pub runtime_name:        String,
+    #[prost(string, tag = "3")]
+    pub runtime_version:     String,
+    #[prost(string, tag = "4")]
+    pub runtime_api_version: String,
+}
+
+/// State of a container, as reported by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ContainerState {
+    ContainerCreated = 0,
+    ContainerRunning = 1,
+    ContainerExited  = 2,
+    ContainerUnknown = 3,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ContainerStateValue {
+    #[prost(enumeration = "ContainerState", tag = "1")]
+    pub state: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ContainerFilter {
+    #[prost(string, tag = "1")]
+    pub id:             String,
+    #[prost(message, optional, tag = "2")]
+    pub state:          Option<ContainerStateValue>,
+    #[prost(string, tag = "3")]
+    pub pod_sandbox_id: String,
+    #[prost(btree_map = "string, string", tag = "4")]
+    pub label_selector: BTreeMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListContainersRequest {
+    #[prost(message, optional, tag = "1")]
+    pub filter: Option<ContainerFilter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListContainersResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub containers: Vec<Container>,
+}
+
+#[derive(Clone, PartialEq, Serialize, prost::Message)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerMetadata {
+    #[prost(string, tag = "1")]
+    pub name:    String,
+    #[prost(uint32, tag = "2")]
+    pub attempt: u32,
+}
+
+#[derive(Clone, PartialEq, Serialize, prost::Message)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageSpec {
+    #[prost(string, tag = "1")]
+    pub image: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Container {
+    #[prost(string, tag = "1")]
+    pub id:             String,
+    #[prost(string, tag = "2")]
+    pub pod_sandbox_id: String,
+    #[prost(message, optional, tag = "3")]
+    pub metadata:       Option<ContainerMetadata>,
+    #[prost(message, optional, tag = "4")]
+    pub image:          Option<ImageSpec>,
+    #[prost(string, tag = "5")]
+    pub image_ref:      String,
+    #[prost(enumeration = "ContainerState", tag = "6")]
+    pub state:          i32,
+    #[prost(int64, tag = "7")]
+    pub created_at:     i64,
+    #[prost(btree_map = "string, string", tag = "8")]
+    pub labels:         BTreeMap<String, String>,
+    #[prost(btree_map = "string, string", tag = "9")]
+    pub annotations:    BTreeMap<String, String>,
+}
+
+/// State of a pod sandbox, as reported by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum PodSandboxState {
+    SandboxReady    = 0,
+    SandboxNotready = 1,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PodSandboxStateValue {
+    #[prost(enumeration = "PodSandboxState", tag = "1")]
+    pub state: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PodSandboxFilter {
+    #[prost(string, tag = "1")]
+    pub id:             String,
+    #[prost(message, optional, tag = "2")]
+    pub state:          Option<PodSandboxStateValue>,
+    #[prost(btree_map = "string, string", tag = "3")]
+    pub label_selector: BTreeMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListPodSandboxRequest {
+    #[prost(message, optional, tag = "1")]
+    pub filter: Option<PodSandboxFilter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListPodSandboxResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub items: Vec<PodSandbox>,
+}
+
+#[derive(Clone, PartialEq, Serialize, prost::Message)]
+#[serde(rename_all = "PascalCase")]
+pub struct PodSandboxMetadata {
+    #[prost(string, tag = "1")]
+    pub name:      String,
+    #[prost(string, tag = "2")]
+    pub uid:       String,
+    #[prost(string, tag = "3")]
+    pub namespace: String,
+    #[prost(uint32, tag = "4")]
+    pub attempt:   u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PodSandbox {
+    #[prost(string, tag = "1")]
+    pub id:              String,
+    #[prost(message, optional, tag = "2")]
+    pub metadata:        Option<PodSandboxMetadata>,
+    #[prost(enumeration = "PodSandboxState", tag = "3")]
+    pub state:           i32,
+    #[prost(int64, tag = "4")]
+    pub created_at:      i64,
+    #[prost(btree_map = "string, string", tag = "5")]
+    pub labels:          BTreeMap<String, String>,
+    #[prost(btree_map = "string, string", tag = "6")]
+    pub annotations:     BTreeMap<String, String>,
+    #[prost(string, tag = "7")]
+    pub runtime_handler: String,
+}
+
+/// Client for the unary RPCs of the CRI runtime service used by rAdvisor
+#[derive(Clone)]
+pub struct RuntimeServiceClient {
+    inner: Grpc<Channel>,
+}
+
+impl RuntimeServiceClient {
+    #[must_use]
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: Grpc::new(channel),
+        }
+    }
+
+    /// Gets the runtime name, runtime version, and runtime API version
+    pub async fn version(&mut self, request: VersionRequest) -> Result<VersionResponse, Status> {
+        self.unary(request, "Version").await
+    }
+
+    /// Lists all containers matching the filter
+    pub async fn list_containers(
+        &mut self,
+        request: ListContainersRequest,
+    ) -> Result<ListContainersResponse, Status> {
+        self.unary(request, "ListContainers").await
+    }
+
+    /// Lists all pod sandboxes matching the filter
+    pub async fn list_pod_sandbox(
+        &mut self,
+        request: ListPodSandboxRequest,
+    ) -> Result<ListPodSandboxResponse, Status> {
+        self.unary(request, "ListPodSandbox").await
+    }
+
+    /// Invokes the given unary method on the runtime service
+    async fn unary<Req, Res>(&mut self, request: Req, method: &str) -> Result<Res, Status>
+    where
+        Req: prost::Message + 'static,
+        Res: prost::Message + Default + 'static,
+    {
+        self.inner
+            .ready()
+            .await
+            .map_err(|err| Status::unknown(format!("Service was not ready: {}", err)))?;
+        let path = PathAndQuery::from_maybe_shared(format!("/{}/{}", RUNTIME_SERVICE, method))
+            .map_err(|err| Status::internal(format!("Invalid method path: {}", err)))?;
+        let codec: ProstCodec<Req, Res> = ProstCodec::default();
+        let response = self.inner.unary(Request::new(request), path, codec).await?;
+        Ok(response.into_inner())
+    }
+}
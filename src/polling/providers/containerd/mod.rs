@@ -0,0 +1,496 @@
+mod cri;
+#[cfg(test)]
+mod tests;
+
+use crate::cli::RunCommand;
+use crate::polling::providers::{InitializationError, Provider};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupDriver, CgroupManager, CgroupPath, ItemPool};
+use cri::{Container, ContainerState, ContainerStateValue, ListContainersRequest,
+          ListPodSandboxRequest, PodSandbox, PodSandboxState, PodSandboxStateValue,
+          RuntimeServiceClient, VersionRequest};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use failure::Error;
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio_1::net::UnixStream;
+use tonic::transport::{Endpoint, Uri};
+
+/// String representation for "None"
+const NONE_STR: &str = "~";
+
+/// Root cgroup for kubernetes pods to fall under
+const ROOT_CGROUP: &str = "kubepods";
+
+/// Quality of service classes that have their own cgroup under the root
+/// cgroup, in the order they are tried. Guaranteed pods are placed directly
+/// under the root cgroup
+const QOS_CGROUPS: &[Option<&str>] = &[Some("burstable"), Some("besteffort"), None];
+
+/// Version of the CRI API that rAdvisor expects
+const CRI_API_VERSION: &str = "v1";
+
+const PROVIDER_TYPE: &str = "containerd";
+
+pub struct Containerd {
+    container_id_pool: ItemPool<String>,
+    /// Ids of all containers that are waiting for their cgroup to be created
+    pending:           BTreeSet<String>,
+    cgroup_manager:    CgroupManager,
+    client:            Option<RuntimeServiceClient>,
+    shell:             Option<Arc<Shell>>,
+    runtime:           RefCell<tokio_1::runtime::Runtime>,
+}
+
+/// Possible errors that can occur during containerd provider initialization
+#[derive(Debug)]
+enum ContainerdInitError {
+    ConnectionFailed(PathBuf, Error),
+    InvalidCgroupMount,
+}
+
+impl Into<InitializationError> for ContainerdInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::ConnectionFailed(endpoint, error) => InitializationError {
+                original:   Some(error),
+                suggestion: format!(
+                    "Could not connect to the CRI runtime service at {}. Are you running rAdvisor \
+                     as root?\nIf the runtime is listening on a non-standard socket, set it with \
+                     --runtime-endpoint.",
+                    endpoint.display()
+                ),
+            },
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+        }
+    }
+}
+
+/// Possible error that can occur during containerd container collection target
+/// initialization
+#[derive(Debug)]
+enum StartCollectionError {
+    MetadataSerializationError(Error),
+    CgroupNotFound,
+    MissingPodUid,
+}
+
+impl Provider for Containerd {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell()
+            .status("Initializing", "containerd CRI provider");
+
+        let endpoint = opts
+            .provider
+            .clone()
+            .into_inner_containerd()
+            .runtime_endpoint;
+        match self.try_init(endpoint) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        let containers = self.get_containers()?;
+        let sandboxes = self.get_sandboxes()?;
+
+        let original_num = containers.len();
+        let to_collect: BTreeMap<String, (Container, &PodSandbox)> = containers
+            .into_iter()
+            .filter_map(|c| {
+                // Only collect containers in a ready pod sandbox
+                let sandbox = sandboxes.get(&c.pod_sandbox_id)?;
+                Some((c.id.clone(), (c, sandbox)))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let ids = to_collect.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.container_id_pool.update(ids);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed_len);
+        // Add all removed Ids as Stop events
+        events.extend(removed.into_iter().map(CollectionEvent::Stop));
+
+        // Add all added Ids as Start events
+        let start_events = added
+            .into_iter()
+            .flat_map(|id| {
+                // It shouldn't be possible to have an Id that doesn't exist in the map, but
+                // check anyways
+                let (container, sandbox) = match to_collect.get(&id) {
+                    Some(entry) => entry,
+                    None => {
+                        self.shell().error(format!(
+                            "Processed Id from ItemPool added result that was not in fetched \
+                             container list. This is a bug!\nId: {}",
+                            id
+                        ));
+                        return None;
+                    },
+                };
+
+                match self.make_start_event(container, sandbox) {
+                    Ok(start) => {
+                        self.pending.remove(&id);
+                        Some(start)
+                    },
+                    Err(StartCollectionError::CgroupNotFound) => {
+                        // Retry on the next poll, in case the cgroup hasn't been created yet
+                        self.container_id_pool.remove(&id);
+                        if self.pending.insert(id) {
+                            self.shell().warn(format!(
+                                "Could not create container metadata for container {}: cgroup \
+                                 path could not be constructed or does not exist (yet)",
+                                display(container, sandbox)
+                            ));
+                        }
+                        None
+                    },
+                    Err(StartCollectionError::MissingPodUid) => {
+                        self.shell().warn(format!(
+                            "Could not create container metadata for container {}: pod sandbox is \
+                             missing its pod UID",
+                            display(container, sandbox)
+                        ));
+
+                        // Ignore container and continue initializing the rest
+                        None
+                    },
+                    Err(StartCollectionError::MetadataSerializationError(cause)) => {
+                        self.shell()
+                            .warn(format!("Could not serialize container metadata: {}", cause));
+
+                        // Ignore container and continue initializing the rest
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        // Stop waiting for containers that were removed before their cgroup appeared
+        self.pending.retain(|id| to_collect.contains_key(id));
+
+        if processed_num != 0 || removed_len != 0 {
+            self.shell().verbose(|sh| {
+                sh.info(format!(
+                    "Received {} -> {} (+{}, -{}) containers from the CRI API",
+                    original_num,
+                    to_collect.len(),
+                    processed_num,
+                    removed_len
+                ))
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Default for Containerd {
+    fn default() -> Self { Self::new() }
+}
+
+impl Containerd {
+    #[must_use]
+    pub fn new() -> Self {
+        // Use a single-threaded runtime so that Tokio doesn't create
+        // a thread pool and instead executes futures in the current thread
+        // (emulating synchronous I/O)
+        let runtime = tokio_1::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        Self {
+            container_id_pool: ItemPool::new(),
+            pending:           BTreeSet::new(),
+            cgroup_manager:    CgroupManager::new(),
+            client:            None,
+            shell:             None,
+            runtime:           RefCell::new(runtime),
+        }
+    }
+
+    /// Executes a future on the internal runtime, blocking the current thread
+    /// until it completes
+    fn exec<F: Future>(&self, future: F) -> F::Output {
+        let rt = self.runtime.borrow_mut();
+        rt.block_on(future)
+    }
+
+    /// Attempts to initialize the containerd provider, failing if the
+    /// connection check to the CRI runtime service failed or if the needed
+    /// Cgroups aren't mounted properly
+    fn try_init(&mut self, endpoint: PathBuf) -> Result<(), ContainerdInitError> {
+        self.connect(endpoint)?;
+
+        // Make sure cgroups are mounted properly
+        if !util::cgroups_mounted_properly() {
+            return Err(ContainerdInitError::InvalidCgroupMount);
+        }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
+
+        Ok(())
+    }
+
+    /// Connects to the CRI runtime service listening on the given Unix domain
+    /// socket, requesting its version to make sure the current process can
+    /// connect
+    fn connect(&mut self, endpoint: PathBuf) -> Result<(), ContainerdInitError> {
+        let mut client = self
+            .exec(connect(endpoint.clone()))
+            .map_err(|err| ContainerdInitError::ConnectionFailed(endpoint.clone(), err))?;
+        let version = self
+            .exec(client.version(VersionRequest {
+                version: String::from(CRI_API_VERSION),
+            }))
+            .map_err(|err| ContainerdInitError::ConnectionFailed(endpoint, Error::from(err)))?;
+        self.shell().info(format!(
+            "Connected to {} {} (CRI API {})",
+            version.runtime_name, version.runtime_version, version.runtime_api_version
+        ));
+        self.client = Some(client);
+        Ok(())
+    }
+
+    /// Gets all running containers from the CRI runtime service
+    fn get_containers(&self) -> Result<Vec<Container>, Error> {
+        let mut client = self.client().clone();
+        let request = ListContainersRequest {
+            filter: Some(cri::ContainerFilter {
+                state: Some(ContainerStateValue {
+                    state: ContainerState::ContainerRunning as i32,
+                }),
+                ..cri::ContainerFilter::default()
+            }),
+        };
+        let response = self.exec(client.list_containers(request))?;
+        Ok(response.containers)
+    }
+
+    /// Gets all ready pod sandboxes from the CRI runtime service, keyed by
+    /// their sandbox Id
+    fn get_sandboxes(&self) -> Result<BTreeMap<String, PodSandbox>, Error> {
+        let mut client = self.client().clone();
+        let request = ListPodSandboxRequest {
+            filter: Some(cri::PodSandboxFilter {
+                state: Some(PodSandboxStateValue {
+                    state: PodSandboxState::SandboxReady as i32,
+                }),
+                ..cri::PodSandboxFilter::default()
+            }),
+        };
+        let response = self.exec(client.list_pod_sandbox(request))?;
+        Ok(response
+            .items
+            .into_iter()
+            .map(|s| (s.id.clone(), s))
+            .collect())
+    }
+
+    /// Converts a container to a collection start event, preparing all
+    /// serialization/cgroup checks needed
+    fn make_start_event(
+        &mut self,
+        container: &Container,
+        sandbox: &PodSandbox,
+    ) -> Result<CollectionEvent, StartCollectionError> {
+        let uid = sandbox
+            .metadata
+            .as_ref()
+            .map(|m| m.uid.as_str())
+            .filter(|uid| !uid.is_empty())
+            .ok_or(StartCollectionError::MissingPodUid)?;
+        let method = self.get_collection_method(container, uid)?;
+        let metadata = match serde_yaml::to_value(ContainerInfo::new(container, sandbox)) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return Err(StartCollectionError::MetadataSerializationError(
+                    Error::from(err),
+                ));
+            },
+        };
+
+        Ok(CollectionEvent::Start {
+            method,
+            target: CollectionTarget {
+                provider:  PROVIDER_TYPE,
+                metadata:  Some(metadata),
+                name:      display(container, sandbox),
+                poll_time: util::nano_ts(),
+                filter:    None,
+                id:        container.id.clone(),
+            },
+        })
+    }
+
+    /// Gets the collection method struct for the container, resolving the
+    /// proper collection method
+    fn get_collection_method(
+        &mut self,
+        container: &Container,
+        uid: &str,
+    ) -> Result<CollectionMethod, StartCollectionError> {
+        match self.get_cgroup(&container.id, uid) {
+            Some(cgroup) => Ok(CollectionMethod::from(cgroup)),
+            None => Err(StartCollectionError::CgroupNotFound),
+        }
+    }
+
+    /// Gets the group path for the given container Id and pod UID, printing out
+    /// a message upon the first successful cgroup resolution. The quality of
+    /// service class of the pod isn't exposed by the CRI, so each possible
+    /// parent cgroup is tried in turn
+    fn get_cgroup(&mut self, id: &str, uid: &str) -> Option<CgroupPath> {
+        let pod_slice = String::from("pod") + uid;
+        let container_scope = format!("cri-containerd-{}.scope", id);
+        // Determine if the manager had a resolved group beforehand
+        let had_driver = self.cgroup_manager.driver().is_some();
+
+        // Container cgroups are leaf cgroups under their pod's cgroup. Under the
+        // systemd driver, they are scopes named after the runtime and (full)
+        // container Id; under the cgroupfs driver, they are named by the Id
+        let cgroup_option: Option<CgroupPath> = QOS_CGROUPS.iter().find_map(|qos| {
+            let pod_slices = match qos {
+                Some(qos) => vec![ROOT_CGROUP, qos, &pod_slice],
+                None => vec![ROOT_CGROUP, &pod_slice],
+            };
+            let systemd_path =
+                util::make(CgroupDriver::Systemd, &pod_slices, true).join(&container_scope);
+            let systemd_path = systemd_path.to_str()?;
+            let mut cgroupfs_slices = pod_slices.clone();
+            cgroupfs_slices.push(id);
+
+            self.cgroup_manager
+                .get_cgroup_divided(&[systemd_path], &cgroupfs_slices, false)
+        });
+
+        if !had_driver {
+            if let Some(driver) = self.cgroup_manager.driver() {
+                self.shell()
+                    .info(format!("Identified {} as cgroup driver", driver));
+            }
+        }
+
+        cgroup_option
+    }
+
+    /// Gets a reference to the current runtime service client
+    fn client(&self) -> &RuntimeServiceClient {
+        self.client
+            .as_ref()
+            .expect("Runtime service client must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+/// Opens a gRPC channel to the CRI runtime service listening on the given Unix
+/// domain socket
+async fn connect(endpoint: PathBuf) -> Result<RuntimeServiceClient, Error> {
+    // The URI is ignored by the connector, but must still be valid
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let endpoint = endpoint.clone();
+            async move { UnixStream::connect(endpoint).await.map(TokioIo::new) }
+        }))
+        .await?;
+    Ok(RuntimeServiceClient::new(channel))
+}
+
+/// Container info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInfo<'a> {
+    id:          &'a str,
+    name:        &'a str,
+    attempt:     Option<u32>,
+    image:       Option<&'a str>,
+    image_ref:   &'a str,
+    created_at:  i64,
+    labels:      &'a BTreeMap<String, String>,
+    annotations: &'a BTreeMap<String, String>,
+    pod:         PodInfo<'a>,
+}
+
+/// Info on the pod sandbox that a container belongs to
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodInfo<'a> {
+    id:        &'a str,
+    uid:       Option<&'a str>,
+    name:      Option<&'a str>,
+    namespace: Option<&'a str>,
+}
+
+impl<'a> ContainerInfo<'a> {
+    /// Extracts all state/metadata from the given container and its pod
+    /// sandbox, and collects it in a single container info struct
+    fn new(c: &'a Container, s: &'a PodSandbox) -> Self {
+        let pod_meta = s.metadata.as_ref();
+        ContainerInfo {
+            id:          &c.id,
+            name:        container_name(c),
+            attempt:     c.metadata.as_ref().map(|m| m.attempt),
+            image:       c.image.as_ref().map(|i| i.image.as_str()),
+            image_ref:   &c.image_ref,
+            created_at:  c.created_at,
+            labels:      &c.labels,
+            annotations: &c.annotations,
+            pod:         PodInfo {
+                id:        &s.id,
+                uid:       pod_meta.map(|m| m.uid.as_str()),
+                name:      pod_meta.map(|m| m.name.as_str()),
+                namespace: pod_meta.map(|m| m.namespace.as_str()),
+            },
+        }
+    }
+}
+
+/// Gets the name of the container from its metadata, falling back to "~"
+fn container_name(container: &Container) -> &str {
+    container
+        .metadata
+        .as_ref()
+        .map(|m| m.name.as_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(NONE_STR)
+}
+
+/// Gets a human-readable representation of the container in the form of
+/// `{pod}/{container}`
+fn display(container: &Container, sandbox: &PodSandbox) -> String {
+    let pod_name = sandbox
+        .metadata
+        .as_ref()
+        .map(|m| m.name.as_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(NONE_STR);
+    format!("{}/{}", pod_name, container_name(container))
+}
@@ -0,0 +1,278 @@
+//! Tests for the containerd provider, run against a fake CRI runtime service
+//! that is served on a Unix domain socket in a temporary directory
+
+use super::cri::{Container, ContainerMetadata, ContainerState, ImageSpec, ListContainersRequest,
+                 ListContainersResponse, ListPodSandboxRequest, ListPodSandboxResponse,
+                 PodSandbox, PodSandboxMetadata, PodSandboxState, VersionRequest, VersionResponse};
+use super::Containerd;
+use crate::polling::providers::Provider;
+use crate::shared::{CollectionEvent, CollectionMethod};
+use crate::shell::Shell;
+use crate::util::{CgroupManager, CgroupVersion};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+
+use tokio_1::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http;
+use tonic::server::{Grpc, NamedService};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tower::Service;
+
+/// Fake CRI runtime service, serving the containers and pod sandboxes that it
+/// holds (applying the state filter of each request)
+#[derive(Clone, Default)]
+struct FakeRuntime {
+    containers: Arc<Mutex<Vec<Container>>>,
+    sandboxes:  Arc<Mutex<Vec<PodSandbox>>>,
+}
+
+impl NamedService for FakeRuntime {
+    const NAME: &'static str = "runtime.v1.RuntimeService";
+}
+
+impl Service<http::Request<BoxBody>> for FakeRuntime {
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+    type Response = http::Response<BoxBody>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let runtime = self.clone();
+        Box::pin(async move {
+            let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+            let response = match method {
+                "Version" => {
+                    let handler = tower::service_fn(|_: Request<VersionRequest>| async {
+                        Ok::<_, Status>(Response::new(VersionResponse {
+                            version:             String::from("0.1.0"),
+                            runtime_name:        String::from("fake"),
+                            runtime_version:     String::from("1.0.0"),
+                            runtime_api_version: String::from("v1"),
+                        }))
+                    });
+                    Grpc::new(ProstCodec::default())
+                        .unary(handler, request)
+                        .await
+                },
+                "ListContainers" => {
+                    let handler = tower::service_fn(|r: Request<ListContainersRequest>| {
+                        let state = r.into_inner().filter.and_then(|f| f.state);
+                        let containers = runtime
+                            .containers
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|c| state.as_ref().is_none_or(|s| s.state == c.state))
+                            .cloned()
+                            .collect();
+                        async {
+                            Ok::<_, Status>(Response::new(ListContainersResponse { containers }))
+                        }
+                    });
+                    Grpc::new(ProstCodec::default())
+                        .unary(handler, request)
+                        .await
+                },
+                "ListPodSandbox" => {
+                    let handler = tower::service_fn(|r: Request<ListPodSandboxRequest>| {
+                        let state = r.into_inner().filter.and_then(|f| f.state);
+                        let items = runtime
+                            .sandboxes
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|s| state.as_ref().is_none_or(|f| f.state == s.state))
+                            .cloned()
+                            .collect();
+                        async { Ok::<_, Status>(Response::new(ListPodSandboxResponse { items })) }
+                    });
+                    Grpc::new(ProstCodec::default())
+                        .unary(handler, request)
+                        .await
+                },
+                _ => Status::unimplemented(method).into_http(),
+            };
+            Ok(response)
+        })
+    }
+}
+
+/// Serves the fake runtime service on a background thread, listening on a new
+/// Unix domain socket at the given path
+fn serve(runtime: FakeRuntime, socket: &Path) {
+    let listener = StdUnixListener::bind(socket).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    thread::spawn(move || {
+        let rt = tokio_1::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let incoming = UnixListenerStream::new(UnixListener::from_std(listener).unwrap());
+            Server::builder()
+                .add_service(runtime)
+                .serve_with_incoming(incoming)
+                .await
+                .unwrap();
+        });
+    });
+}
+
+fn sandbox(id: &str, uid: &str, name: &str, state: PodSandboxState) -> PodSandbox {
+    PodSandbox {
+        id: String::from(id),
+        metadata: Some(PodSandboxMetadata {
+            name:      String::from(name),
+            uid:       String::from(uid),
+            namespace: String::from("default"),
+            attempt:   0,
+        }),
+        state: state as i32,
+        ..PodSandbox::default()
+    }
+}
+
+fn container(id: &str, sandbox: &str, name: &str, state: ContainerState) -> Container {
+    let mut labels = BTreeMap::new();
+    labels.insert(String::from("app"), String::from(name));
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        String::from("io.kubernetes.container.restartCount"),
+        String::from("0"),
+    );
+    Container {
+        id: String::from(id),
+        pod_sandbox_id: String::from(sandbox),
+        metadata: Some(ContainerMetadata {
+            name:    String::from(name),
+            attempt: 0,
+        }),
+        image: Some(ImageSpec {
+            image: String::from("docker.io/library/nginx:latest"),
+        }),
+        state: state as i32,
+        labels,
+        annotations,
+        ..Container::default()
+    }
+}
+
+#[test]
+fn poll_emits_start_and_stop_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("containerd.sock");
+    let cgroups = dir.path().join("cgroup");
+
+    let runtime = FakeRuntime::default();
+    *runtime.sandboxes.lock().unwrap() = vec![
+        sandbox(
+            "sandbox-a",
+            "1234-5678",
+            "web",
+            PodSandboxState::SandboxReady,
+        ),
+        sandbox(
+            "sandbox-b",
+            "8765-4321",
+            "db",
+            PodSandboxState::SandboxNotready,
+        ),
+    ];
+    *runtime.containers.lock().unwrap() = vec![
+        container(
+            "app",
+            "sandbox-a",
+            "nginx",
+            ContainerState::ContainerRunning,
+        ),
+        container(
+            "late",
+            "sandbox-a",
+            "sidecar",
+            ContainerState::ContainerRunning,
+        ),
+        container(
+            "exited",
+            "sandbox-a",
+            "init",
+            ContainerState::ContainerExited,
+        ),
+        container(
+            "unready",
+            "sandbox-b",
+            "postgres",
+            ContainerState::ContainerRunning,
+        ),
+    ];
+    serve(runtime.clone(), &socket);
+
+    // Only the first running container in the ready sandbox has a cgroup so far
+    let pod = cgroups
+        .join("kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234_5678.slice");
+    fs::create_dir_all(pod.join("cri-containerd-app.scope")).unwrap();
+
+    let mut provider = Containerd::new();
+    provider.shell = Some(Arc::new(Shell::from_write(
+        Box::new(io::sink()),
+        Box::new(io::sink()),
+    )));
+    provider.cgroup_manager = CgroupManager::with_root(cgroups, CgroupVersion::V2);
+    provider.connect(socket).unwrap();
+
+    let events = provider.poll().unwrap();
+    assert_eq!(events.len(), 1);
+    match &events[0] {
+        CollectionEvent::Start { target, method } => {
+            assert_eq!(target.id, "app");
+            assert_eq!(target.name, "web/nginx");
+            assert_eq!(target.provider, "containerd");
+            match method {
+                CollectionMethod::LinuxCgroupsV2(cgroup) => {
+                    assert!(cgroup.path.ends_with("cri-containerd-app.scope"));
+                },
+                other => panic!("unexpected collection method {:?}", other),
+            }
+
+            let metadata = target.metadata.as_ref().unwrap();
+            assert_eq!(metadata["Labels"]["app"].as_str(), Some("nginx"));
+            assert_eq!(
+                metadata["Annotations"]["io.kubernetes.container.restartCount"].as_str(),
+                Some("0")
+            );
+            assert_eq!(metadata["Pod"]["Uid"].as_str(), Some("1234-5678"));
+            assert_eq!(metadata["Pod"]["Namespace"].as_str(), Some("default"));
+        },
+        CollectionEvent::Stop(id) => panic!("expected a Start event, got Stop({})", id),
+    }
+
+    // Nothing changed, so there are no new events
+    assert_eq!(provider.poll().unwrap(), Vec::new());
+
+    // Containers whose cgroup didn't exist yet are started once it appears
+    fs::create_dir_all(pod.join("cri-containerd-late.scope")).unwrap();
+    match provider.poll().unwrap().as_slice() {
+        [CollectionEvent::Start { target, .. }] => assert_eq!(target.id, "late"),
+        other => panic!("expected a single Start event, got {:?}", other),
+    }
+
+    runtime.containers.lock().unwrap().retain(|c| c.id != "app");
+    assert_eq!(provider.poll().unwrap(), vec![CollectionEvent::Stop(
+        String::from("app")
+    )]);
+}
@@ -1,26 +1,50 @@
 use crate::cli::RunCommand;
+use crate::polling::filter::{Candidate, TargetFilter};
 use crate::polling::providers::{InitializationError, Provider};
 use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
 use crate::shell::Shell;
 use crate::util::{self, CgroupManager, CgroupPath, ItemPool};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::iter;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use failure::Error;
 use futures_01::future::Future;
-use shiplift::builder::ContainerListOptions;
-use shiplift::rep::Container;
+use futures_01::stream::Stream;
+use shiplift::builder::{ContainerListOptions, EventFilter, EventFilterType, EventsOptions};
+use shiplift::rep::{Container, ContainerDetails, Event};
 use tokio_01::runtime::current_thread::Runtime;
 
 const PROVIDER_TYPE: &str = "docker";
 
+/// Container actions in the Docker event stream that are subscribed to
+const EVENT_ACTIONS: &[&str] = &["start", "die", "destroy"];
+
+/// Delay before re-subscribing to the Docker event stream after it ends
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
 pub struct Docker {
+    tracker: Arc<Mutex<ContainerTracker>>,
+    client:  shiplift::Docker,
+    shell:   Option<Arc<Shell>>,
+    runtime: RefCell<Runtime>,
+    events:  bool,
+    /// Sender to the collection thread, if the event stream was subscribed to
+    tx:      Option<Sender<CollectionEvent>>,
+}
+
+/// State shared between polling and the event stream thread. Generating events
+/// and updating the pool happen while holding the lock, so that the events of
+/// both are sent in the same order that they were applied to the pool. The
+/// event stream thread doesn't hold the lock while requesting the Docker API
+struct ContainerTracker {
     container_id_pool: ItemPool<String>,
     cgroup_manager:    CgroupManager,
-    client:            shiplift::Docker,
-    shell:             Option<Arc<Shell>>,
-    runtime:           RefCell<Runtime>,
+    filter:            TargetFilter,
 }
 
 /// Possible errors that can occur during Docker provider initialization
@@ -28,6 +52,7 @@ pub struct Docker {
 enum DockerInitError {
     ConnectionFailed(shiplift::Error),
     InvalidCgroupMount,
+    UnsupportedNamespaceFilter,
 }
 
 impl Into<InitializationError> for DockerInitError {
@@ -44,6 +69,13 @@ impl Into<InitializationError> for DockerInitError {
                 original:   None,
                 suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
             },
+            Self::UnsupportedNamespaceFilter => InitializationError {
+                original:   None,
+                suggestion: String::from(
+                    "--namespace isn't supported by the docker provider, since Docker containers \
+                     don't belong to a namespace",
+                ),
+            },
         }
     }
 }
@@ -59,11 +91,20 @@ enum StartCollectionError {
 impl Provider for Docker {
     fn initialize(
         &mut self,
-        _opts: &RunCommand,
+        opts: &RunCommand,
         shell: Arc<Shell>,
     ) -> Result<(), InitializationError> {
         self.shell = Some(Arc::clone(&shell));
         self.shell().status("Initializing", "Docker API provider");
+        let inner_opts = opts.provider.clone().into_inner_docker();
+        self.events = inner_opts.events;
+
+        // Every container would be excluded by a namespace filter
+        if !inner_opts.filter.namespaces.is_empty() {
+            return Err(DockerInitError::UnsupportedNamespaceFilter.into());
+        }
+        self.tracker.lock().unwrap().filter =
+            TargetFilter::new(&inner_opts.filter).map_err(Into::into)?;
 
         match self.try_init() {
             Ok(_) => Ok(()),
@@ -72,6 +113,9 @@ impl Provider for Docker {
     }
 
     fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        // Hold the lock while listing so that the event stream doesn't change the
+        // pool between listing and reconciling
+        let mut tracker = self.tracker.lock().unwrap();
         let future = self
             .client
             .containers()
@@ -82,7 +126,7 @@ impl Provider for Docker {
         let to_collect: BTreeMap<String, Container> = containers
             .into_iter()
             .filter_map(|c| {
-                if should_collect_stats(&c) {
+                if tracker.should_collect_stats(&c) {
                     Some((c.id.clone(), c))
                 } else {
                     None
@@ -92,7 +136,7 @@ impl Provider for Docker {
 
         let ids = to_collect.keys().map(String::clone);
         let mut events: Vec<CollectionEvent> = Vec::new();
-        let (added, removed) = self.container_id_pool.update(ids);
+        let (added, removed) = tracker.container_id_pool.update(ids);
 
         let removed_len = removed.len();
         events.reserve_exact(added.len() + removed_len);
@@ -117,26 +161,10 @@ impl Provider for Docker {
                     },
                 };
 
-                match self.make_start_event(container) {
+                match tracker.make_start_event(container, self.shell()) {
                     Ok(start) => Some(start),
                     Err(error) => {
-                        let container_display = display(container);
-                        match error {
-                            StartCollectionError::CgroupNotFound => {
-                                self.shell().warn(format!(
-                                    "Could not create container metadata for container {}: cgroup \
-                                     path could not be constructed or does not exist",
-                                    container_display
-                                ));
-                            },
-                            StartCollectionError::MetadataSerializationError(cause) => {
-                                self.shell().warn(format!(
-                                    "Could not serialize container metadata: {}",
-                                    cause
-                                ));
-                            },
-                        }
-
+                        report_start_error(self.shell(), container, error);
                         // Ignore container and continue initializing the rest
                         None
                     },
@@ -158,8 +186,36 @@ impl Provider for Docker {
             });
         }
 
+        // When streaming, send the events while still holding the lock to keep
+        // them ordered with the events from the event stream thread
+        if let Some(tx) = &self.tx {
+            for event in events {
+                tx.send(event)?;
+            }
+            return Ok(Vec::with_capacity(0));
+        }
+
         Ok(events)
     }
+
+    fn stream(&mut self, tx: &Sender<CollectionEvent>) {
+        if !self.events {
+            return;
+        }
+
+        self.tx = Some(tx.clone());
+        let tracker = Arc::clone(&self.tracker);
+        let shell = Arc::clone(
+            self.shell
+                .as_ref()
+                .expect("Shell must be initialized: invariant violated"),
+        );
+        let tx = tx.clone();
+        thread::Builder::new()
+            .name(String::from("poll-events"))
+            .spawn(move || run_event_stream(&tracker, &shell, &tx))
+            .unwrap();
+    }
 }
 
 impl Default for Docker {
@@ -174,11 +230,12 @@ impl Docker {
         // (emulating synchronous I/O)
         let runtime = Runtime::new().unwrap();
         Self {
-            container_id_pool: ItemPool::new(),
-            cgroup_manager:    CgroupManager::new(),
-            client:            shiplift::Docker::new(),
-            shell:             None,
-            runtime:           RefCell::new(runtime),
+            tracker: Arc::new(Mutex::new(ContainerTracker::new())),
+            client:  shiplift::Docker::new(),
+            shell:   None,
+            runtime: RefCell::new(runtime),
+            events:  false,
+            tx:      None,
         }
     }
 
@@ -202,17 +259,55 @@ impl Docker {
         if !util::cgroups_mounted_properly() {
             return Err(DockerInitError::InvalidCgroupMount);
         }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
 
         Ok(())
     }
 
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+impl ContainerTracker {
+    fn new() -> Self {
+        Self {
+            container_id_pool: ItemPool::new(),
+            cgroup_manager:    CgroupManager::new(),
+            filter:            TargetFilter::default(),
+        }
+    }
+
+    /// Whether radvisor should collect statistics for the given container,
+    /// according to the target filter
+    fn should_collect_stats(&self, c: &Container) -> bool {
+        let candidate = Candidate {
+            name:      display(c).trim_start_matches('/'),
+            labels:    c
+                .labels
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+            namespace: None,
+            images:    vec![c.image.as_str()],
+        };
+        self.filter.matches(&candidate)
+    }
+
     /// Converts a container to a collection start event, preparing all
     /// serialization/cgroup checks needed
     fn make_start_event(
         &mut self,
         container: &Container,
+        shell: &Shell,
     ) -> Result<CollectionEvent, StartCollectionError> {
-        let method = self.get_collection_method(container)?;
+        let method = self.get_collection_method(container, shell)?;
         let metadata = match serde_yaml::to_value(container) {
             Ok(metadata) => metadata,
             Err(err) => {
@@ -229,6 +324,7 @@ impl Docker {
                 metadata:  Some(metadata),
                 name:      container.names.get(0).unwrap_or(&container.id).clone(),
                 poll_time: util::nano_ts(),
+                filter:    self.filter.expression(),
                 id:        container.id.clone(),
             },
         })
@@ -239,17 +335,17 @@ impl Docker {
     fn get_collection_method(
         &mut self,
         container: &Container,
+        shell: &Shell,
     ) -> Result<CollectionMethod, StartCollectionError> {
-        // Only one type of CollectionMethod currently
-        match self.get_cgroup(container) {
-            Some(cgroup) => Ok(CollectionMethod::LinuxCgroups(cgroup)),
+        match self.get_cgroup(container, shell) {
+            Some(cgroup) => Ok(CollectionMethod::from(cgroup)),
             None => Err(StartCollectionError::CgroupNotFound),
         }
     }
 
     /// Gets the group path for the given container, printing out a
     /// message upon the first successful cgroup resolution
-    fn get_cgroup(&mut self, c: &Container) -> Option<CgroupPath> {
+    fn get_cgroup(&mut self, c: &Container, shell: &Shell) -> Option<CgroupPath> {
         // Determine if the manager had a resolved group beforehand
         let had_driver = self.cgroup_manager.driver().is_some();
 
@@ -266,26 +362,204 @@ impl Docker {
 
         if !had_driver {
             if let Some(driver) = self.cgroup_manager.driver() {
-                self.shell()
-                    .info(format!("Identified {} as cgroup driver", driver));
+                shell.info(format!("Identified {} as cgroup driver", driver));
             }
         }
 
         cgroup_option
     }
 
-    /// Gets a reference to the current shell
-    fn shell(&self) -> &Shell {
-        self.shell
-            .as_ref()
-            .expect("Shell must be initialized: invariant violated")
+    /// Handles a start event from the Docker event stream for the given
+    /// (inspected) container, adding it to the pool and generating a start
+    /// collection event if it should be collected and isn't already
+    fn handle_start(&mut self, container: &Container, shell: &Shell) -> Option<CollectionEvent> {
+        // The container may have been added by polling while it was inspected
+        if self.container_id_pool.contains(&container.id) || !self.should_collect_stats(container) {
+            return None;
+        }
+
+        match self.make_start_event(container, shell) {
+            Ok(start) => {
+                self.container_id_pool.insert(container.id.clone());
+                shell.verbose(|sh| {
+                    sh.info(format!(
+                        "Received start event for container {} from the Docker API",
+                        display(container)
+                    ))
+                });
+                Some(start)
+            },
+            Err(error) => {
+                report_start_error(shell, container, error);
+                None
+            },
+        }
+    }
+
+    /// Handles a die/destroy event from the Docker event stream, removing the
+    /// container from the pool and generating a stop collection event if it
+    /// was being collected
+    fn handle_stop(&mut self, event: &Event, shell: &Shell) -> Option<CollectionEvent> {
+        let id = &event.actor.id;
+        if !self.container_id_pool.remove(id) {
+            return None;
+        }
+
+        shell.verbose(|sh| {
+            sh.info(format!(
+                "Received {} event for container {} from the Docker API",
+                event.action, id
+            ))
+        });
+        Some(CollectionEvent::Stop(id.clone()))
     }
 }
 
-/// Whether radvisor should collect statistics for the given container
-/// TODO investigate more stringent checks
-#[allow(clippy::missing_const_for_fn)]
-fn should_collect_stats(_c: &Container) -> bool { true }
+/// Thread function that subscribes to the container events in the Docker event
+/// stream, sending collection events as soon as containers start and die.
+/// Re-subscribes (replaying missed events) if the stream ends, and exits once
+/// the collection thread stops receiving events
+fn run_event_stream(
+    tracker: &Mutex<ContainerTracker>,
+    shell: &Shell,
+    tx: &Sender<CollectionEvent>,
+) {
+    let mut runtime = Runtime::new().unwrap();
+    let client = shiplift::Docker::new();
+    let mut since: Option<u64> = None;
+
+    loop {
+        let mut builder = EventsOptions::builder();
+        let mut filters = vec![EventFilter::Type(EventFilterType::Container)];
+        filters.extend(
+            EVENT_ACTIONS
+                .iter()
+                .map(|&action| EventFilter::Event(String::from(action))),
+        );
+        builder.filter(filters);
+        if let Some(time) = &since {
+            builder.since(time);
+        }
+
+        shell.verbose(|sh| sh.info("Subscribing to container events from the Docker API"));
+        let mut stream = client.events(&builder.build());
+        loop {
+            match runtime.block_on(stream.into_future()) {
+                Ok((Some(event), rest)) => {
+                    stream = rest;
+                    since = Some(util::remap::<_, u64>(event.time.timestamp()));
+                    let collection_event = match event.action.as_str() {
+                        "start" => {
+                            if tracker
+                                .lock()
+                                .unwrap()
+                                .container_id_pool
+                                .contains(&event.actor.id)
+                            {
+                                continue;
+                            }
+
+                            // Inspect the container without holding the lock, so that polling
+                            // isn't blocked on the request
+                            match inspect_container(&event.actor.id, &mut runtime, &client, shell) {
+                                Some(container) => {
+                                    tracker.lock().unwrap().handle_start(&container, shell)
+                                },
+                                None => None,
+                            }
+                        },
+                        _ => tracker.lock().unwrap().handle_stop(&event, shell),
+                    };
+                    if let Some(collection_event) = collection_event {
+                        if tx.send(collection_event).is_err() {
+                            // The collection thread has stopped
+                            return;
+                        }
+                    }
+                },
+                Ok((None, _)) => {
+                    shell.warn("Docker event stream ended; re-subscribing");
+                    break;
+                },
+                Err((err, _)) => {
+                    shell.warn(format!(
+                        "Could not read from the Docker event stream: {}",
+                        err
+                    ));
+                    break;
+                },
+            }
+        }
+
+        thread::sleep(RESUBSCRIBE_DELAY);
+    }
+}
+
+/// Inspects a single container through the Docker API, converting its details
+/// to the summary that listing containers returns (so that started containers
+/// have the same metadata whether they were polled or received as an event).
+/// Inspecting also finds containers that have already exited, such as
+/// short-lived ones
+fn inspect_container(
+    id: &str,
+    runtime: &mut Runtime,
+    client: &shiplift::Docker,
+    shell: &Shell,
+) -> Option<Container> {
+    match runtime.block_on(client.containers().get(id).inspect()) {
+        Ok(details) => Some(summarize(details)),
+        Err(err) => {
+            shell.warn(format!(
+                "Could not inspect container {} from the Docker API: {}",
+                id, err
+            ));
+            None
+        },
+    }
+}
+
+/// Converts the details of an inspected container to a container summary. Port
+/// mappings and sizes aren't included in the details, so they are left empty
+fn summarize(details: ContainerDetails) -> Container {
+    let command = iter::once(details.path)
+        .chain(details.args)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let status = if details.state.running {
+        "running"
+    } else {
+        "exited"
+    };
+    Container {
+        created: details.created,
+        command,
+        id: details.id,
+        image: details.config.image,
+        labels: details.config.labels.unwrap_or_default(),
+        names: vec![details.name],
+        ports: Vec::new(),
+        status: String::from(status),
+        size_rw: None,
+        size_root_fs: None,
+    }
+}
+
+/// Prints a warning for a container that collection couldn't be started for
+fn report_start_error(shell: &Shell, container: &Container, error: StartCollectionError) {
+    let container_display = display(container);
+    match error {
+        StartCollectionError::CgroupNotFound => {
+            shell.warn(format!(
+                "Could not create container metadata for container {}: cgroup path could not be \
+                 constructed or does not exist",
+                container_display
+            ));
+        },
+        StartCollectionError::MetadataSerializationError(cause) => {
+            shell.warn(format!("Could not serialize container metadata: {}", cause));
+        },
+    }
+}
 
 /// Gets a human-readable representation of the container, attempting to use the
 /// name before using the Id as a fallback
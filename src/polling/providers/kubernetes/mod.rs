@@ -0,0 +1,952 @@
+#[cfg(test)]
+mod tests;
+
+use crate::cli::RunCommand;
+use crate::polling::filter::{Candidate, TargetFilter};
+use crate::polling::providers::{InitializationError, KubernetesOptions, Provider};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupDriver, CgroupManager, CgroupPath, ItemPool};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::Error;
+use futures_03::stream::StreamExt;
+use gethostname::gethostname;
+use k8s_openapi::api::core::v1::{ContainerStatus, Node, Pod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::{Api, ListParams, Meta};
+use kube::client::Client;
+use kube::config;
+use kube_runtime::reflector::{self, store, Store};
+use kube_runtime::watcher;
+use serde::Serialize;
+use strum_macros::{EnumString, IntoStaticStr};
+
+/// String representation for "None"
+const NONE_STR: &str = "~";
+
+/// Root cgroup for kubernetes pods to fall under
+const ROOT_CGROUP: &str = "kubepods";
+
+/// Pod phases for pods whose containers have all terminated, which no longer
+/// have a cgroup
+const TERMINATED_PHASES: &[&str] = &["Succeeded", "Failed"];
+
+/// Delay before continuing to watch pods after the watch fails
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const PROVIDER_TYPE: &str = "kubernetes";
+
+pub struct Kubernetes {
+    tracker:     Arc<Mutex<PodTracker>>,
+    runtime:     RefCell<tokio_02::runtime::Runtime>,
+    pod_client:  Option<Api<Pod>>,
+    node_client: Option<Api<Node>>,
+    /// Cache of the pods on the current node, kept up to date by the pod watch
+    pod_store:   Option<Store<Pod>>,
+    shell:       Option<Arc<Shell>>,
+    hostname:    Option<String>,
+    node_name:   Option<String>,
+    /// Sender to the collection thread, if the pod watch was started
+    tx:          Option<Sender<CollectionEvent>>,
+}
+
+/// State shared between polling and the pod watch thread. Generating events and
+/// updating the pool happen while holding the lock, so that the events of both
+/// are sent in the same order that they were applied to the pool
+struct PodTracker {
+    cgroup_manager: CgroupManager,
+    /// Kinds of targets to create for each pod
+    kinds:          Vec<TargetKind>,
+    filter:         TargetFilter,
+    /// Ids of all pods (uids) and containers that collection was started for
+    target_pool:    ItemPool<String>,
+    /// Ids of all targets that are waiting for their cgroup to be created
+    pending:        BTreeSet<String>,
+}
+
+/// Possible errors that can occur during Kubernetes provider initialization
+#[derive(Debug)]
+enum KubernetesInitError {
+    InvalidCgroupMount,
+    InvalidHostnameError(std::ffi::OsString),
+    ConfigLoadError(Error),
+    NodeDetectionError,
+    NodeFetchError(Error),
+    MissingNodeNameError,
+}
+
+impl Into<InitializationError> for KubernetesInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+            Self::InvalidHostnameError(hostname) => InitializationError {
+                original:   None,
+                suggestion: format!(
+                    "Could not retrieve hostname to use for node detection: Invalid string '{:?}' \
+                     returned",
+                    hostname
+                ),
+            },
+            Self::ConfigLoadError(error) => InitializationError {
+                original:   Some(error),
+                suggestion: String::from(
+                    "Could not load kubernetes config. Make sure the current machine is a part of \
+                     a cluster \nand has the cluster configuration copied to the config directory.",
+                ),
+            },
+            Self::NodeDetectionError => InitializationError {
+                original:   None,
+                suggestion: String::from(
+                    "Could not get the current node via the Kubernetes API. \nMake sure the \
+                     current machine is running its own node.",
+                ),
+            },
+            Self::NodeFetchError(error) => InitializationError {
+                original:   Some(error),
+                suggestion: String::from("Could not get list of nodes in the Kubernetes cluster"),
+            },
+            Self::MissingNodeNameError => InitializationError {
+                original:   None,
+                suggestion: String::from(
+                    "The node running on the current host lacks a Name field. \nThe pod polling \
+                     cannot function without this.",
+                ),
+            },
+        }
+    }
+}
+
+/// Quality of service classes for pods. For more information, see
+/// [the Kubernetes docs](https://kubernetes.io/docs/tasks/configure-pod-container/quality-service-pod/#qos-classes)
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq, Serialize)]
+#[strum(serialize_all = "lowercase")]
+enum QualityOfService {
+    BestEffort,
+    Guaranteed,
+    Burstable,
+}
+
+impl QualityOfService {
+    /// Attempts to extract the quality of service value from a pod's status
+    fn from_pod(pod: &Pod) -> Option<Self> {
+        pod.status
+            .as_ref()
+            .and_then(|status| status.qos_class.as_ref())
+            // Use strum_macro's `EnumString::from_str` implementation here
+            .and_then(|qos| Self::from_str(&qos.to_lowercase()).ok())
+    }
+}
+
+/// Kind of collection target to create for the pods on the current node
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq, Serialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    /// Each pod, using the pod cgroup that contains all of its containers
+    Pod,
+    /// Each running container, using its own cgroup under the pod cgroup
+    Container,
+}
+
+/// Collection target for either an entire pod or a single container in a pod
+#[derive(Clone, Copy, Debug)]
+enum Target<'a> {
+    Pod(&'a Pod),
+    Container(&'a Pod, &'a ContainerStatus),
+}
+
+impl<'a> Target<'a> {
+    /// Gets the pod that the target belongs to
+    const fn pod(self) -> &'a Pod {
+        match self {
+            Self::Pod(pod) | Self::Container(pod, _) => pod,
+        }
+    }
+
+    /// Gets the display name of the target
+    fn name(self) -> String {
+        match self {
+            Self::Pod(pod) => name(pod).to_owned(),
+            Self::Container(pod, container) => format!("{}/{}", name(pod), container.name),
+        }
+    }
+}
+
+/// Container runtime that created a container, included as the scheme of the
+/// container Id in the container's status (such as `containerd://<id>`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ContainerRuntime {
+    Docker,
+    Containerd,
+    CriO,
+}
+
+impl ContainerRuntime {
+    /// Attempts to parse a container Id from a container's status into the
+    /// runtime and the bare container Id
+    fn parse(container_id: &str) -> Option<(Self, &str)> {
+        let (scheme, id) = container_id.split_once("://")?;
+        let runtime = match scheme {
+            "docker" => Self::Docker,
+            "containerd" => Self::Containerd,
+            "cri-o" => Self::CriO,
+            _ => return None,
+        };
+        Some((runtime, id))
+    }
+
+    /// Gets the name of the container's cgroup, which is a child of the pod
+    /// cgroup
+    fn cgroup_name(self, id: &str, driver: CgroupDriver) -> String {
+        match (driver, self) {
+            (CgroupDriver::Systemd, Self::Docker) => format!("docker-{}.scope", id),
+            (CgroupDriver::Systemd, Self::Containerd) => format!("cri-containerd-{}.scope", id),
+            (CgroupDriver::Systemd, Self::CriO) => format!("crio-{}.scope", id),
+            (CgroupDriver::Cgroupfs, Self::CriO) => format!("crio-{}", id),
+            (CgroupDriver::Cgroupfs, Self::Docker | Self::Containerd) => id.to_owned(),
+        }
+    }
+}
+
+/// Possible error that can occur during Kubernetes pod collection target
+/// initialization
+#[derive(Debug)]
+enum StartCollectionError {
+    MetadataSerializationError(Error),
+    CgroupNotFound,
+    MissingPodUid,
+    FailedQosParse,
+    UnknownContainerRuntime(String),
+}
+
+impl StartCollectionError {
+    fn display(&self, target: Target) -> String {
+        let pod = target.pod();
+        let pod_display: &str = name_option(pod)
+            .or_else(|| uid_option(pod))
+            .unwrap_or(NONE_STR);
+        match self {
+            Self::CgroupNotFound => format!(
+                "Could not create pod metadata for pod {}: cgroup path could not be constructed \
+                 or does not exist",
+                pod_display
+            ),
+            Self::MetadataSerializationError(cause) => format!(
+                "Could not serialize metadata for pod {}: {}",
+                pod_display, cause
+            ),
+            Self::MissingPodUid => format!(
+                "Could not get uid for node {}! This shouldn't happen",
+                pod_display
+            ),
+            Self::FailedQosParse => format!(
+                "Could not parse quality of service class for pod {}: invalid value '{}'",
+                pod_display,
+                pod.status
+                    .as_ref()
+                    .and_then(|s| s.qos_class.as_deref())
+                    .unwrap_or(NONE_STR)
+            ),
+            Self::UnknownContainerRuntime(container_id) => format!(
+                "Could not resolve cgroup for container {}: unknown container runtime in \
+                 container Id '{}'",
+                target.name(),
+                container_id
+            ),
+        }
+    }
+}
+
+impl Provider for Kubernetes {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell()
+            .status("Initializing", "Kubernetes API provider");
+
+        let inner_opts: KubernetesOptions = opts.provider.clone().into_inner_kubernetes();
+        let mut tracker = self.tracker.lock().unwrap();
+        tracker.kinds = inner_opts.targets;
+        tracker.filter = TargetFilter::new(&inner_opts.filter).map_err(Into::into)?;
+        drop(tracker);
+
+        match self.try_init(inner_opts.kube_config) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        let pods = self.get_pods()?;
+        let mut tracker = self.tracker.lock().unwrap();
+        let events = tracker.reconcile(pods, self.shell());
+
+        // When watching, send the events while still holding the lock to keep
+        // them ordered with the events from the pod watch thread
+        if let Some(tx) = &self.tx {
+            for event in events {
+                tx.send(event)?;
+            }
+            return Ok(Vec::with_capacity(0));
+        }
+
+        Ok(events)
+    }
+
+    fn stream(&mut self, tx: &Sender<CollectionEvent>) {
+        let watch = PodWatch::new(
+            self.pod_client().clone(),
+            self.list_params(),
+            Arc::clone(&self.tracker),
+            Arc::clone(
+                self.shell
+                    .as_ref()
+                    .expect("Shell must be initialized: invariant violated"),
+            ),
+            tx.clone(),
+        );
+        self.pod_store = Some(watch.pod_store.clone());
+        self.tx = Some(tx.clone());
+
+        thread::Builder::new()
+            .name(String::from("poll-watch"))
+            .spawn(move || watch.run())
+            .unwrap();
+    }
+}
+
+impl Default for Kubernetes {
+    fn default() -> Self { Self::new() }
+}
+
+impl Kubernetes {
+    #[must_use]
+    pub fn new() -> Self {
+        // Use a single-threaded runtime so that Tokio doesn't create
+        // a thread pool and instead executes futures in the current thread
+        // (emulating synchronous I/O)
+        let runtime = tokio_02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        Self {
+            tracker:     Arc::new(Mutex::new(PodTracker::new())),
+            runtime:     RefCell::new(runtime),
+            pod_client:  None,
+            node_client: None,
+            pod_store:   None,
+            hostname:    None,
+            node_name:   None,
+            shell:       None,
+            tx:          None,
+        }
+    }
+
+    /// Executes a future on the internal runtime, blocking the current thread
+    /// until it completes
+    fn exec<F: Future>(&self, future: F) -> F::Output {
+        let mut rt = self.runtime.borrow_mut();
+        rt.block_on(future)
+    }
+
+    /// Attempts to initialize the Kubernetes provider, failing if one of the
+    /// following conditions happens:
+    ///   1. Invalid hostname from system
+    ///   2. Can't load Kubernetes config from filesystem
+    ///   3. Cgroups mounted unexpectedly/improperly
+    ///   4. API server/Node can't be communicated with
+    fn try_init(&mut self, kube_config: Option<PathBuf>) -> Result<(), KubernetesInitError> {
+        if !util::cgroups_mounted_properly() {
+            return Err(KubernetesInitError::InvalidCgroupMount);
+        }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
+
+        // Load the config using the given kubeconfig file if given,
+        // otherwise use the standard series of potential sources
+        let config_load_result = match kube_config {
+            None => self.exec(config::Config::infer()),
+            Some(kube_config) => self.exec(config::Config::from_custom_kubeconfig(
+                config::Kubeconfig::read_from(kube_config)
+                    .map_err(|err| KubernetesInitError::ConfigLoadError(Error::from(err)))?,
+                &config::KubeConfigOptions::default(),
+            )),
+        };
+        let config = config_load_result
+            .map_err(|err| KubernetesInitError::ConfigLoadError(Error::from(err)))?;
+
+        // Initialize the API clients
+        let client = Client::new(config);
+        self.pod_client = Some(Api::all(client.clone()));
+        self.node_client = Some(Api::all(client));
+
+        // Load the hostname of the machine
+        let hostname = gethostname()
+            .into_string()
+            .map_err(KubernetesInitError::InvalidHostnameError)?;
+        self.hostname = Some(hostname);
+
+        // Get current node by hostname and store in provider
+        let node = self.get_current_node()?;
+        let node_name = name_option(&node).ok_or(KubernetesInitError::MissingNodeNameError)?;
+        self.node_name = Some(String::from(node_name));
+
+        Ok(())
+    }
+
+    /// Tries to get the current Node from the kubernetes API by its hostname
+    fn get_current_node(&self) -> Result<Node, KubernetesInitError> {
+        // Attempt to find a node with the kubernetes.io/hostname label set
+        let lp =
+            ListParams::default().labels(&format!("kubernetes.io/hostname={}", self.hostname()));
+        let future = self.node_client().list(&lp);
+        self.exec(future)
+            .map_err(|err| KubernetesInitError::NodeFetchError(Error::from(err)))?
+            .into_iter()
+            .next()
+            .ok_or(KubernetesInitError::NodeDetectionError)
+    }
+
+    /// Tries to get all pods that are running on the current node, reading the
+    /// cache kept up to date by the pod watch if it was started. Otherwise,
+    /// polls the Kubernetes API backend to get a fresh list
+    fn get_pods(&self) -> Result<Vec<Pod>, Error> {
+        if let Some(pod_store) = &self.pod_store {
+            return Ok(pod_store.state());
+        }
+
+        let lp = self.list_params();
+        let future = self.pod_client().list(&lp);
+        let pods = self.exec(future)?.into_iter().collect::<Vec<_>>();
+        Ok(pods)
+    }
+
+    /// Gets the list parameters that select all pods scheduled to the current
+    /// node
+    fn list_params(&self) -> ListParams {
+        ListParams::default().fields(&format!("spec.nodeName={}", self.node_name()))
+    }
+
+    /// Gets the current node's hostname
+    fn hostname(&self) -> &str {
+        self.hostname
+            .as_ref()
+            .expect("Hostname must be initialized: invariant violated")
+    }
+
+    /// Gets the current node's name
+    fn node_name(&self) -> &str {
+        self.node_name
+            .as_ref()
+            .expect("Node name must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current pod client
+    fn pod_client(&self) -> &Api<Pod> {
+        self.pod_client
+            .as_ref()
+            .expect("Pod client must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current node client
+    fn node_client(&self) -> &Api<Node> {
+        self.node_client
+            .as_ref()
+            .expect("Node client must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+impl PodTracker {
+    fn new() -> Self {
+        Self {
+            cgroup_manager: CgroupManager::new(),
+            kinds:          Vec::new(),
+            filter:         TargetFilter::default(),
+            target_pool:    ItemPool::new(),
+            pending:        BTreeSet::new(),
+        }
+    }
+
+    /// Reconciles the pool of collected targets with the given list of all pods
+    /// on the current node, generating the corresponding collection events.
+    /// Targets whose cgroup doesn't exist yet are left out of the pool, so that
+    /// collection starts as soon as their cgroup appears
+    fn reconcile(&mut self, pods: Vec<Pod>, shell: &Shell) -> Vec<CollectionEvent> {
+        let original_num = pods.len();
+        let pods = pods
+            .into_iter()
+            .filter(|p| !is_terminated(p))
+            .collect::<Vec<_>>();
+        let targets_map: BTreeMap<String, Target> = pods
+            .iter()
+            .flat_map(|p| self.targets(p))
+            .collect::<BTreeMap<_, _>>();
+
+        let ids = targets_map.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.target_pool.update(ids);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed.len());
+        // Add all removed Ids as Stop events
+        events.extend(removed.into_iter().map(CollectionEvent::Stop));
+
+        // Add all added Ids as Start events
+        let start_events = added
+            .into_iter()
+            .flat_map(|id| {
+                // It shouldn't be possible to have an Id that doesn't exist in the map, but
+                // check anyways
+                let target: Target = match targets_map.get(id.as_str()) {
+                    Some(target) => *target,
+                    None => {
+                        shell.error(format!(
+                            "Processed Id from ItemPool added result that was not in fetched pod \
+                             list. This is a bug!\nId: {}",
+                            id
+                        ));
+                        return None;
+                    },
+                };
+
+                match self.make_start_event(&id, target, shell) {
+                    Ok(start) => {
+                        self.pending.remove(&id);
+                        Some(start)
+                    },
+                    Err(StartCollectionError::CgroupNotFound) => {
+                        // Retry once the cgroup has been created
+                        self.target_pool.remove(&id);
+                        if self.pending.insert(id) {
+                            shell.verbose(|sh| {
+                                sh.info(format!("Waiting for the cgroup of {}", target.name()))
+                            });
+                        }
+                        None
+                    },
+                    Err(error) => {
+                        shell.warn(error.display(target));
+                        // Ignore target and continue initializing the rest
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        // Stop waiting for targets that were removed before their cgroup appeared
+        self.pending.retain(|id| targets_map.contains_key(id));
+
+        if processed_num != 0 || removed_len != 0 {
+            shell.verbose(|sh| {
+                sh.info(format!(
+                    "Received {} -> {} (+{}, -{}) containers from the Kubernetes API",
+                    original_num,
+                    targets_map.len(),
+                    processed_num,
+                    removed_len
+                ))
+            });
+        }
+
+        events
+    }
+
+    /// Gets all collection targets for the given pod that match the target
+    /// filter, keyed by their Id: the pod's uid for the pod itself, and the
+    /// (bare) container Id for each of its running containers
+    fn targets<'a>(&self, pod: &'a Pod) -> Vec<(String, Target<'a>)> {
+        let mut targets = Vec::new();
+        if self.kinds.contains(&TargetKind::Pod) {
+            if let Some(uid) = uid_option(pod) {
+                if self.should_collect_stats(Target::Pod(pod)) {
+                    targets.push((uid.to_owned(), Target::Pod(pod)));
+                }
+            }
+        }
+
+        if self.kinds.contains(&TargetKind::Container) {
+            for container in running_containers(pod) {
+                let target = Target::Container(pod, container);
+                if !self.should_collect_stats(target) {
+                    continue;
+                }
+
+                if let Some(container_id) = &container.container_id {
+                    let id = ContainerRuntime::parse(container_id)
+                        .map_or(container_id.as_str(), |(_, id)| id);
+                    targets.push((id.to_owned(), target));
+                }
+            }
+        }
+
+        targets
+    }
+
+    /// Whether radvisor should collect statistics for the given target,
+    /// according to the target filter. Both pods and containers are matched
+    /// using the labels and namespace of the pod, and the images given in the
+    /// pod spec
+    fn should_collect_stats(&self, target: Target) -> bool {
+        let pod = target.pod();
+        let meta = Meta::meta(pod);
+        let name = target.name();
+        let spec_containers = pod.spec.iter().flat_map(|spec| spec.containers.iter());
+        let images = match target {
+            Target::Pod(_) => spec_containers
+                .filter_map(|c| c.image.as_deref())
+                .collect::<Vec<_>>(),
+            Target::Container(_, container) => spec_containers
+                .filter(|c| c.name == container.name)
+                .filter_map(|c| c.image.as_deref())
+                .collect::<Vec<_>>(),
+        };
+
+        let candidate = Candidate {
+            name: &name,
+            labels: meta
+                .labels
+                .iter()
+                .flatten()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+            namespace: meta.namespace.as_deref(),
+            images,
+        };
+        self.filter.matches(&candidate)
+    }
+
+    /// Converts a pod or container to a collection start event, preparing all
+    /// serialization/cgroup checks needed
+    fn make_start_event(
+        &mut self,
+        id: &str,
+        target: Target,
+        shell: &Shell,
+    ) -> Result<CollectionEvent, StartCollectionError> {
+        let method = self.get_collection_method(target, shell)?;
+        let metadata = match serialize_info(target) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return Err(StartCollectionError::MetadataSerializationError(err));
+            },
+        };
+
+        Ok(CollectionEvent::Start {
+            method,
+            target: CollectionTarget {
+                provider:  PROVIDER_TYPE,
+                metadata:  Some(metadata),
+                name:      target.name(),
+                poll_time: util::nano_ts(),
+                filter:    self.filter.expression(),
+                id:        id.to_owned(),
+            },
+        })
+    }
+
+    /// Gets the collection method struct for the pod or container, resolving
+    /// the proper collection method
+    fn get_collection_method(
+        &mut self,
+        target: Target,
+        shell: &Shell,
+    ) -> Result<CollectionMethod, StartCollectionError> {
+        let pod = target.pod();
+        let uid: &str = uid_option(pod).ok_or(StartCollectionError::MissingPodUid)?;
+        let qos_class: QualityOfService =
+            QualityOfService::from_pod(pod).ok_or(StartCollectionError::FailedQosParse)?;
+
+        // Construct the cgroup path from the UID and QoS class
+        // from the metadata, and make sure it exists/is mounted
+        let pod_cgroup = self
+            .get_cgroup(uid, qos_class, shell)
+            .ok_or(StartCollectionError::CgroupNotFound)?;
+
+        let cgroup = match target {
+            Target::Pod(_) => pod_cgroup,
+            Target::Container(_, container) => {
+                let container_id = container.container_id.as_deref().unwrap_or(NONE_STR);
+                let (runtime, id) = ContainerRuntime::parse(container_id).ok_or_else(|| {
+                    StartCollectionError::UnknownContainerRuntime(container_id.to_owned())
+                })?;
+
+                // Containers are placed in a child cgroup of their pod's cgroup
+                let child = runtime.cgroup_name(id, pod_cgroup.driver);
+                self.cgroup_manager
+                    .get_child(&pod_cgroup, &child)
+                    .ok_or(StartCollectionError::CgroupNotFound)?
+            },
+        };
+
+        Ok(CollectionMethod::from(cgroup))
+    }
+
+    /// Gets the group path for the given UID and quality of service class,
+    /// printing out a message upon the first successful cgroup resolution
+    fn get_cgroup(
+        &mut self,
+        uid: &str,
+        qos_class: QualityOfService,
+        shell: &Shell,
+    ) -> Option<CgroupPath> {
+        let pod_slice = String::from("pod") + uid;
+        // Determine if the manager had a resolved group beforehand
+        let had_driver = self.cgroup_manager.driver().is_some();
+
+        let cgroup_option: Option<CgroupPath> = self
+            .cgroup_manager
+            .get_cgroup(&[ROOT_CGROUP, qos_class.into(), &pod_slice], true);
+
+        if !had_driver {
+            if let Some(driver) = self.cgroup_manager.driver() {
+                shell.info(format!("Identified {} as cgroup driver", driver));
+            }
+        }
+
+        cgroup_option
+    }
+}
+
+/// Watch on all pods scheduled to the current node, which keeps the pod cache
+/// up to date and reconciles the collected pods after every change. The
+/// underlying watcher resumes from the last seen resource version when the
+/// watch ends, and re-lists all pods if that version is too old (410 Gone)
+struct PodWatch {
+    api:         Api<Pod>,
+    list_params: ListParams,
+    writer:      store::Writer<Pod>,
+    pod_store:   Store<Pod>,
+    tracker:     Arc<Mutex<PodTracker>>,
+    shell:       Arc<Shell>,
+    tx:          Sender<CollectionEvent>,
+}
+
+impl PodWatch {
+    /// Creates a new pod watch that lists and watches pods using the given API
+    /// client, keeping a new pod cache up to date
+    fn new(
+        api: Api<Pod>,
+        list_params: ListParams,
+        tracker: Arc<Mutex<PodTracker>>,
+        shell: Arc<Shell>,
+        tx: Sender<CollectionEvent>,
+    ) -> Self {
+        let writer = store::Writer::<Pod>::default();
+        let pod_store = writer.as_reader();
+        Self {
+            api,
+            list_params,
+            writer,
+            pod_store,
+            tracker,
+            shell,
+            tx,
+        }
+    }
+
+    /// Thread function that runs the pod watch until the collection thread
+    /// stops receiving events
+    fn run(self) {
+        let mut runtime = tokio_02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(self.watch());
+    }
+
+    /// Watches all pods on the current node, sending collection events for any
+    /// pods that were added or removed after each change
+    async fn watch(self) {
+        let Self {
+            api,
+            list_params,
+            writer,
+            pod_store,
+            tracker,
+            shell,
+            tx,
+        } = self;
+
+        shell.verbose(|sh| sh.info("Watching pods on the current node from the Kubernetes API"));
+        let mut stream = Box::pin(reflector::reflector(writer, watcher(api, list_params)));
+        while let Some(result) = stream.next().await {
+            match result {
+                Ok(_) => {
+                    let mut tracker = tracker.lock().unwrap();
+                    for event in tracker.reconcile(pod_store.state(), &shell) {
+                        if tx.send(event).is_err() {
+                            // The collection thread has stopped
+                            return;
+                        }
+                    }
+                },
+                Err(err) => {
+                    shell.warn(format!(
+                        "Could not watch pods from the Kubernetes API: {}",
+                        err
+                    ));
+                    tokio_02::time::delay_for(WATCH_RETRY_DELAY).await;
+                },
+            }
+        }
+    }
+}
+
+/// Pod info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodInfo<'a> {
+    uid:        &'a Option<String>,
+    name:       &'a Option<String>,
+    created_at: &'a Option<Time>,
+    labels:     &'a Option<BTreeMap<String, String>>,
+    namespace:  &'a Option<String>,
+    node_name:  &'a Option<String>,
+    host_ip:    &'a Option<String>,
+    phase:      &'a Option<String>,
+    qos_class:  &'a Option<String>,
+    started_at: &'a Option<Time>,
+}
+
+impl<'a> PodInfo<'a> {
+    /// Attempts to extract all state/metadata from the given pod, and collects
+    /// it in a single pod info struct
+    fn new(p: &'a Pod) -> Self {
+        let meta = Meta::meta(p);
+        let (uid, name, created_at, labels, namespace) = (
+            &meta.uid,
+            &meta.name,
+            &meta.creation_timestamp,
+            &meta.labels,
+            &meta.namespace,
+        );
+
+        let node_name = match p.spec.as_ref() {
+            None => &None,
+            Some(spec) => &spec.node_name,
+        };
+
+        let (host_ip, phase, qos_class, started_at) = match p.status.as_ref() {
+            None => (&None, &None, &None, &None),
+            Some(status) => (
+                &status.host_ip,
+                &status.phase,
+                &status.qos_class,
+                &status.start_time,
+            ),
+        };
+
+        PodInfo {
+            uid,
+            name,
+            created_at,
+            labels,
+            namespace,
+            node_name,
+            host_ip,
+            phase,
+            qos_class,
+            started_at,
+        }
+    }
+}
+
+/// Container info struct that gets included with the log file of each
+/// container, alongside the info of its pod
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInfo<'a> {
+    container_id:  &'a Option<String>,
+    name:          &'a str,
+    image:         &'a str,
+    image_id:      &'a str,
+    restart_count: i32,
+    started_at:    Option<&'a Time>,
+    pod:           PodInfo<'a>,
+}
+
+impl<'a> ContainerInfo<'a> {
+    /// Extracts all state/metadata from the given container status, along
+    /// with the pod info of the pod it belongs to
+    fn new(p: &'a Pod, c: &'a ContainerStatus) -> Self {
+        let started_at = c
+            .state
+            .as_ref()
+            .and_then(|state| state.running.as_ref())
+            .and_then(|running| running.started_at.as_ref());
+
+        ContainerInfo {
+            container_id: &c.container_id,
+            name: &c.name,
+            image: &c.image,
+            image_id: &c.image_id,
+            restart_count: c.restart_count,
+            started_at,
+            pod: PodInfo::new(p),
+        }
+    }
+}
+
+/// Attempts to format pod or container info, potentially failing to do so
+fn serialize_info(target: Target) -> Result<serde_yaml::Value, Error> {
+    let serde_output = match target {
+        Target::Pod(pod) => {
+            let pod_info = PodInfo::new(pod);
+            serde_yaml::to_value(&pod_info)?
+        },
+        Target::Container(pod, container) => {
+            let container_info = ContainerInfo::new(pod, container);
+            serde_yaml::to_value(&container_info)?
+        },
+    };
+    Ok(serde_output)
+}
+
+fn uid_option<O: Meta>(obj: &O) -> Option<&str> { Meta::meta(obj).uid.as_deref() }
+
+fn name_option<O: Meta>(obj: &O) -> Option<&str> { Meta::meta(obj).name.as_deref() }
+
+fn name<O: Meta>(obj: &O) -> &str { name_option(obj).unwrap_or(NONE_STR) }
+
+/// Gets the statuses of all containers in the pod that are currently running
+fn running_containers(pod: &Pod) -> impl Iterator<Item = &ContainerStatus> {
+    pod.status
+        .iter()
+        .flat_map(|status| status.container_statuses.iter().flatten())
+        .filter(|c| matches!(&c.state, Some(state) if state.running.is_some()))
+}
+
+/// Whether all containers in the pod have terminated
+fn is_terminated(pod: &Pod) -> bool {
+    let phase = pod.status.as_ref().and_then(|s| s.phase.as_deref());
+    matches!(phase, Some(phase) if TERMINATED_PHASES.contains(&phase))
+}
@@ -0,0 +1,189 @@
+//! Tests for the Kubernetes pod watch, run against a mocked API server that is
+//! served over HTTP on the loopback interface
+
+use super::{PodTracker, PodWatch, TargetKind};
+use crate::shared::CollectionEvent;
+use crate::shell::Shell;
+use crate::util::{CgroupManager, CgroupVersion};
+use std::convert::Infallible;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures_03::channel::mpsc::{unbounded, UnboundedSender};
+use hyper_013::service::make_service_fn;
+use hyper_013::{Body, Request, Response, Server};
+use kube::api::{Api, ListParams};
+use kube::client::Client;
+use kube::config::Config;
+use tower_test::mock::{self, Handle, Mock};
+
+/// Maximum time to wait for a request from the pod watch or for an event
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type ApiServer = Handle<Request<Body>, Response<Body>>;
+
+/// Serves the mocked API server on a background thread, returning its URL
+fn serve(api_server: Mock<Request<Body>, Response<Body>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let mut rt = tokio_02::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let make_service = make_service_fn(move |_| {
+                let api_server = api_server.clone();
+                async move { Ok::<_, Infallible>(api_server) }
+            });
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(make_service)
+                .await
+                .unwrap();
+        });
+    });
+    url
+}
+
+/// Waits for the next request to the mocked API server, asserting whether it
+/// starts a watch, and responds to it with the given body
+fn respond(
+    rt: &mut tokio_02::runtime::Runtime,
+    api_server: &mut ApiServer,
+    watch: bool,
+    body: Body,
+) {
+    let (request, send) = rt
+        .block_on(async { tokio_02::time::timeout(TIMEOUT, api_server.next_request()).await })
+        .expect("timed out waiting for a request")
+        .unwrap();
+    assert_eq!(request.uri().path(), "/api/v1/pods");
+    let query = request.uri().query().unwrap_or_default();
+    assert!(query.contains("fieldSelector=spec.nodeName%3Dnode"));
+    assert_eq!(
+        query.contains("watch=true"),
+        watch,
+        "unexpected query {}",
+        query
+    );
+    send.send_response(Response::new(body));
+}
+
+fn pod(name: &str, uid: &str, resource_version: u32) -> String {
+    format!(
+        r#"{{"apiVersion":"v1","kind":"Pod","metadata":{{"name":"{}","namespace":"default","uid":"{}","resourceVersion":"{}"}},"spec":{{"nodeName":"node","containers":[{{"name":"app","image":"nginx"}}]}},"status":{{"phase":"Running","qosClass":"BestEffort"}}}}"#,
+        name, uid, resource_version
+    )
+}
+
+fn pod_list(pods: &[String], resource_version: u32) -> Body {
+    Body::from(format!(
+        r#"{{"apiVersion":"v1","kind":"PodList","metadata":{{"resourceVersion":"{}"}},"items":[{}]}}"#,
+        resource_version,
+        pods.join(",")
+    ))
+}
+
+fn send_watch_event(tx: &UnboundedSender<Result<String, io::Error>>, r#type: &str, object: &str) {
+    tx.unbounded_send(Ok(format!(
+        "{{\"type\":\"{}\",\"object\":{}}}\n",
+        r#type, object
+    )))
+    .unwrap();
+}
+
+fn next_event(rx: &Receiver<CollectionEvent>) -> CollectionEvent {
+    rx.recv_timeout(TIMEOUT)
+        .expect("timed out waiting for an event")
+}
+
+fn assert_start(event: &CollectionEvent, uid: &str) {
+    match event {
+        CollectionEvent::Start { target, .. } => {
+            assert_eq!(target.id, uid);
+            assert_eq!(target.provider, "kubernetes");
+        },
+        CollectionEvent::Stop(id) => panic!("expected a Start event, got Stop({})", id),
+    }
+}
+
+#[test]
+fn watch_emits_start_and_stop_events() {
+    let dir = tempfile::tempdir().unwrap();
+    for uid in &["1111", "2222"] {
+        fs::create_dir_all(
+            dir.path()
+                .join("kubepods/besteffort")
+                .join(format!("pod{}", uid)),
+        )
+        .unwrap();
+    }
+
+    let (service, mut api_server) = mock::pair::<Request<Body>, Response<Body>>();
+    let url = serve(service);
+    let api = Api::all(Client::new(Config::new(url.parse().unwrap())));
+
+    let mut tracker = PodTracker::new();
+    tracker.kinds = vec![TargetKind::Pod];
+    tracker.cgroup_manager = CgroupManager::with_root(dir.path().to_owned(), CgroupVersion::V2);
+    let shell = Arc::new(Shell::from_write(
+        Box::new(io::sink()),
+        Box::new(io::sink()),
+    ));
+    let (tx, rx) = mpsc::channel();
+    let watch = PodWatch::new(
+        api,
+        ListParams::default().fields("spec.nodeName=node"),
+        Arc::new(Mutex::new(tracker)),
+        shell,
+        tx,
+    );
+    thread::spawn(move || watch.run());
+
+    let mut rt = tokio_02::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // The initial list starts collection for the existing pod
+    respond(
+        &mut rt,
+        &mut api_server,
+        false,
+        pod_list(&[pod("a", "1111", 1)], 1),
+    );
+    assert_start(&next_event(&rx), "1111");
+
+    // Pods added and deleted during the watch are started and stopped
+    let (watch_tx, watch_rx) = unbounded();
+    respond(&mut rt, &mut api_server, true, Body::wrap_stream(watch_rx));
+    send_watch_event(&watch_tx, "ADDED", &pod("b", "2222", 2));
+    assert_start(&next_event(&rx), "2222");
+    send_watch_event(&watch_tx, "DELETED", &pod("a", "1111", 3));
+    assert_eq!(next_event(&rx), CollectionEvent::Stop(String::from("1111")));
+
+    // An expired resource version re-lists all pods, without starting
+    // collection again for pods that are already collected
+    send_watch_event(
+        &watch_tx,
+        "ERROR",
+        r#"{"kind":"Status","apiVersion":"v1","metadata":{},"status":"Failure","message":"too old resource version","reason":"Expired","code":410}"#,
+    );
+    respond(
+        &mut rt,
+        &mut api_server,
+        false,
+        pod_list(&[pod("b", "2222", 4)], 4),
+    );
+    let (_watch_tx, watch_rx) = unbounded::<Result<String, io::Error>>();
+    respond(&mut rt, &mut api_server, true, Body::wrap_stream(watch_rx));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+}
@@ -1,13 +1,24 @@
+pub mod cgroups;
+#[cfg(feature = "containerd")]
+pub mod containerd;
 #[cfg(feature = "docker")]
 pub mod docker;
 #[cfg(feature = "kubernetes")]
 pub mod kubernetes;
+#[cfg(feature = "podman")]
+pub mod podman;
+pub mod process;
+#[cfg(feature = "systemd")]
+pub mod systemd;
 
+#[cfg(any(feature = "docker", feature = "kubernetes"))]
+use crate::cli::FilterOptions;
 use crate::cli::{CollectionOptions, PollingOptions, RunCommand};
 use crate::shared::CollectionEvent;
 use crate::shell::Shell;
-#[cfg(feature = "kubernetes")]
+#[cfg(any(feature = "kubernetes", feature = "containerd", feature = "podman"))]
 use std::path::PathBuf;
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
 
 use clap::{Clap, ValueHint};
@@ -36,6 +47,11 @@ pub trait Provider {
     /// Attempts to poll the provider for a list of collection events (new/old
     /// targets), returning an Error if it failed
     fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error>;
+    /// Starts pushing collection events to the collection thread as soon as
+    /// they happen, for providers that subscribe to an event stream. Called
+    /// once after initialization, before the first poll. By default, providers
+    /// only generate events when polled
+    fn stream(&mut self, _tx: &Sender<CollectionEvent>) {}
 }
 
 pub use provider_type::ProviderType;
@@ -45,10 +61,18 @@ mod provider_type {
     // private module
     #![allow(clippy::default_trait_access)]
 
+    use super::CgroupsOptions;
+    #[cfg(feature = "containerd")]
+    use super::ContainerdOptions;
     #[cfg(feature = "docker")]
     use super::DockerOptions;
     #[cfg(feature = "kubernetes")]
     use super::KubernetesOptions;
+    #[cfg(feature = "podman")]
+    use super::PodmanOptions;
+    use super::ProcessOptions;
+    #[cfg(feature = "systemd")]
+    use super::SystemdOptions;
     use crate::cli::{AUTHORS, VERSION};
     use clap::Clap;
     use strum_macros::IntoStaticStr;
@@ -74,6 +98,49 @@ mod provider_type {
             each pod"
         )]
         Kubernetes(KubernetesOptions),
+
+        #[cfg(feature = "containerd")]
+        #[clap(
+            version = VERSION.unwrap_or("unknown"),
+            author = AUTHORS.as_deref().unwrap_or("contributors"),
+            about = "Runs collection using containerd (via the CRI) as the target backend; \
+            collecting stats for each container"
+        )]
+        Containerd(ContainerdOptions),
+
+        #[cfg(feature = "podman")]
+        #[clap(
+            version = VERSION.unwrap_or("unknown"),
+            author = AUTHORS.as_deref().unwrap_or("contributors"),
+            about = "Runs collection using podman (via the libpod API) as the target backend; \
+            collecting stats for each container"
+        )]
+        Podman(PodmanOptions),
+
+        #[clap(
+            version = VERSION.unwrap_or("unknown"),
+            author = AUTHORS.as_deref().unwrap_or("contributors"),
+            about = "Runs collection for an explicit list of cgroups (or glob patterns); \
+            collecting stats for each cgroup while it exists"
+        )]
+        Cgroups(CgroupsOptions),
+
+        #[cfg(feature = "systemd")]
+        #[clap(
+            version = VERSION.unwrap_or("unknown"),
+            author = AUTHORS.as_deref().unwrap_or("contributors"),
+            about = "Runs collection using systemd (via D-Bus) as the target backend; collecting \
+            stats for each active unit"
+        )]
+        Systemd(SystemdOptions),
+
+        #[clap(
+            version = VERSION.unwrap_or("unknown"),
+            author = AUTHORS.as_deref().unwrap_or("contributors"),
+            about = "Runs collection for processes given by PID or matching a command pattern; \
+            collecting stats for the cgroup of each process (or each process itself)"
+        )]
+        Process(ProcessOptions),
     }
 }
 
@@ -96,6 +163,16 @@ impl ProviderType {
             #[cfg(feature = "docker")]
             Self::Docker(_) => panic!("Cannot unwrap Docker provider to Kubernetes options"),
             Self::Kubernetes(opts) => opts,
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => {
+                panic!("Cannot unwrap containerd provider to Kubernetes options")
+            },
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to Kubernetes options"),
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to Kubernetes options"),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to Kubernetes options"),
+            Self::Process(_) => panic!("Cannot unwrap process provider to Kubernetes options"),
         }
     }
     /// Gets the inner options struct for Docker
@@ -106,6 +183,111 @@ impl ProviderType {
             Self::Docker(opts) => opts,
             #[cfg(feature = "kubernetes")]
             Self::Kubernetes(_) => panic!("Cannot unwrap Kubernetes provider to Docker options"),
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => panic!("Cannot unwrap containerd provider to Docker options"),
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to Docker options"),
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to Docker options"),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to Docker options"),
+            Self::Process(_) => panic!("Cannot unwrap process provider to Docker options"),
+        }
+    }
+    /// Gets the inner options struct for containerd
+    #[must_use]
+    #[cfg(feature = "containerd")]
+    pub fn into_inner_containerd(self) -> ContainerdOptions {
+        match self {
+            #[cfg(feature = "docker")]
+            Self::Docker(_) => panic!("Cannot unwrap Docker provider to containerd options"),
+            #[cfg(feature = "kubernetes")]
+            Self::Kubernetes(_) => {
+                panic!("Cannot unwrap Kubernetes provider to containerd options")
+            },
+            Self::Containerd(opts) => opts,
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to containerd options"),
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to containerd options"),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to containerd options"),
+            Self::Process(_) => panic!("Cannot unwrap process provider to containerd options"),
+        }
+    }
+    /// Gets the inner options struct for Podman
+    #[must_use]
+    #[cfg(feature = "podman")]
+    pub fn into_inner_podman(self) -> PodmanOptions {
+        match self {
+            #[cfg(feature = "docker")]
+            Self::Docker(_) => panic!("Cannot unwrap Docker provider to Podman options"),
+            #[cfg(feature = "kubernetes")]
+            Self::Kubernetes(_) => panic!("Cannot unwrap Kubernetes provider to Podman options"),
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => panic!("Cannot unwrap containerd provider to Podman options"),
+            Self::Podman(opts) => opts,
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to Podman options"),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to Podman options"),
+            Self::Process(_) => panic!("Cannot unwrap process provider to Podman options"),
+        }
+    }
+    /// Gets the inner options struct for the static cgroups provider
+    #[must_use]
+    pub fn into_inner_cgroups(self) -> CgroupsOptions {
+        match self {
+            #[cfg(feature = "docker")]
+            Self::Docker(_) => panic!("Cannot unwrap Docker provider to cgroups options"),
+            #[cfg(feature = "kubernetes")]
+            Self::Kubernetes(_) => {
+                panic!("Cannot unwrap Kubernetes provider to cgroups options")
+            },
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => {
+                panic!("Cannot unwrap containerd provider to cgroups options")
+            },
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to cgroups options"),
+            Self::Cgroups(opts) => opts,
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to cgroups options"),
+            Self::Process(_) => panic!("Cannot unwrap process provider to cgroups options"),
+        }
+    }
+    /// Gets the inner options struct for systemd
+    #[must_use]
+    #[cfg(feature = "systemd")]
+    pub fn into_inner_systemd(self) -> SystemdOptions {
+        match self {
+            #[cfg(feature = "docker")]
+            Self::Docker(_) => panic!("Cannot unwrap Docker provider to systemd options"),
+            #[cfg(feature = "kubernetes")]
+            Self::Kubernetes(_) => panic!("Cannot unwrap Kubernetes provider to systemd options"),
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => panic!("Cannot unwrap containerd provider to systemd options"),
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to systemd options"),
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to systemd options"),
+            Self::Systemd(opts) => opts,
+            Self::Process(_) => panic!("Cannot unwrap process provider to systemd options"),
+        }
+    }
+
+    /// Gets the inner options struct for the process provider
+    #[must_use]
+    pub fn into_inner_process(self) -> ProcessOptions {
+        match self {
+            #[cfg(feature = "docker")]
+            Self::Docker(_) => panic!("Cannot unwrap Docker provider to process options"),
+            #[cfg(feature = "kubernetes")]
+            Self::Kubernetes(_) => panic!("Cannot unwrap Kubernetes provider to process options"),
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => panic!("Cannot unwrap containerd provider to process options"),
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => panic!("Cannot unwrap Podman provider to process options"),
+            Self::Cgroups(_) => panic!("Cannot unwrap cgroups provider to process options"),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => panic!("Cannot unwrap systemd provider to process options"),
+            Self::Process(opts) => opts,
         }
     }
 
@@ -117,6 +299,14 @@ impl ProviderType {
             Self::Docker(_) => Box::new(docker::Docker::new()),
             #[cfg(feature = "kubernetes")]
             Self::Kubernetes(_) => Box::new(kubernetes::Kubernetes::new()),
+            #[cfg(feature = "containerd")]
+            Self::Containerd(_) => Box::new(containerd::Containerd::new()),
+            #[cfg(feature = "podman")]
+            Self::Podman(_) => Box::new(podman::Podman::new()),
+            Self::Cgroups(_) => Box::new(cgroups::Cgroups::new()),
+            #[cfg(feature = "systemd")]
+            Self::Systemd(_) => Box::new(systemd::Systemd::new()),
+            Self::Process(_) => Box::new(process::Process::new()),
         }
     }
 
@@ -128,6 +318,14 @@ impl ProviderType {
             Self::Docker(opts) => &opts.collection,
             #[cfg(feature = "kubernetes")]
             Self::Kubernetes(opts) => &opts.collection,
+            #[cfg(feature = "containerd")]
+            Self::Containerd(opts) => &opts.collection,
+            #[cfg(feature = "podman")]
+            Self::Podman(opts) => &opts.collection,
+            Self::Cgroups(opts) => &opts.collection,
+            #[cfg(feature = "systemd")]
+            Self::Systemd(opts) => &opts.collection,
+            Self::Process(opts) => &opts.collection,
         }
     }
 
@@ -139,6 +337,14 @@ impl ProviderType {
             Self::Docker(opts) => &opts.polling,
             #[cfg(feature = "kubernetes")]
             Self::Kubernetes(opts) => &opts.polling,
+            #[cfg(feature = "containerd")]
+            Self::Containerd(opts) => &opts.polling,
+            #[cfg(feature = "podman")]
+            Self::Podman(opts) => &opts.polling,
+            Self::Cgroups(opts) => &opts.polling,
+            #[cfg(feature = "systemd")]
+            Self::Systemd(opts) => &opts.polling,
+            Self::Process(opts) => &opts.polling,
         }
     }
 }
@@ -148,6 +354,16 @@ impl ProviderType {
 #[cfg(feature = "docker")]
 #[derive(Clap, Clone, Debug, PartialEq)]
 pub struct DockerOptions {
+    /// Whether to subscribe to the Docker event stream to start and stop
+    /// collection as soon as containers start and die. Polling is then only
+    /// used to reconcile the list of running containers
+    #[clap(long = "events")]
+    pub events: bool,
+
+    // Target filter options
+    #[clap(flatten)]
+    pub filter: FilterOptions,
+
     // Polling-related options
     #[clap(flatten)]
     pub polling: PollingOptions,
@@ -169,6 +385,145 @@ pub struct KubernetesOptions {
     )]
     pub kube_config: Option<PathBuf>,
 
+    /// Kinds of targets to collect statistics for: each pod (merging all of
+    /// its containers), and/or each running container in each pod
+    #[clap(
+        name = "targets",
+        long = "targets",
+        default_value = "pod",
+        possible_values = &["pod", "container"],
+        use_delimiter = true
+    )]
+    pub targets: Vec<kubernetes::TargetKind>,
+
+    // Target filter options
+    #[clap(flatten)]
+    pub filter: FilterOptions,
+
+    // Polling-related options
+    #[clap(flatten)]
+    pub polling: PollingOptions,
+
+    // Collection-related options
+    #[clap(flatten)]
+    pub collection: CollectionOptions,
+}
+
+#[cfg(feature = "containerd")]
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct ContainerdOptions {
+    /// Location of the Unix domain socket that the CRI runtime service listens
+    /// on
+    #[clap(
+        parse(from_os_str),
+        short = 'r',
+        long = "runtime-endpoint",
+        default_value = "/run/containerd/containerd.sock",
+        value_hint = ValueHint::FilePath
+    )]
+    pub runtime_endpoint: PathBuf,
+
+    // Polling-related options
+    #[clap(flatten)]
+    pub polling: PollingOptions,
+
+    // Collection-related options
+    #[clap(flatten)]
+    pub collection: CollectionOptions,
+}
+
+#[cfg(feature = "podman")]
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct PodmanOptions {
+    /// Location of the Podman API socket (defaults to /run/podman/podman.sock
+    /// when running as root, or podman/podman.sock in the user's runtime
+    /// directory otherwise)
+    #[clap(
+        parse(from_os_str),
+        short = 's',
+        long = "socket",
+        value_hint = ValueHint::FilePath
+    )]
+    pub socket: Option<PathBuf>,
+
+    // Polling-related options
+    #[clap(flatten)]
+    pub polling: PollingOptions,
+
+    // Collection-related options
+    #[clap(flatten)]
+    pub collection: CollectionOptions,
+}
+
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct CgroupsOptions {
+    /// Cgroups to collect statistics for, either as paths in the cgroup
+    /// hierarchy (such as /system.slice/docker.service) or as paths in the
+    /// cgroup filesystem. Supports glob patterns (such as
+    /// /system.slice/*.service)
+    #[clap(name = "path", required = true, min_values = 1)]
+    pub paths: Vec<String>,
+
+    // Polling-related options
+    #[clap(flatten)]
+    pub polling: PollingOptions,
+
+    // Collection-related options
+    #[clap(flatten)]
+    pub collection: CollectionOptions,
+}
+
+#[cfg(feature = "systemd")]
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct SystemdOptions {
+    /// Types of units to collect statistics for
+    #[clap(
+        name = "type",
+        short = 't',
+        long = "type",
+        default_value = "service,scope,slice",
+        possible_values = &["service", "scope", "slice"],
+        use_delimiter = true
+    )]
+    pub types: Vec<systemd::UnitType>,
+
+    /// (optional) Glob patterns of unit names to collect statistics for (such
+    /// as nginx*.service). If not given, then all active units are collected
+    #[clap(short = 'u', long = "unit", value_hint = ValueHint::Other)]
+    pub units: Vec<String>,
+
+    // Polling-related options
+    #[clap(flatten)]
+    pub polling: PollingOptions,
+
+    // Collection-related options
+    #[clap(flatten)]
+    pub collection: CollectionOptions,
+}
+
+#[derive(Clap, Clone, Debug, PartialEq)]
+pub struct ProcessOptions {
+    /// Process IDs of processes to collect statistics for
+    #[clap(
+        name = "pid",
+        long = "pid",
+        required_unless_present = "match",
+        value_hint = ValueHint::Other
+    )]
+    pub pids: Vec<u32>,
+
+    /// Regular expressions matched against the full command line of every
+    /// running process (such as "^nginx: worker"). Statistics are collected
+    /// for each matching process
+    #[clap(name = "match", short = 'm', long = "match", value_hint = ValueHint::Other)]
+    pub patterns: Vec<String>,
+
+    /// Whether to collect per-process statistics from /proc/<pid> for each
+    /// process instead of collecting statistics for the cgroup that contains
+    /// the process (and the rest of its process tree)
+    #[clap(long = "per-process")]
+    pub per_process: bool,
+
     // Polling-related options
     #[clap(flatten)]
     pub polling: PollingOptions,
@@ -0,0 +1,489 @@
+use crate::cli::RunCommand;
+use crate::polling::providers::{InitializationError, Provider};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupManager, CgroupPath, ItemPool};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use failure::{format_err, Error};
+use http_body_util::{BodyExt, Empty};
+use hyper_1::body::Bytes;
+use hyper_1::client::conn::http1;
+use hyper_1::header::HOST;
+use hyper_1::Request;
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use tokio_1::net::UnixStream;
+
+/// Version of the libpod API that rAdvisor targets
+const LIBPOD_API_VERSION: &str = "v3.0.0";
+
+/// Location of the Podman API socket when running rootful
+const ROOTFUL_SOCKET: &str = "/run/podman/podman.sock";
+
+/// Parent cgroup that libpod places containers in under the cgroupfs driver
+const CGROUPFS_PARENT: &str = "libpod_parent";
+
+const PROVIDER_TYPE: &str = "podman";
+
+pub struct Podman {
+    container_id_pool: ItemPool<String>,
+    /// Ids of all containers that are waiting for their cgroup to be created
+    pending:           BTreeSet<String>,
+    cgroup_manager:    CgroupManager,
+    socket:            Option<PathBuf>,
+    uid:               u32,
+    shell:             Option<Arc<Shell>>,
+    runtime:           RefCell<tokio_1::runtime::Runtime>,
+}
+
+/// Possible errors that can occur during Podman provider initialization
+#[derive(Debug)]
+enum PodmanInitError {
+    ConnectionFailed(PathBuf, Error),
+    InvalidCgroupMount,
+}
+
+impl Into<InitializationError> for PodmanInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::ConnectionFailed(socket, error) => InitializationError {
+                original:   Some(error),
+                suggestion: format!(
+                    "Could not connect to the Podman API socket at {}. Make sure the Podman \
+                     service is running (systemctl [--user] start podman.socket).\nIf the socket \
+                     is at a non-standard location, set it with --socket.",
+                    socket.display()
+                ),
+            },
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+        }
+    }
+}
+
+/// Possible error that can occur during Podman container collection target
+/// initialization
+#[derive(Debug)]
+enum StartCollectionError {
+    MetadataSerializationError(Error),
+    CgroupNotFound,
+}
+
+/// Container entry returned from the libpod `/containers/json` endpoint,
+/// including only the fields used by rAdvisor
+/// see [`pkg/domain/entities/container_ps.go`](https://github.com/containers/podman/blob/v3.0.0/pkg/domain/entities/container_ps.go)
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    id:         String,
+    #[serde(default)]
+    names:      Vec<String>,
+    #[serde(default)]
+    image:      String,
+    #[serde(rename = "ImageID", default)]
+    image_id:   String,
+    #[serde(default)]
+    command:    Option<Vec<String>>,
+    /// Either a timestamp string or a Unix timestamp depending on the API
+    /// version
+    #[serde(default)]
+    created:    serde_json::Value,
+    #[serde(default)]
+    started_at: i64,
+    #[serde(default)]
+    state:      String,
+    #[serde(default)]
+    labels:     Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pid:        i64,
+    /// Id of the pod the container belongs to, if any
+    #[serde(default)]
+    pod:        String,
+    #[serde(default)]
+    pod_name:   String,
+}
+
+impl Provider for Podman {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell().status("Initializing", "Podman API provider");
+
+        let socket = opts
+            .provider
+            .clone()
+            .into_inner_podman()
+            .socket
+            .unwrap_or_else(|| default_socket(self.uid));
+        match self.try_init(socket) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        let containers = self.get_containers()?;
+
+        let original_num = containers.len();
+        let to_collect: BTreeMap<String, Container> = containers
+            .into_iter()
+            .filter_map(|c| {
+                if should_collect_stats(&c) {
+                    Some((c.id.clone(), c))
+                } else {
+                    None
+                }
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let ids = to_collect.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.container_id_pool.update(ids);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed_len);
+        // Add all removed Ids as Stop events
+        events.extend(removed.into_iter().map(CollectionEvent::Stop));
+
+        // Add all added Ids as Start events
+        let start_events = added
+            .into_iter()
+            .flat_map(|id| {
+                // It shouldn't be possible to have an Id that doesn't exist in the map, but
+                // check anyways
+                let container = match to_collect.get(&id) {
+                    Some(container) => container,
+                    None => {
+                        self.shell().error(format!(
+                            "Processed Id from ItemPool added result that was not in fetched \
+                             container list. This is a bug!\nId: {}",
+                            id
+                        ));
+                        return None;
+                    },
+                };
+
+                match self.make_start_event(container) {
+                    Ok(start) => {
+                        self.pending.remove(&id);
+                        Some(start)
+                    },
+                    Err(StartCollectionError::CgroupNotFound) => {
+                        // Retry on the next poll, in case the cgroup hasn't been created yet
+                        self.container_id_pool.remove(&id);
+                        if self.pending.insert(id) {
+                            self.shell().warn(format!(
+                                "Could not create container metadata for container {}: cgroup \
+                                 path could not be constructed or does not exist (yet)",
+                                display(container)
+                            ));
+                        }
+                        None
+                    },
+                    Err(StartCollectionError::MetadataSerializationError(cause)) => {
+                        self.shell()
+                            .warn(format!("Could not serialize container metadata: {}", cause));
+
+                        // Ignore container and continue initializing the rest
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        // Stop waiting for containers that were removed before their cgroup appeared
+        self.pending.retain(|id| to_collect.contains_key(id));
+
+        if processed_num != 0 || removed_len != 0 {
+            self.shell().verbose(|sh| {
+                sh.info(format!(
+                    "Received {} -> {} (+{}, -{}) containers from the Podman API",
+                    original_num,
+                    to_collect.len(),
+                    processed_num,
+                    removed_len
+                ))
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Default for Podman {
+    fn default() -> Self { Self::new() }
+}
+
+impl Podman {
+    #[must_use]
+    pub fn new() -> Self {
+        // Use a single-threaded runtime so that Tokio doesn't create
+        // a thread pool and instead executes futures in the current thread
+        // (emulating synchronous I/O)
+        let runtime = tokio_1::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        Self {
+            container_id_pool: ItemPool::new(),
+            pending:           BTreeSet::new(),
+            cgroup_manager:    CgroupManager::new(),
+            socket:            None,
+            uid:               unsafe { libc::geteuid() },
+            shell:             None,
+            runtime:           RefCell::new(runtime),
+        }
+    }
+
+    /// Executes a future on the internal runtime, blocking the current thread
+    /// until it completes
+    fn exec<F: Future>(&self, future: F) -> F::Output {
+        let rt = self.runtime.borrow_mut();
+        rt.block_on(future)
+    }
+
+    /// Attempts to initialize the Podman provider, failing if the connection
+    /// check to the Podman service failed or if the needed Cgroups aren't
+    /// mounted properly
+    fn try_init(&mut self, socket: PathBuf) -> Result<(), PodmanInitError> {
+        // Ping the libpod API to make sure the current process can connect
+        self.exec(get(&socket, "/_ping"))
+            .map_err(|err| PodmanInitError::ConnectionFailed(socket.clone(), err))?;
+        self.shell().info(format!(
+            "Connected to the Podman API at {}",
+            socket.display()
+        ));
+        self.socket = Some(socket);
+
+        // Make sure cgroups are mounted properly
+        if !util::cgroups_mounted_properly() {
+            return Err(PodmanInitError::InvalidCgroupMount);
+        }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
+
+        Ok(())
+    }
+
+    /// Gets all running containers from the libpod API
+    fn get_containers(&self) -> Result<Vec<Container>, Error> {
+        let body = self.exec(get(self.socket(), "/containers/json"))?;
+        let containers = serde_json::from_slice::<Vec<Container>>(&body)?;
+        Ok(containers)
+    }
+
+    /// Converts a container to a collection start event, preparing all
+    /// serialization/cgroup checks needed
+    fn make_start_event(
+        &mut self,
+        container: &Container,
+    ) -> Result<CollectionEvent, StartCollectionError> {
+        let method = self.get_collection_method(container)?;
+        let metadata = match serde_yaml::to_value(ContainerInfo::new(container)) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return Err(StartCollectionError::MetadataSerializationError(
+                    Error::from(err),
+                ));
+            },
+        };
+
+        Ok(CollectionEvent::Start {
+            method,
+            target: CollectionTarget {
+                provider:  PROVIDER_TYPE,
+                metadata:  Some(metadata),
+                name:      display(container).to_owned(),
+                poll_time: util::nano_ts(),
+                filter:    None,
+                id:        container.id.clone(),
+            },
+        })
+    }
+
+    /// Gets the collection method struct for the container, resolving the
+    /// proper collection method
+    fn get_collection_method(
+        &mut self,
+        container: &Container,
+    ) -> Result<CollectionMethod, StartCollectionError> {
+        match self.get_cgroup(container) {
+            Some(cgroup) => Ok(CollectionMethod::from(cgroup)),
+            None => Err(StartCollectionError::CgroupNotFound),
+        }
+    }
+
+    /// Gets the group path for the given container, printing out a
+    /// message upon the first successful cgroup resolution
+    fn get_cgroup(&mut self, c: &Container) -> Option<CgroupPath> {
+        // Determine if the manager had a resolved group beforehand
+        let had_driver = self.cgroup_manager.driver().is_some();
+
+        // Container cgroups are leaf cgroups by (full) container ID. Under the
+        // systemd driver, they are scopes in `machine.slice` (or in the
+        // user's slice when rootless); under the cgroupfs driver, they are
+        // under `libpod_parent`. Containers in a pod are instead placed in
+        // the pod's cgroup, whose slice is prefixed by its parent slice
+        let scope = format!("libpod-{}.scope", c.id);
+        let leaf = format!("libpod-{}", c.id);
+        let (systemd_parent, pod_prefix) = match self.uid {
+            0 => (String::from("machine.slice"), "machine"),
+            uid => (
+                format!("user.slice/user-{0}.slice/user@{0}.service/user.slice", uid),
+                "user",
+            ),
+        };
+        let cgroup_option: Option<CgroupPath> = if c.pod.is_empty() {
+            self.cgroup_manager.get_cgroup_divided(
+                &[&systemd_parent, &scope],
+                &[CGROUPFS_PARENT, &leaf],
+                false,
+            )
+        } else {
+            let pod_slice = format!("{}-libpod_pod_{}.slice", pod_prefix, c.pod);
+            self.cgroup_manager.get_cgroup_divided(
+                &[&systemd_parent, &pod_slice, &scope],
+                &[CGROUPFS_PARENT, &c.pod, &leaf],
+                false,
+            )
+        };
+
+        if !had_driver {
+            if let Some(driver) = self.cgroup_manager.driver() {
+                self.shell()
+                    .info(format!("Identified {} as cgroup driver", driver));
+            }
+        }
+
+        cgroup_option
+    }
+
+    /// Gets the location of the Podman API socket
+    fn socket(&self) -> &PathBuf {
+        self.socket
+            .as_ref()
+            .expect("Socket must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+/// Gets the default location of the Podman API socket for the given user,
+/// which is under `$XDG_RUNTIME_DIR` for rootless Podman
+fn default_socket(uid: u32) -> PathBuf {
+    match uid {
+        0 => PathBuf::from(ROOTFUL_SOCKET),
+        uid => std::env::var_os("XDG_RUNTIME_DIR")
+            .map_or_else(
+                || PathBuf::from(format!("/run/user/{}", uid)),
+                PathBuf::from,
+            )
+            .join("podman/podman.sock"),
+    }
+}
+
+/// Sends a GET request to the given (unversioned) libpod API endpoint over the
+/// Unix domain socket, returning the response body if successful
+async fn get(socket: &PathBuf, endpoint: &str) -> Result<Bytes, Error> {
+    let stream = UnixStream::connect(socket).await?;
+    let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+    // Drive the connection on the (current-thread) runtime until the response
+    // has been fully read
+    tokio_1::spawn(connection);
+
+    let request = Request::get(format!("/{}/libpod{}", LIBPOD_API_VERSION, endpoint))
+        .header(HOST, "d")
+        .body(Empty::<Bytes>::new())?;
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    if !status.is_success() {
+        return Err(format_err!(
+            "libpod API returned {} for {}: {}",
+            status,
+            endpoint,
+            String::from_utf8_lossy(&body)
+        ));
+    }
+
+    Ok(body)
+}
+
+/// Container info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInfo<'a> {
+    id:         &'a str,
+    names:      &'a [String],
+    image:      &'a str,
+    image_id:   &'a str,
+    command:    &'a Option<Vec<String>>,
+    created:    &'a serde_json::Value,
+    started_at: i64,
+    state:      &'a str,
+    labels:     &'a Option<BTreeMap<String, String>>,
+    pid:        i64,
+    pod:        Option<PodInfo<'a>>,
+}
+
+/// Info on the pod that a container belongs to
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodInfo<'a> {
+    id:   &'a str,
+    name: &'a str,
+}
+
+impl<'a> ContainerInfo<'a> {
+    /// Extracts all state/metadata from the given container, and collects it
+    /// in a single container info struct
+    fn new(c: &'a Container) -> Self {
+        let pod = match c.pod.is_empty() {
+            true => None,
+            false => Some(PodInfo {
+                id:   &c.pod,
+                name: &c.pod_name,
+            }),
+        };
+
+        ContainerInfo {
+            id: &c.id,
+            names: &c.names,
+            image: &c.image,
+            image_id: &c.image_id,
+            command: &c.command,
+            created: &c.created,
+            started_at: c.started_at,
+            state: &c.state,
+            labels: &c.labels,
+            pid: c.pid,
+            pod,
+        }
+    }
+}
+
+/// Whether radvisor should collect statistics for the given container
+fn should_collect_stats(c: &Container) -> bool { c.state == "running" }
+
+/// Gets a human-readable representation of the container, attempting to use the
+/// name before using the Id as a fallback
+fn display(container: &Container) -> &str { container.names.get(0).unwrap_or(&container.id) }
@@ -0,0 +1,408 @@
+use crate::cli::RunCommand;
+use crate::polling::providers::cgroups::{infer_driver, make_id};
+use crate::polling::providers::{InitializationError, Provider};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupPath, CgroupVersion, ItemPool};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use failure::Error;
+use regex::Regex;
+use serde::Serialize;
+
+/// Matcher recorded in the metadata of processes given directly by Id
+const PID_MATCHER: &str = "pid";
+
+const PROVIDER_TYPE: &str = "process";
+
+pub struct Process {
+    target_pool: ItemPool<String>,
+    version:     CgroupVersion,
+    pids:        Vec<u32>,
+    patterns:    Vec<Regex>,
+    proc_stats:  bool,
+    shell:       Option<Arc<Shell>>,
+}
+
+/// Possible errors that can occur during process provider initialization
+#[derive(Debug)]
+enum ProcessInitError {
+    InvalidCgroupMount,
+    InvalidPattern(String, regex::Error),
+}
+
+impl Into<InitializationError> for ProcessInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+            Self::InvalidPattern(raw, error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: format!(
+                    "Invalid process command pattern '{}'. Patterns are regular expressions \
+                     matched against the full command line of each process",
+                    raw
+                ),
+            },
+        }
+    }
+}
+
+/// Single running process that was either given directly or matched one of the
+/// command patterns
+#[derive(Clone, Debug, PartialEq)]
+struct MatchedProcess {
+    pid:        u32,
+    /// Start time of the process (in clock ticks after boot), used to
+    /// distinguish between processes if a process Id is reused
+    start_time: u64,
+    comm:       String,
+    cmdline:    String,
+    /// Process Id or command pattern that the process matched
+    matcher:    String,
+}
+
+impl Provider for Process {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell().status("Initializing", "process provider");
+
+        let options = opts.provider.clone().into_inner_process();
+        self.pids = options.pids;
+        self.proc_stats = options.per_process;
+        match self.try_init(&options.patterns) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        let processes = self.find_processes();
+        let process_num = processes.len();
+
+        // Map of target ids to the processes they contain. When collecting
+        // per-process stats, each target is a single process; otherwise, each
+        // target is a cgroup that contains one or more of the processes
+        let mut targets: BTreeMap<String, Vec<MatchedProcess>> = BTreeMap::new();
+        for process in processes {
+            let key = match self.proc_stats {
+                true => Some(format!("{}-{}", process.pid, process.start_time)),
+                false => self.resolve_cgroup(process.pid),
+            };
+            if let Some(key) = key {
+                targets.entry(key).or_default().push(process);
+            }
+        }
+
+        let keys = targets.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.target_pool.update(keys);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed_len);
+        // Add all removed targets as Stop events
+        events.extend(
+            removed
+                .into_iter()
+                .map(|key| CollectionEvent::Stop(self.make_target_id(&key))),
+        );
+
+        // Add all added targets as Start events
+        let start_events = added
+            .into_iter()
+            .filter_map(|key| {
+                // The pool only contains keys from the map, so this always exists
+                let processes = targets.get(&key)?;
+                match self.make_start_event(&key, processes) {
+                    Ok(start) => Some(start),
+                    Err(cause) => {
+                        self.shell()
+                            .warn(format!("Could not serialize process metadata: {}", cause));
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        if processed_num != 0 || removed_len != 0 {
+            self.shell().verbose(|sh| {
+                sh.info(format!(
+                    "Found {} matching process(es) in {} (+{}, -{}) targets",
+                    process_num,
+                    targets.len(),
+                    processed_num,
+                    removed_len
+                ))
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Default for Process {
+    fn default() -> Self { Self::new() }
+}
+
+impl Process {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            target_pool: ItemPool::new(),
+            version:     CgroupVersion::detect(),
+            pids:        Vec::new(),
+            patterns:    Vec::new(),
+            proc_stats:  false,
+            shell:       None,
+        }
+    }
+
+    /// Attempts to initialize the process provider, failing if the needed
+    /// Cgroups aren't mounted properly (when collecting cgroup stats) or if any
+    /// of the given command patterns are invalid regular expressions
+    fn try_init(&mut self, patterns: &[String]) -> Result<(), ProcessInitError> {
+        if !self.proc_stats {
+            // Make sure cgroups are mounted properly
+            if !util::cgroups_mounted_properly() {
+                return Err(ProcessInitError::InvalidCgroupMount);
+            }
+            self.shell().info(format!(
+                "Discovered cgroup hierarchies in {} mode",
+                util::cgroup_mounts().mode
+            ));
+        }
+
+        for raw in patterns {
+            let pattern = Regex::new(raw)
+                .map_err(|err| ProcessInitError::InvalidPattern(raw.clone(), err))?;
+            self.patterns.push(pattern);
+        }
+
+        Ok(())
+    }
+
+    /// Finds all running processes that were either given directly or that
+    /// match one of the command patterns. Processes that exit while being
+    /// examined are skipped
+    fn find_processes(&self) -> Vec<MatchedProcess> {
+        let mut processes: BTreeMap<u32, MatchedProcess> = BTreeMap::new();
+        for &pid in &self.pids {
+            if let Some(process) = examine(pid, String::from(PID_MATCHER)) {
+                processes.insert(pid, process);
+            }
+        }
+
+        if self.patterns.is_empty() {
+            return processes.into_values().collect();
+        }
+
+        let own_pid = process::id();
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return processes.into_values().collect(),
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => continue,
+            };
+            if pid == own_pid || processes.contains_key(&pid) {
+                continue;
+            }
+            let command = match read_command(pid) {
+                Some(command) => command,
+                None => continue,
+            };
+            if let Some(pattern) = self.patterns.iter().find(|p| p.is_match(&command)) {
+                if let Some(process) = examine(pid, String::from(pattern.as_str())) {
+                    processes.insert(pid, process);
+                }
+            }
+        }
+
+        processes.into_values().collect()
+    }
+
+    /// Resolves the cgroup that contains the given process from
+    /// `/proc/<pid>/cgroup`, as a path relative to the root of the cgroup
+    /// hierarchy. On cgroup v1, this is the cgroup in the hierarchy of the
+    /// `cpuacct` controller
+    fn resolve_cgroup(&self, pid: u32) -> Option<String> {
+        let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+        let cgroup = content.lines().find_map(|line| {
+            // Each line is in the form of hierarchy-ID:controller-list:cgroup-path
+            let mut parts = line.splitn(3, ':');
+            let hierarchy_id = parts.next()?;
+            let controllers = parts.next()?;
+            let path = parts.next()?;
+            let matches = match self.version {
+                CgroupVersion::V1 => controllers.split(',').any(|c| c == "cpuacct"),
+                CgroupVersion::V2 => hierarchy_id == "0" && controllers.is_empty(),
+            };
+            match matches {
+                true => Some(path),
+                false => None,
+            }
+        })?;
+
+        // Cgroups outside of the current cgroup namespace can't be collected
+        let path = Path::new(cgroup);
+        if path.components().any(|c| c == Component::ParentDir) {
+            return None;
+        }
+        let relative = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect::<PathBuf>();
+        Some(relative.to_string_lossy().into_owned())
+    }
+
+    /// Converts a matched process or cgroup to a collection start event,
+    /// preparing all serialization needed
+    fn make_start_event(
+        &self,
+        key: &str,
+        processes: &[MatchedProcess],
+    ) -> Result<CollectionEvent, Error> {
+        let infos = processes.iter().map(ProcessInfo::from).collect::<Vec<_>>();
+        let (method, name, metadata) = match self.proc_stats {
+            true => {
+                // Per-process targets always contain a single process
+                let process = &processes[0];
+                let name = format!("{}[{}]", process.comm, process.pid);
+                let metadata = serde_yaml::to_value(&infos[0])?;
+                (CollectionMethod::Process(process.pid), name, metadata)
+            },
+            false => {
+                let info = CgroupInfo {
+                    cgroup:    format!("/{}", key),
+                    processes: infos,
+                };
+                let metadata = serde_yaml::to_value(&info)?;
+                let path = CgroupPath {
+                    path:    PathBuf::from(key),
+                    driver:  infer_driver(key),
+                    version: self.version,
+                };
+                (CollectionMethod::from(path), info.cgroup, metadata)
+            },
+        };
+
+        Ok(CollectionEvent::Start {
+            method,
+            target: CollectionTarget {
+                provider: PROVIDER_TYPE,
+                metadata: Some(metadata),
+                name,
+                poll_time: util::nano_ts(),
+                filter: None,
+                id: self.make_target_id(key),
+            },
+        })
+    }
+
+    /// Creates a target Id for the given target pool key that can be used in
+    /// log file names
+    fn make_target_id(&self, key: &str) -> String {
+        match self.proc_stats {
+            true => String::from(key),
+            false => make_id(key),
+        }
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+/// Process info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProcessInfo<'a> {
+    pid:        u32,
+    start_time: u64,
+    comm:       &'a str,
+    cmdline:    &'a str,
+    matched:    &'a str,
+}
+
+impl<'a> From<&'a MatchedProcess> for ProcessInfo<'a> {
+    fn from(process: &'a MatchedProcess) -> Self {
+        Self {
+            pid:        process.pid,
+            start_time: process.start_time,
+            comm:       &process.comm,
+            cmdline:    &process.cmdline,
+            matched:    &process.matcher,
+        }
+    }
+}
+
+/// Cgroup info struct that gets included with each log file when collecting
+/// the cgroups that contain the matched processes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CgroupInfo<'a> {
+    cgroup:    String,
+    processes: Vec<ProcessInfo<'a>>,
+}
+
+/// Examines the given process in /proc, returning None if it doesn't exist (or
+/// exited in the meantime)
+fn examine(pid: u32, matcher: String) -> Option<MatchedProcess> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is enclosed in parentheses and may itself contain spaces
+    // or parentheses, so the remaining fields start after the last ')'
+    let comm_start = stat.find('(')?;
+    let comm_end = stat.rfind(')')?;
+    let comm = stat.get((comm_start + 1)..comm_end)?;
+    // The start time is field 22, which is the 20th field after the command name
+    let start_time = stat
+        .get((comm_end + 1)..)?
+        .split_whitespace()
+        .nth(19)?
+        .parse::<u64>()
+        .ok()?;
+
+    Some(MatchedProcess {
+        pid,
+        start_time,
+        comm: String::from(comm),
+        cmdline: read_command(pid).unwrap_or_default(),
+        matcher,
+    })
+}
+
+/// Reads the full command line of the given process, with each argument
+/// separated by a space. Kernel threads (and zombie processes) have an empty
+/// command line, in which case the command name is used instead
+fn read_command(pid: u32) -> Option<String> {
+    let raw = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>();
+    match args.is_empty() {
+        false => Some(args.join(" ")),
+        true => {
+            let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+            Some(String::from(comm.trim_end()))
+        },
+    }
+}
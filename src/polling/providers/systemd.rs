@@ -0,0 +1,491 @@
+use crate::cli::RunCommand;
+use crate::polling::providers::{InitializationError, Provider, SystemdOptions};
+use crate::shared::{CollectionEvent, CollectionMethod, CollectionTarget};
+use crate::shell::Shell;
+use crate::util::{self, CgroupDriver, CgroupPath, CgroupVersion, ItemPool};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::Error;
+use glob::{Pattern, PatternError};
+use serde::Serialize;
+use strum_macros::{EnumString, IntoStaticStr};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::OwnedObjectPath;
+
+/// Well-known bus name of the systemd service manager
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+
+/// Object path of the systemd service manager
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+
+/// Interface of the systemd service manager
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+/// Slice that system services are placed in by default, used when walking the
+/// cgroup hierarchy if D-Bus is unavailable
+const SYSTEM_SLICE: &str = "system.slice";
+
+const PROVIDER_TYPE: &str = "systemd";
+
+/// Single unit as returned from the `ListUnits` method of the service manager:
+/// (name, description, load state, active state, sub state, followed unit,
+/// unit object path, job Id, job type, job object path)
+type UnitListEntry = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    OwnedObjectPath,
+    u32,
+    String,
+    OwnedObjectPath,
+);
+
+pub struct Systemd {
+    unit_pool:  ItemPool<String>,
+    version:    CgroupVersion,
+    hierarchy:  Option<PathBuf>,
+    connection: Option<Connection>,
+    types:      Vec<UnitType>,
+    patterns:   Vec<Pattern>,
+    shell:      Option<Arc<Shell>>,
+}
+
+/// Possible errors that can occur during systemd provider initialization
+#[derive(Debug)]
+enum SystemdInitError {
+    InvalidCgroupMount,
+    InvalidPattern(String, PatternError),
+    MissingSystemSlice(zbus::Error),
+}
+
+impl Into<InitializationError> for SystemdInitError {
+    fn into(self) -> InitializationError {
+        match self {
+            Self::InvalidCgroupMount => InitializationError {
+                original:   None,
+                suggestion: String::from(util::INVALID_CGROUP_MOUNT_MESSAGE),
+            },
+            Self::InvalidPattern(raw, error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: format!(
+                    "Invalid unit name pattern '{}'. Patterns use shell-style globs (such as \
+                     nginx*.service)",
+                    raw
+                ),
+            },
+            Self::MissingSystemSlice(error) => InitializationError {
+                original:   Some(error.into()),
+                suggestion: String::from(
+                    "Could not connect to systemd over the D-Bus system bus, and could not find \
+                     system.slice in the cgroup hierarchy to fall back to.\nMake sure that the \
+                     current machine is running systemd.",
+                ),
+            },
+        }
+    }
+}
+
+/// Possible error that can occur during systemd unit collection target
+/// initialization
+#[derive(Debug)]
+enum StartCollectionError {
+    MetadataSerializationError(Error),
+    PropertyFetchError(zbus::Error),
+    MissingControlGroup,
+    CgroupNotFound(String),
+}
+
+/// Type of systemd unit that has its own cgroup
+#[derive(EnumString, IntoStaticStr, Clone, Copy, Debug, PartialEq, Serialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UnitType {
+    Service,
+    Scope,
+    Slice,
+}
+
+impl UnitType {
+    /// Gets the unit type from the suffix of a unit name (such as
+    /// `nginx.service`)
+    fn from_name(name: &str) -> Option<Self> {
+        let (_, suffix) = name.rsplit_once('.')?;
+        suffix.parse().ok()
+    }
+
+    /// Gets the type-specific D-Bus interface of units of this type, which
+    /// includes the `ControlGroup` property
+    const fn interface(self) -> &'static str {
+        match self {
+            Self::Service => "org.freedesktop.systemd1.Service",
+            Self::Scope => "org.freedesktop.systemd1.Scope",
+            Self::Slice => "org.freedesktop.systemd1.Slice",
+        }
+    }
+}
+
+/// Active unit found either over D-Bus or by walking the cgroup hierarchy
+#[derive(Clone, Debug, PartialEq)]
+struct Unit {
+    name:        String,
+    kind:        UnitType,
+    description: Option<String>,
+    /// D-Bus object path of the unit, if discovered over D-Bus
+    object_path: Option<OwnedObjectPath>,
+    /// Control group of the unit, if already known
+    cgroup:      Option<String>,
+}
+
+impl Provider for Systemd {
+    fn initialize(
+        &mut self,
+        opts: &RunCommand,
+        shell: Arc<Shell>,
+    ) -> Result<(), InitializationError> {
+        self.shell = Some(Arc::clone(&shell));
+        self.shell().status("Initializing", "systemd unit provider");
+
+        let systemd_opts = opts.provider.clone().into_inner_systemd();
+        match self.try_init(systemd_opts) {
+            Ok(_) => Ok(()),
+            Err(init_err) => Err(init_err.into()),
+        }
+    }
+
+    fn poll(&mut self) -> Result<Vec<CollectionEvent>, Error> {
+        let units = match self.connection {
+            Some(_) => self.list_units_dbus()?,
+            None => self.list_units_cgroupfs()?,
+        };
+
+        let original_num = units.len();
+        let to_collect: BTreeMap<String, Unit> = units
+            .into_iter()
+            .filter(|u| self.should_collect_stats(u))
+            .map(|u| (u.name.clone(), u))
+            .collect::<BTreeMap<_, _>>();
+
+        let names = to_collect.keys().map(String::clone);
+        let mut events: Vec<CollectionEvent> = Vec::new();
+        let (added, removed) = self.unit_pool.update(names);
+
+        let removed_len = removed.len();
+        events.reserve_exact(added.len() + removed_len);
+        // Add all removed units as Stop events
+        events.extend(removed.into_iter().map(CollectionEvent::Stop));
+
+        // Add all added units as Start events
+        let start_events = added
+            .into_iter()
+            .filter_map(|name| {
+                // It shouldn't be possible to have a name that doesn't exist in the map, but
+                // check anyways
+                let unit = match to_collect.get(&name) {
+                    Some(unit) => unit,
+                    None => {
+                        self.shell().error(format!(
+                            "Processed name from ItemPool added result that was not in fetched \
+                             unit list. This is a bug!\nName: {}",
+                            name
+                        ));
+                        return None;
+                    },
+                };
+
+                match self.make_start_event(unit) {
+                    Ok(start) => Some(start),
+                    Err(error) => {
+                        match error {
+                            StartCollectionError::MissingControlGroup => {
+                                self.shell().warn(format!(
+                                    "Could not create unit metadata for unit {}: unit does not \
+                                     have a control group",
+                                    unit.name
+                                ));
+                            },
+                            StartCollectionError::CgroupNotFound(cgroup) => {
+                                self.shell().warn(format!(
+                                    "Could not create unit metadata for unit {}: cgroup {} does \
+                                     not exist (is accounting enabled for the unit?)",
+                                    unit.name, cgroup
+                                ));
+                            },
+                            StartCollectionError::PropertyFetchError(cause) => {
+                                self.shell().warn(format!(
+                                    "Could not fetch unit properties for unit {}: {}",
+                                    unit.name, cause
+                                ));
+                            },
+                            StartCollectionError::MetadataSerializationError(cause) => {
+                                self.shell()
+                                    .warn(format!("Could not serialize unit metadata: {}", cause));
+                            },
+                        }
+
+                        // Ignore unit and continue initializing the rest
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let processed_num = start_events.len();
+        events.extend(start_events);
+
+        if processed_num != 0 || removed_len != 0 {
+            self.shell().verbose(|sh| {
+                sh.info(format!(
+                    "Received {} -> {} (+{}, -{}) units from {}",
+                    original_num,
+                    to_collect.len(),
+                    processed_num,
+                    removed_len,
+                    match self.connection {
+                        Some(_) => "the systemd D-Bus API",
+                        None => SYSTEM_SLICE,
+                    }
+                ))
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+impl Default for Systemd {
+    fn default() -> Self { Self::new() }
+}
+
+impl Systemd {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            unit_pool:  ItemPool::new(),
+            version:    CgroupVersion::detect(),
+            hierarchy:  None,
+            connection: None,
+            types:      Vec::new(),
+            patterns:   Vec::new(),
+            shell:      None,
+        }
+    }
+
+    /// Attempts to initialize the systemd provider, failing if the needed
+    /// Cgroups aren't mounted properly, if any of the name patterns are
+    /// invalid, or if neither D-Bus nor the system slice are available
+    fn try_init(&mut self, opts: SystemdOptions) -> Result<(), SystemdInitError> {
+        // Make sure cgroups are mounted properly
+        if !util::cgroups_mounted_properly() {
+            return Err(SystemdInitError::InvalidCgroupMount);
+        }
+        self.shell().info(format!(
+            "Discovered cgroup hierarchies in {} mode",
+            util::cgroup_mounts().mode
+        ));
+        self.hierarchy =
+            Some(util::cgroup_dir("", self.version).ok_or(SystemdInitError::InvalidCgroupMount)?);
+
+        self.types = opts.types;
+        for raw in &opts.units {
+            let pattern = Pattern::new(raw)
+                .map_err(|err| SystemdInitError::InvalidPattern(raw.clone(), err))?;
+            self.patterns.push(pattern);
+        }
+
+        // Connect to the system bus, falling back to walking the system slice
+        match Connection::system() {
+            Ok(connection) => {
+                self.shell()
+                    .info("Connected to systemd over the D-Bus system bus");
+                self.connection = Some(connection);
+            },
+            Err(err) => {
+                if !self.hierarchy().join(SYSTEM_SLICE).is_dir() {
+                    return Err(SystemdInitError::MissingSystemSlice(err));
+                }
+                self.shell().warn(format!(
+                    "Could not connect to the D-Bus system bus ({}); falling back to walking {}",
+                    err, SYSTEM_SLICE
+                ));
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Lists all active units from the systemd service manager over D-Bus
+    fn list_units_dbus(&self) -> Result<Vec<Unit>, Error> {
+        let proxy = Proxy::new(
+            self.connection(),
+            SYSTEMD_DESTINATION,
+            SYSTEMD_PATH,
+            MANAGER_INTERFACE,
+        )?;
+        let entries: Vec<UnitListEntry> = proxy.call("ListUnits", &())?;
+        let units = entries
+            .into_iter()
+            .filter(|entry| entry.3 == "active")
+            .filter_map(|(name, description, _, _, _, _, object_path, ..)| {
+                let kind = UnitType::from_name(&name)?;
+                Some(Unit {
+                    name,
+                    kind,
+                    description: Some(description),
+                    object_path: Some(object_path),
+                    cgroup: None,
+                })
+            })
+            .collect();
+        Ok(units)
+    }
+
+    /// Lists all units that have a cgroup directly under the system slice, used
+    /// if D-Bus is unavailable
+    fn list_units_cgroupfs(&self) -> Result<Vec<Unit>, Error> {
+        let system_slice = self.hierarchy().join(SYSTEM_SLICE);
+        let units = fs::read_dir(&system_slice)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let kind = UnitType::from_name(&name)?;
+                Some(Unit {
+                    cgroup: Some(format!("/{}/{}", SYSTEM_SLICE, name)),
+                    name,
+                    kind,
+                    description: None,
+                    object_path: None,
+                })
+            })
+            .collect();
+        Ok(units)
+    }
+
+    /// Whether radvisor should collect statistics for the given unit, according
+    /// to the unit type and name filters
+    fn should_collect_stats(&self, unit: &Unit) -> bool {
+        self.types.contains(&unit.kind)
+            && (self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(&unit.name)))
+    }
+
+    /// Converts a unit to a collection start event, fetching its control group
+    /// and main PID and preparing all serialization/cgroup checks needed
+    fn make_start_event(&self, unit: &Unit) -> Result<CollectionEvent, StartCollectionError> {
+        let (cgroup, main_pid) = match &unit.object_path {
+            Some(object_path) => self
+                .get_unit_properties(unit, object_path)
+                .map_err(StartCollectionError::PropertyFetchError)?,
+            None => (unit.cgroup.clone().unwrap_or_default(), None),
+        };
+        if cgroup.is_empty() {
+            return Err(StartCollectionError::MissingControlGroup);
+        }
+
+        let method = self.get_collection_method(&cgroup)?;
+        let info = UnitInfo {
+            name: &unit.name,
+            kind: unit.kind,
+            description: unit.description.as_deref(),
+            control_group: &cgroup,
+            main_pid,
+        };
+        let metadata = match serde_yaml::to_value(&info) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return Err(StartCollectionError::MetadataSerializationError(
+                    Error::from(err),
+                ));
+            },
+        };
+
+        Ok(CollectionEvent::Start {
+            method,
+            target: CollectionTarget {
+                provider:  PROVIDER_TYPE,
+                metadata:  Some(metadata),
+                name:      unit.name.clone(),
+                poll_time: util::nano_ts(),
+                filter:    None,
+                id:        unit.name.clone(),
+            },
+        })
+    }
+
+    /// Fetches the `ControlGroup` property (and the `MainPID` property, for
+    /// services) of the given unit over D-Bus
+    fn get_unit_properties(
+        &self,
+        unit: &Unit,
+        object_path: &OwnedObjectPath,
+    ) -> Result<(String, Option<u32>), zbus::Error> {
+        let proxy = Proxy::new(
+            self.connection(),
+            SYSTEMD_DESTINATION,
+            object_path.as_str(),
+            unit.kind.interface(),
+        )?;
+        let cgroup: String = proxy.get_property("ControlGroup")?;
+        let main_pid: Option<u32> = match unit.kind {
+            UnitType::Service => Some(proxy.get_property("MainPID")?),
+            UnitType::Scope | UnitType::Slice => None,
+        };
+        Ok((cgroup, main_pid))
+    }
+
+    /// Gets the collection method struct for the given control group, making
+    /// sure that it exists in the cgroup hierarchy that is collected from
+    fn get_collection_method(
+        &self,
+        cgroup: &str,
+    ) -> Result<CollectionMethod, StartCollectionError> {
+        let relative = cgroup.trim_start_matches('/');
+        if !self.hierarchy().join(relative).is_dir() {
+            return Err(StartCollectionError::CgroupNotFound(cgroup.to_owned()));
+        }
+
+        Ok(CollectionMethod::from(CgroupPath {
+            path:    PathBuf::from(relative),
+            driver:  CgroupDriver::Systemd,
+            version: self.version,
+        }))
+    }
+
+    /// Gets the absolute directory of the root of the cgroup hierarchy
+    fn hierarchy(&self) -> &Path {
+        self.hierarchy
+            .as_ref()
+            .expect("Hierarchy must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current D-Bus connection
+    fn connection(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("Connection must be initialized: invariant violated")
+    }
+
+    /// Gets a reference to the current shell
+    fn shell(&self) -> &Shell {
+        self.shell
+            .as_ref()
+            .expect("Shell must be initialized: invariant violated")
+    }
+}
+
+/// Unit info struct that gets included with each log file
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct UnitInfo<'a> {
+    name:          &'a str,
+    #[serde(rename = "Type")]
+    kind:          UnitType,
+    description:   Option<&'a str>,
+    control_group: &'a str,
+    #[serde(rename = "MainPID")]
+    main_pid:      Option<u32>,
+}
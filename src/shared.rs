@@ -1,5 +1,5 @@
 use crate::shell::Shell;
-use crate::util::CgroupPath;
+use crate::util::{CgroupPath, CgroupVersion};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -32,6 +32,24 @@ pub enum CollectionEvent {
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum CollectionMethod {
     LinuxCgroups(CgroupPath),
+    LinuxCgroupsV2(CgroupPath),
+    /// Single process, identified by its process ID
+    Process(u32),
+    /// System-wide statistics, collected by a built-in target
+    Host,
+    /// System-wide pressure stall information, collected by a built-in target
+    HostPressure,
+}
+
+impl From<CgroupPath> for CollectionMethod {
+    /// Selects the cgroup collection method that matches the version of the
+    /// hierarchy that the cgroup was resolved in
+    fn from(cgroup: CgroupPath) -> Self {
+        match cgroup.version {
+            CgroupVersion::V1 => Self::LinuxCgroups(cgroup),
+            CgroupVersion::V2 => Self::LinuxCgroupsV2(cgroup),
+        }
+    }
 }
 
 /// Single container/pod/process/other entity that represents a single target
@@ -49,4 +67,7 @@ pub struct CollectionTarget {
     pub metadata:  Option<serde_yaml::Value>,
     /// Time of polling
     pub poll_time: u128,
+    /// Expression of the target filter that the target was selected with, if
+    /// the provider filtered its targets
+    pub filter:    Option<String>,
 }
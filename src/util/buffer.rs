@@ -7,8 +7,10 @@ use std::str::from_utf8;
 
 use serde::{Serialize, Serializer};
 
-/// Size of internal capacity
-pub const SIZE: usize = 1024;
+/// Size of internal capacity. Large enough to fit the entirety of the larger
+/// stat files, such as `memory.stat` in the unified (v2) cgroup hierarchy or
+/// `/proc/stat` and `/proc/vmstat` on hosts with many CPUs
+pub const SIZE: usize = 64 * 1024;
 
 /// Working buffer of raw bytes. Can operate both in **managed** mode (where it
 /// keeps track of length) and **unmanaged** mode (where it acts) as a plain
@@ -59,6 +61,9 @@ impl Buffer {
         buffer
     }
 
+    // Buffers are only created once per collection thread, where they get moved
+    // into the long-lived working buffers
+    #[allow(clippy::large_stack_arrays)]
     #[must_use]
     pub const fn new() -> Self {
         Self {
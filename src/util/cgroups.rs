@@ -1,3 +1,4 @@
+use crate::util::mountinfo::{cgroup_mounts, CgroupMode};
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -22,18 +23,57 @@ impl fmt::Display for CgroupDriver {
     }
 }
 
+/// Version of the cgroup hierarchy mounted on the current system, which
+/// determines the layout of the cgroup virtual filesystem
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupVersion {
+    /// Legacy hierarchy, with one directory per resource controller
+    V1,
+    /// Unified hierarchy, with all controllers in a single directory per cgroup
+    V2,
+}
+
+impl CgroupVersion {
+    /// Detects the version of the cgroup hierarchy to collect statistics from,
+    /// using the unified (v2) hierarchy only if no cgroup v1 controllers are
+    /// mounted (hybrid systems still expose all controllers through v1)
+    #[must_use]
+    pub fn detect() -> Self {
+        match cgroup_mounts().mode {
+            CgroupMode::V2 => Self::V2,
+            CgroupMode::V1 | CgroupMode::Hybrid => Self::V1,
+        }
+    }
+}
+
+impl fmt::Display for CgroupVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V1 => write!(f, "v1"),
+            Self::V2 => write!(f, "v2"),
+        }
+    }
+}
+
 /// Encapsulated behavior for lazy-resolution of Docker cgroup driver (systemd
-/// or cgroupfs). Works for cgroups v1
+/// or cgroupfs). Works for both cgroups v1 and v2
 pub struct CgroupManager {
-    driver: Option<CgroupDriver>,
+    driver:  Option<CgroupDriver>,
+    version: CgroupVersion,
+    /// Directory that cgroups are resolved in instead of the mounted cgroup
+    /// hierarchy, if any (only used by tests)
+    #[cfg(test)]
+    root:    Option<PathBuf>,
 }
 
 /// Resolved and existing cgroup path constructed from the construction methods
 /// on `CgroupManager`
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct CgroupPath {
-    pub path:   PathBuf,
-    pub driver: CgroupDriver,
+    pub path:    PathBuf,
+    pub driver:  CgroupDriver,
+    pub version: CgroupVersion,
 }
 
 impl Default for CgroupManager {
@@ -41,9 +81,29 @@ impl Default for CgroupManager {
 }
 
 impl CgroupManager {
-    /// Creates a new cgroup manager with an unknown driver type
+    /// Creates a new cgroup manager with an unknown driver type, detecting the
+    /// version of the mounted cgroup hierarchy
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            driver:            None,
+            version:           CgroupVersion::detect(),
+            #[cfg(test)]
+            root:              None,
+        }
+    }
+
+    /// Creates a new cgroup manager that resolves cgroups as directories in the
+    /// given root directory, rather than in the mounted cgroup hierarchy
+    #[cfg(test)]
     #[must_use]
-    pub const fn new() -> Self { Self { driver: None } }
+    pub fn with_root(root: PathBuf, version: CgroupVersion) -> Self {
+        Self {
+            driver: None,
+            version,
+            root: Some(root),
+        }
+    }
 
     /// Joins together the given slices to make a target cgroup, performing
     /// formatting conversions as necessary to target the current cgroup
@@ -60,10 +120,7 @@ impl CgroupManager {
         match self.driver {
             Some(driver) => {
                 let path = make(driver, slices, construct);
-                match cgroup_exists(&path) {
-                    true => Some(CgroupPath { path, driver }),
-                    false => None,
-                }
+                self.existing(path, driver)
             },
             None => self
                 .try_resolve(CgroupDriver::Systemd, slices, construct)
@@ -97,10 +154,7 @@ impl CgroupManager {
                     CgroupDriver::Systemd { .. } => make(driver, systemd_slices, construct),
                     CgroupDriver::Cgroupfs => make(driver, cgroupfs_slices, construct),
                 };
-                match cgroup_exists(&path) {
-                    true => Some(CgroupPath { path, driver }),
-                    false => None,
-                }
+                self.existing(path, driver)
             },
             None => self
                 .try_resolve(CgroupDriver::Systemd, systemd_slices, construct)
@@ -108,6 +162,15 @@ impl CgroupManager {
         }
     }
 
+    /// Joins the given child slice to an already-resolved cgroup, returning
+    /// `Some(path)` if the child cgroup exists, else `None`. The child slice is
+    /// used as-is, since its formatting generally depends on the runtime that
+    /// created it rather than the cgroup driver
+    #[must_use]
+    pub fn get_child(&self, parent: &CgroupPath, child: &str) -> Option<CgroupPath> {
+        self.existing(parent.path.join(child), parent.driver)
+    }
+
     /// Attempts to resolve the cgroup driver, by making the cgroup path for the
     /// given driver and then testing whether it exists
     ///
@@ -120,18 +183,44 @@ impl CgroupManager {
         construct: bool,
     ) -> Option<CgroupPath> {
         let path = make(driver, slices, construct);
-        match cgroup_exists(&path) {
+        let cgroup = self.existing(path, driver)?;
+        self.driver = Some(driver);
+        Some(cgroup)
+    }
+
+    /// Wraps the given cgroup path if it exists in the cgroup filesystem
+    fn existing(&self, path: PathBuf, driver: CgroupDriver) -> Option<CgroupPath> {
+        match self.exists(&path) {
+            true => Some(CgroupPath {
+                path,
+                driver,
+                version: self.version,
+            }),
             false => None,
-            true => {
-                self.driver = Some(driver);
-                Some(CgroupPath { path, driver })
-            },
+        }
+    }
+
+    /// Whether the given cgroup exists in the cgroup filesystem
+    #[cfg(not(test))]
+    fn exists(&self, path: &Path) -> bool { cgroup_exists(path, self.version) }
+
+    /// Whether the given cgroup exists in the root directory of the manager (if
+    /// any), or else in the cgroup filesystem
+    #[cfg(test)]
+    fn exists(&self, path: &Path) -> bool {
+        match &self.root {
+            Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)).is_dir(),
+            None => cgroup_exists(path, self.version),
         }
     }
 
     /// Gets the current resolved driver for the manager
     #[must_use]
     pub const fn driver(&self) -> Option<CgroupDriver> { self.driver }
+
+    /// Gets the version of the cgroup hierarchy the manager resolves paths in
+    #[must_use]
+    pub const fn version(&self) -> CgroupVersion { self.version }
 }
 
 /// Constructs a cgroup absolute path according to the style expected by the
@@ -204,30 +293,45 @@ pub fn escape_systemd(slice: &str) -> String { slice.replace("-", "_") }
 fn make_cgroupfs(slices: &[&str]) -> PathBuf { slices.iter().collect() }
 
 pub const INVALID_CGROUP_MOUNT_MESSAGE: &str =
-    "rAdvisor expects cgroups to be mounted in /sys/fs/cgroup. If this is\nthe case, make sure \
-     that the 'cpuacct' resource controller has not been disabled.";
+    "rAdvisor could not find the expected cgroup hierarchies in /proc/self/mountinfo. Make \
+     sure\nthat the 'cpuacct' resource controller has not been disabled (cgroup v1/hybrid) or \
+     that\nthe unified hierarchy is mounted (cgroup v2).";
 
-/// Checks if cgroups are mounted in /sys/fs/cgroup and if the cpuacct subsystem
-/// is enabled (necessary for proper driver detection)
+/// Checks if the cgroup hierarchy used for driver detection is mounted: the
+/// hierarchy containing the cpuacct subsystem for cgroup v1, or the unified
+/// hierarchy for cgroup v2
 #[must_use]
 pub fn cgroups_mounted_properly() -> bool {
     // Use the raw subsystem directory to see if the expected cgroup hierarchy
     // exists
-    cgroup_exists("")
+    cgroup_exists("", CgroupVersion::detect())
 }
 
 pub const LINUX_CGROUP_ROOT: &str = "/sys/fs/cgroup";
 
+/// Gets the absolute path of the directory for the given (relative) cgroup in
+/// the virtual filesystem, using the discovered cgroup mounts. On cgroup v1,
+/// this is the directory in the hierarchy of the `cpuacct` controller, while on
+/// cgroup v2 this is the directory in the unified hierarchy. If the hierarchy
+/// isn't mounted, returns None
+#[must_use]
+pub fn cgroup_dir<C: AsRef<Path>>(path: C, version: CgroupVersion) -> Option<PathBuf> {
+    let mounts = cgroup_mounts();
+    let mount = match version {
+        CgroupVersion::V1 => mounts.controller("cpuacct"),
+        CgroupVersion::V2 => mounts.unified.as_ref(),
+    }?;
+    Some(mount.resolve(path))
+}
+
 /// Determines whether the given (absolute) cgroup exists in the virtual
-/// filesystem **Note**: fails if cgroups aren't mounted in /sys/fs/cgroup or if
-/// the cpuacct subsystem isn't enabled.
+/// filesystem **Note**: fails if the needed cgroup hierarchy isn't mounted or
+/// if the cpuacct subsystem isn't enabled (on cgroup v1).
 #[must_use]
-fn cgroup_exists<C: AsRef<Path>>(path: C) -> bool {
-    let mut full_path: PathBuf = [LINUX_CGROUP_ROOT, "cpuacct"].iter().collect();
-    full_path.push(path);
-    match fs::metadata(full_path) {
-        Err(_) => false,
+fn cgroup_exists<C: AsRef<Path>>(path: C, version: CgroupVersion) -> bool {
+    match cgroup_dir(path, version).map(fs::metadata) {
+        None | Some(Err(_)) => false,
         // As long as it exists and is a directory, assume all is good
-        Ok(metadata) => metadata.is_dir(),
+        Some(Ok(metadata)) => metadata.is_dir(),
     }
 }
@@ -10,6 +10,7 @@ pub(self) mod buffer;
 pub(self) mod byte;
 pub(self) mod cgroups;
 pub(self) mod lazy_quantity;
+pub(self) mod mountinfo;
 pub(self) mod pool;
 pub(self) mod system;
 
@@ -17,6 +18,7 @@ pub use buffer::*;
 pub use byte::*;
 pub use cgroups::*;
 pub use lazy_quantity::*;
+pub use mountinfo::*;
 pub use pool::*;
 pub use system::*;
 
@@ -0,0 +1,254 @@
+//! Discovery of cgroup hierarchy mounts via `/proc/self/mountinfo`, used to
+//! find where each resource controller lives instead of assuming
+//! `/sys/fs/cgroup/<controller>`
+
+use crate::util::cgroups::LINUX_CGROUP_ROOT;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// Location of the mount information for the current process's mount namespace
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Filesystem type of cgroup v1 hierarchies
+const CGROUP_V1_FS: &str = "cgroup";
+
+/// Filesystem type of the unified cgroup v2 hierarchy
+const CGROUP_V2_FS: &str = "cgroup2";
+
+/// Controllers assumed to be mounted at `/sys/fs/cgroup/<controller>` if the
+/// mount information can't be read
+const ASSUMED_V1_CONTROLLERS: &[&str] = &["pids", "cpu", "cpuacct", "memory", "blkio"];
+
+/// Overall layout of the cgroup hierarchies mounted on the system
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupMode {
+    /// Only cgroup v1 hierarchies, one per (group of co-mounted) controllers
+    V1,
+    /// Only the unified cgroup v2 hierarchy
+    V2,
+    /// cgroup v1 controller hierarchies alongside an unified hierarchy (that
+    /// usually has no controllers enabled)
+    Hybrid,
+}
+
+impl fmt::Display for CgroupMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V1 => write!(f, "v1"),
+            Self::V2 => write!(f, "v2"),
+            Self::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+/// Single mounted cgroup hierarchy
+#[derive(Clone, Debug, PartialEq)]
+pub struct CgroupMount {
+    /// Location that the hierarchy is mounted at
+    pub mount_point: PathBuf,
+    /// Cgroup in the hierarchy that forms the root of the mount. This is `/`
+    /// unless the process is in a non-root cgroup namespace or the hierarchy
+    /// was bind-mounted
+    pub root:        PathBuf,
+}
+
+impl CgroupMount {
+    /// Resolves the absolute directory of the given cgroup in the mounted
+    /// hierarchy, accounting for the root of the mount
+    #[must_use]
+    pub fn resolve<C: AsRef<Path>>(&self, cgroup: C) -> PathBuf {
+        let cgroup = cgroup.as_ref();
+        let absolute = Path::new("/").join(cgroup);
+        let relative = absolute
+            .strip_prefix(&self.root)
+            .or_else(|_| absolute.strip_prefix("/"))
+            .unwrap_or(cgroup);
+        let mut full_path = self.mount_point.clone();
+        // Pushing an empty path would add a trailing separator
+        if !relative.as_os_str().is_empty() {
+            full_path.push(relative);
+        }
+        full_path
+    }
+}
+
+/// Map of every mounted cgroup hierarchy on the system, discovered from the
+/// mount information of the current process
+#[derive(Clone, Debug, PartialEq)]
+pub struct CgroupMounts {
+    pub mode:        CgroupMode,
+    /// Map of cgroup v1 controller names (such as `cpuacct`) to the hierarchy
+    /// they are mounted in. Co-mounted controllers (such as `cpu,cpuacct`)
+    /// each have an entry
+    pub controllers: BTreeMap<String, CgroupMount>,
+    /// Unified cgroup v2 hierarchy, if mounted
+    pub unified:     Option<CgroupMount>,
+}
+
+lazy_static::lazy_static! {
+    /// Cgroup mounts discovered once at startup
+    static ref MOUNTS: CgroupMounts = CgroupMounts::discover();
+}
+
+/// Gets the cgroup mounts discovered for the current process
+#[must_use]
+pub fn cgroup_mounts() -> &'static CgroupMounts { &MOUNTS }
+
+impl CgroupMounts {
+    /// Discovers all cgroup hierarchies by parsing `/proc/self/mountinfo`,
+    /// falling back to assuming the standard layout under `/sys/fs/cgroup`
+    /// if the mount information can't be read
+    #[must_use]
+    pub fn discover() -> Self {
+        match fs::read_to_string(MOUNTINFO_PATH) {
+            Ok(content) => Self::parse(&content).unwrap_or_else(Self::assumed),
+            Err(_) => Self::assumed(),
+        }
+    }
+
+    /// Parses the contents of a `mountinfo` file, returning None if no cgroup
+    /// hierarchies were found.
+    /// Each line is in the form of:
+    /// ```txt
+    /// 36 25 0:31 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid - cgroup cgroup rw,cpu,cpuacct
+    /// 30 25 0:26 / /sys/fs/cgroup/unified rw,nosuid shared:7 - cgroup2 cgroup2 rw
+    /// ```
+    /// see <https://www.kernel.org/doc/Documentation/filesystems/proc.txt>
+    #[must_use]
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut controllers: BTreeMap<String, CgroupMount> = BTreeMap::new();
+        let mut unified: Option<CgroupMount> = None;
+
+        for line in content.lines() {
+            match parse_line(line) {
+                Some(MountLine::Unified(mount)) => unified = Some(mount),
+                Some(MountLine::Controllers(mount, super_options)) => {
+                    // Named hierarchies (such as name=systemd) don't have any controllers
+                    for option in super_options.split(',') {
+                        if is_controller_option(option) {
+                            controllers.insert(option.to_owned(), mount.clone());
+                        }
+                    }
+                },
+                None => {},
+            }
+        }
+
+        let mode = match (controllers.is_empty(), unified.is_some()) {
+            (true, false) => return None,
+            (true, true) => CgroupMode::V2,
+            (false, false) => CgroupMode::V1,
+            (false, true) => CgroupMode::Hybrid,
+        };
+        Some(Self {
+            mode,
+            controllers,
+            unified,
+        })
+    }
+
+    /// Assumes the standard layout of cgroup mounts under `/sys/fs/cgroup`,
+    /// using the presence of `cgroup.controllers` to detect the unified
+    /// hierarchy
+    #[must_use]
+    pub fn assumed() -> Self {
+        let root_mount = |mount_point: PathBuf| CgroupMount {
+            mount_point,
+            root: PathBuf::from("/"),
+        };
+        let root = PathBuf::from(LINUX_CGROUP_ROOT);
+
+        if root.join("cgroup.controllers").exists() {
+            Self {
+                mode:        CgroupMode::V2,
+                controllers: BTreeMap::new(),
+                unified:     Some(root_mount(root)),
+            }
+        } else {
+            Self {
+                mode:        CgroupMode::V1,
+                controllers: ASSUMED_V1_CONTROLLERS
+                    .iter()
+                    .map(|&c| (c.to_owned(), root_mount(root.join(c))))
+                    .collect(),
+                unified:     None,
+            }
+        }
+    }
+
+    /// Gets the hierarchy that the given cgroup v1 controller is mounted in
+    #[must_use]
+    pub fn controller(&self, controller: &str) -> Option<&CgroupMount> {
+        self.controllers.get(controller)
+    }
+}
+
+/// Single cgroup hierarchy mount parsed from a line in `mountinfo`
+enum MountLine<'a> {
+    /// Unified cgroup v2 hierarchy
+    Unified(CgroupMount),
+    /// cgroup v1 hierarchy, along with its super options (that include the
+    /// names of its controllers)
+    Controllers(CgroupMount, &'a str),
+}
+
+/// Parses a single line in `mountinfo`, returning None if it isn't a cgroup
+/// hierarchy mount
+fn parse_line(line: &str) -> Option<MountLine<'_>> {
+    // The optional fields end with a single hyphen, after which come the
+    // filesystem type, mount source, and super options
+    let mut halves = line.splitn(2, " - ");
+    let mount_fields = halves.next()?;
+    let mut fs_fields = halves.next()?.split(' ');
+    let fs_type = fs_fields.next()?;
+    let super_options = fs_fields.nth(1).unwrap_or("");
+
+    let mut mount_fields = mount_fields.split(' ').skip(3);
+    let mount = CgroupMount {
+        root:        PathBuf::from(unescape(mount_fields.next()?)),
+        mount_point: PathBuf::from(unescape(mount_fields.next()?)),
+    };
+
+    match fs_type {
+        CGROUP_V2_FS => Some(MountLine::Unified(mount)),
+        CGROUP_V1_FS => Some(MountLine::Controllers(mount, super_options)),
+        _ => None,
+    }
+}
+
+/// Whether the given cgroup v1 super option names a resource controller, as
+/// opposed to a generic mount option or a named hierarchy
+fn is_controller_option(option: &str) -> bool {
+    !matches!(
+        option,
+        "rw" | "ro" | "noprefix" | "clone_children" | "xattr" | "cpuset_v2_mode" | ""
+    ) && !option.contains('=')
+}
+
+/// Decodes the octal escapes (such as `\040` for a space) used by the kernel
+/// for special characters in paths in `mountinfo`
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let code = std::str::from_utf8(&bytes[(i + 1)..(i + 4)])
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+            if let Some(code) = code {
+                decoded.push(code);
+                i += 4;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -53,4 +53,16 @@ impl<T: Ord + Clone> ItemPool<T> {
         working_set.clear();
         (added, removed)
     }
+
+    /// Adds a single item to the pool outside of a full update, returning
+    /// whether the item was newly added
+    pub fn insert(&mut self, item: T) -> bool { self.items.insert(item) }
+
+    /// Removes a single item from the pool outside of a full update, returning
+    /// whether the item was previously in the pool
+    pub fn remove(&mut self, item: &T) -> bool { self.items.remove(item) }
+
+    /// Whether the item is currently in the pool
+    #[must_use]
+    pub fn contains(&self, item: &T) -> bool { self.items.contains(item) }
 }
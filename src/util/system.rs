@@ -26,6 +26,11 @@ pub fn num_cores() -> u64 { cpu::num_cores() }
 #[must_use]
 pub fn num_available_cores() -> u64 { cpu::num_available_cores() }
 
+/// Gets the size of a memory page on the system (in bytes), used to convert
+/// page counts (such as the resident set size in `/proc/<pid>/stat`) to bytes
+#[must_use]
+pub fn page_size() -> u64 { memory::page_size() }
+
 /// Attempts to get the width of the given terminal type (in characters),
 /// returning None if no applicable width can be found
 #[must_use]
@@ -79,6 +84,17 @@ mod cpu {
     }
 }
 
+#[cfg(target_os = "linux")]
+mod memory {
+    use super::remap;
+    use libc::{c_long, sysconf, _SC_PAGESIZE};
+
+    pub fn page_size() -> u64 {
+        let size: c_long = unsafe { sysconf(_SC_PAGESIZE) };
+        remap::<_, u64>(size)
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod terminal {
     use std::mem;